@@ -0,0 +1,11 @@
+#version 450
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+void main() {
+    // source and target have the same size, so pixels map one to one
+    f_color = texelFetch(sampler2D(t_source, s_source), ivec2(gl_FragCoord.xy), 0);
+}
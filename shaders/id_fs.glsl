@@ -0,0 +1,13 @@
+#version 450
+
+layout(location=0) in flat int instance_index;
+
+// the instance is offset by one to keep zero for the background
+layout(location=0) out uint id_instance;
+layout(location=1) out uint id_primitive;
+
+void main()
+{
+    id_instance = uint(instance_index) + 1u;
+    id_primitive = uint(gl_PrimitiveID);
+}
@@ -0,0 +1,23 @@
+#version 450
+
+layout (location = 0) in vec3 a_position;
+
+layout(location=0) out flat int instance_index;
+
+layout(set=0, binding=0)
+uniform MvpUniforms {
+    vec4 u_view_position;
+    mat4 u_view_proj;
+    mat4 u_model;
+};
+
+layout(set=0, binding=2)
+buffer Transforms {
+    mat4 s_models[];
+};
+
+void main()
+{
+    instance_index = gl_InstanceIndex;
+    gl_Position = u_view_proj * s_models[gl_InstanceIndex] * vec4(a_position, 1.0);
+}
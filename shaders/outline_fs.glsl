@@ -0,0 +1,15 @@
+#version 450
+
+layout(location=0) out vec4 frag_color;
+
+layout(set=1, binding=0)
+uniform OutlineUniforms {
+    vec4 u_color;
+    vec2 u_viewport;
+    float u_width;
+};
+
+void main()
+{
+    frag_color = u_color;
+}
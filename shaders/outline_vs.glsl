@@ -0,0 +1,40 @@
+#version 450
+
+layout (location = 0) in vec3 a_position;
+layout (location = 1) in vec2 a_tex_coords;
+layout (location = 2) in vec3 a_normal;
+layout (location = 3) in vec4 a_tangent;
+
+layout(set=0, binding=0)
+uniform MvpUniforms {
+    vec4 u_view_position;
+    mat4 u_view_proj;
+    mat4 u_model;
+};
+
+layout(set=0, binding=2)
+buffer Transforms {
+    mat4 s_models[];
+};
+
+layout(set=1, binding=0)
+uniform OutlineUniforms {
+    vec4 u_color;
+    vec2 u_viewport;
+    float u_width;
+};
+
+void main()
+{
+    mat4 s_model = s_models[gl_InstanceIndex];
+    vec4 clip_pos = u_view_proj * s_model * vec4(a_position, 1.0);
+    vec3 normal = mat3(transpose(inverse(s_model))) * a_normal;
+    vec2 clip_normal = (u_view_proj * vec4(normal, 0.0)).xy;
+
+    // push the silhouette out by a constant number of pixels
+    if (dot(clip_normal, clip_normal) > 0.0) {
+        vec2 offset = normalize(clip_normal) * u_width * 2.0 / u_viewport;
+        clip_pos.xy += offset * clip_pos.w;
+    }
+    gl_Position = clip_pos;
+}
@@ -4,8 +4,12 @@ layout(location=1) in vec3 world_pos;
 layout(location=2) in vec3 normal;
 
 layout(location=3) in flat int instance_index;
+layout(location=4) in vec4 tangent;
 
 layout(location=0) out vec4 frag_color;
+// id buffer, the instance is offset by one to keep zero for the background
+layout(location=1) out uint id_instance;
+layout(location=2) out uint id_primitive;
 
 layout(set=0, binding=0)
 uniform MvpUniforms {
@@ -38,20 +42,54 @@ layout(set = 1, binding = 5) uniform sampler s_ao;
 layout(set = 1, binding = 6) uniform texture2D t_normal;
 layout(set = 1, binding = 7) uniform sampler s_normal;
 
+layout(set = 1, binding = 8)
+uniform PbrMaterialUniforms {
+    vec4 base_color_factor;
+    vec4 emissive;  // rgb factor, w strength
+    vec4 factors;   // metallic, roughness, clearcoat, clearcoat roughness
+    uvec4 flags;    // x unlit, y DirectX (green down) normal map, z glTF metallic roughness
+    vec4 uv_transforms[14];
+};
+
+layout(set = 1, binding = 9) uniform texture2D t_emissive;
+layout(set = 1, binding = 10) uniform sampler s_emissive;
+
+layout(set = 1, binding = 11) uniform texture2D t_clearcoat;
+layout(set = 1, binding = 12) uniform sampler s_clearcoat;
+
+layout(set = 1, binding = 13) uniform texture2D t_clearcoat_roughness;
+layout(set = 1, binding = 14) uniform sampler s_clearcoat_roughness;
+
+const int UV_ALBEDO = 0;
+const int UV_ROUGHNESS = 1;
+const int UV_AO = 2;
+const int UV_NORMAL = 3;
+const int UV_EMISSIVE = 4;
+const int UV_CLEARCOAT = 5;
+const int UV_CLEARCOAT_ROUGHNESS = 6;
+
+// below this GGX divides by zero at the highlight
+const float MIN_ROUGHNESS = 0.045;
+
 const float PI = 3.14159265359;
 
-vec3 getNormalFromMap()
+vec2 transformUv(int slot)
 {
-    vec3 tangentNormal = texture(sampler2D(t_normal, s_normal), v_tex_coords).xyz * 2.0 - 1.0;
+    vec3 uv = vec3(v_tex_coords, 1.0);
+    return vec2(dot(uv_transforms[slot * 2].xyz, uv), dot(uv_transforms[slot * 2 + 1].xyz, uv));
+}
 
-    vec3 Q1  = dFdx(world_pos);
-    vec3 Q2  = dFdy(world_pos);
-    vec2 st1 = dFdx(v_tex_coords);
-    vec2 st2 = dFdy(v_tex_coords);
+vec3 getNormalFromMap()
+{
+    vec3 tangentNormal = texture(sampler2D(t_normal, s_normal), transformUv(UV_NORMAL)).xyz * 2.0 - 1.0;
+    if (flags.y != 0) {
+        tangentNormal.y = -tangentNormal.y;
+    }
 
+    // interpolated MikkTSpace frame, re-orthogonalized per pixel
     vec3 N   = normalize(normal);
-    vec3 T   = normalize(Q1*st2.t - Q2*st1.t);
-    vec3 B   = -normalize(cross(N, T));
+    vec3 T   = normalize(tangent.xyz - N * dot(N, tangent.xyz));
+    vec3 B   = cross(N, T) * tangent.w;
     mat3 TBN = mat3(T, B, N);
 
     return normalize(TBN * tangentNormal);
@@ -97,18 +135,42 @@ float GeometrySmith(float NdotV, float NdotL, float roughness)
 
 void main()
 {
+    id_instance = uint(instance_index) + 1u;
+    id_primitive = uint(gl_PrimitiveID);
+
+    // per instance multipliers of the material's metallic, roughness and
+    // ambient occlusion
     vec4 info = s_infos[instance_index];
-    float metallic = 1.0; // info.x;
-    // float roughness = info.y;
-    // float ambient_occlusion = info.z;
-    
-    vec3 albedo = pow(texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords).rgb, vec3(2.2));
-    float roughness = texture(sampler2D(t_roughness, s_roughness), v_tex_coords).r;
-    float ambient_occlusion = texture(sampler2D(t_ao, s_ao), v_tex_coords).r;
+    float metallic = factors.x * info.x;
+
+    vec3 albedo = pow(texture(sampler2D(t_diffuse, s_diffuse), transformUv(UV_ALBEDO)).rgb, vec3(2.2));
+    albedo *= base_color_factor.rgb;
+
+    if (flags.x != 0) {
+        frag_color = vec4(pow(albedo, vec3(1.0/2.2)), 1.0);
+        return;
+    }
+
+    // glTF packs metallic in blue and roughness in green
+    vec4 metallic_roughness = texture(sampler2D(t_roughness, s_roughness), transformUv(UV_ROUGHNESS));
+    float roughness = metallic_roughness.r * factors.y * info.y;
+    if (flags.z != 0) {
+        roughness = metallic_roughness.g * factors.y * info.y;
+        metallic *= metallic_roughness.b;
+    }
+    roughness = max(roughness, MIN_ROUGHNESS);
+    float ambient_occlusion = texture(sampler2D(t_ao, s_ao), transformUv(UV_AO)).r * info.z;
 
     vec3 N = getNormalFromMap(); // normalize(normal);
     vec3 V = normalize(vec3(u_view_position) - world_pos);
 
+    // clearcoat layer sits on top of the normal-mapped base
+    vec3 Nc = normalize(normal);
+    float clearcoat = factors.z * texture(sampler2D(t_clearcoat, s_clearcoat), transformUv(UV_CLEARCOAT)).r;
+    float clearcoat_roughness = factors.w
+        * texture(sampler2D(t_clearcoat_roughness, s_clearcoat_roughness), transformUv(UV_CLEARCOAT_ROUGHNESS)).g;
+    clearcoat_roughness = max(clearcoat_roughness, MIN_ROUGHNESS);
+
     vec3 F0 = vec3(0.04);
     F0 = mix(F0, albedo, metallic);
 
@@ -140,13 +202,27 @@ void main()
         vec3 kD = vec3(1.0) - kS;
         kD *= 1.0 - metallic;
 
+        vec3 base = (kD * albedo / PI + specular) * radiance * NdotL;
+
+        // clearcoat lobe, dielectric with F0 = 0.04
+        if (clearcoat > 0.0) {
+            float NcdotV = max(dot(Nc, V), 0.0);
+            float NcdotL = max(dot(Nc, L), 0.0);
+            vec3 Fc = fresnelSchlick(clamp(dot(H, V), 0.0, 1.0), vec3(0.04));
+            vec3 coat = DistributionGGX(Nc, H, clearcoat_roughness)
+                * GeometrySmith(NcdotV, NcdotL, clearcoat_roughness) * Fc
+                / (4.0 * NcdotV * NcdotL + 0.0001);
+            base = base * (1.0 - clearcoat * Fc) + clearcoat * coat * radiance * NcdotL;
+        }
+
         // add to outgoing radiance
-        Lo += (kD * albedo / PI + specular) * radiance * NdotL;
+        Lo += base;
     }
 
     // ambient, to be replace with IBL
     vec3 ambient = vec3(0.03) * albedo * ambient_occlusion;
-    vec3 color = ambient + Lo;
+    vec3 emitted = pow(texture(sampler2D(t_emissive, s_emissive), transformUv(UV_EMISSIVE)).rgb, vec3(2.2));
+    vec3 color = ambient + Lo + emitted * emissive.rgb * emissive.w;
 
     // HDR tonemap
     color = color / (color + vec3(1.0));
@@ -3,12 +3,14 @@
 layout (location = 0) in vec3 a_position;
 layout (location = 1) in vec2 a_tex_coords;
 layout (location = 2) in vec3 a_normal;
+layout (location = 3) in vec4 a_tangent;
 
 layout(location=0) out vec2 v_tex_coords;
 layout(location=1) out vec3 world_pos;
 layout(location=2) out vec3 normal;
 
 layout(location=3) out flat int instance_index;
+layout(location=4) out vec4 tangent;
 
 layout(set=0, binding=0)
 uniform MvpUniforms {
@@ -29,6 +31,7 @@ void main()
     v_tex_coords = a_tex_coords;
     world_pos = vec3(s_model * vec4(a_position, 1.0));
     normal = mat3(transpose(inverse(s_model))) * a_normal;
+    tangent = vec4(mat3(s_model) * a_tangent.xyz, a_tangent.w);
     gl_Position = u_view_proj * vec4(world_pos, 1.0);
 }
 
@@ -0,0 +1,199 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3, Vector4};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// `None` for an empty set of points.
+    pub fn from_points<I: IntoIterator<Item = [f32; 3]>>(points: I) -> Option<Self> {
+        points.into_iter().fold(None, |aabb, p| {
+            let p = Point3::from(p);
+            Some(match aabb {
+                Some(aabb) => aabb.extend(p),
+                None => Aabb { min: p, max: p },
+            })
+        })
+    }
+
+    pub fn extend(&self, p: Point3<f32>) -> Self {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        self.extend(other.min).extend(other.max)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.0
+    }
+
+    /// The box around the transformed box, after Arvo's "Transforming
+    /// Axis-Aligned Bounding Boxes".
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = Point3::from_homogeneous(matrix * self.center().to_homogeneous());
+        let half = self.half_extents();
+        let extent = |row: usize| {
+            matrix.x[row].abs() * half.x
+                + matrix.y[row].abs() * half.y
+                + matrix.z[row].abs() * half.z
+        };
+        let extent = Vector3::new(extent(0), extent(1), extent(2));
+        Aabb {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Scales the radius by the largest axis scale, so it stays conservative
+    /// under non-uniform scaling.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let scale = matrix
+            .x
+            .truncate()
+            .magnitude()
+            .max(matrix.y.truncate().magnitude())
+            .max(matrix.z.truncate().magnitude());
+        BoundingSphere {
+            center: Point3::from_homogeneous(matrix * self.center.to_homogeneous()),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Box and sphere around the same points, the sphere is centered on the box
+/// but only as large as the points need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    /// An empty set of points gets a zero sized box at the origin.
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        let aabb = Aabb::from_points(points.iter().copied()).unwrap_or(Aabb {
+            min: Point3::origin(),
+            max: Point3::origin(),
+        });
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|&p| (Point3::from(p) - center).magnitude2())
+            .fold(0.0, f32::max)
+            .sqrt();
+        Bounds {
+            aabb,
+            sphere: BoundingSphere { center, radius },
+        }
+    }
+
+    pub fn from_aabb(aabb: Aabb) -> Self {
+        Bounds {
+            aabb,
+            sphere: BoundingSphere {
+                center: aabb.center(),
+                radius: aabb.half_extents().magnitude(),
+            },
+        }
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Bounds {
+            aabb: self.aabb.transform(matrix),
+            sphere: self.sphere.transform(matrix),
+        }
+    }
+}
+
+/// Planes of a view frustum pointing inwards, as `(normal, distance)` with
+/// unit normals.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Gribb and Hartmann's plane extraction, for wgpu's clip space where
+    /// depth goes from 0 to 1.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let normalize = |plane: Vector4<f32>| plane / plane.truncate().magnitude();
+        Frustum {
+            planes: [
+                normalize(w + x),
+                normalize(w - x),
+                normalize(w + y),
+                normalize(w - y),
+                normalize(z),
+                normalize(w - z),
+            ],
+        }
+    }
+
+    fn distance(plane: &Vector4<f32>, p: Point3<f32>) -> f32 {
+        plane.truncate().dot(p.to_vec()) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Frustum::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let corner = Point3::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            Frustum::distance(plane, corner) >= 0.0
+        })
+    }
+
+    /// Sphere first since it's cheaper, the box rejects what the sphere lets
+    /// through along the diagonals.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
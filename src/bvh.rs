@@ -0,0 +1,442 @@
+use crate::bounds::Aabb;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3};
+
+/// Triangles per leaf before a node is split.
+const MAX_LEAF_TRIANGLES: usize = 4;
+/// Buckets along the split axis for the surface area heuristic.
+const SAH_BINS: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The direction is left unnormalized, so a hit at `t` on the transformed
+    /// ray is at `t` on this one as well.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Ray {
+            origin: Point3::from_homogeneous(matrix * self.origin.to_homogeneous()),
+            direction: (matrix * self.direction.extend(0.0)).truncate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TriangleHit {
+    /// Index into the mesh's triangle list, i.e. `indices[3 * triangle..]`.
+    pub triangle: u32,
+    /// Weights of the triangle's three vertices at the hit.
+    pub barycentrics: [f32; 3],
+    /// Ray parameter of the hit, a distance if the direction is unit length.
+    pub distance: f32,
+}
+
+/// Interior nodes have `count == 0` and keep their right child at `start`,
+/// the left child follows the node directly.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    aabb: Aabb,
+    start: u32,
+    count: u32,
+}
+
+/// Bounding volume hierarchy over the triangles of one mesh.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // triangle corners in leaf order, with their index in the mesh
+    triangles: Vec<([Point3<f32>; 3], u32)>,
+}
+
+impl Bvh {
+    pub fn new(positions: &[[f32; 3]], indices: &[u16]) -> Self {
+        let triangles: Vec<_> = indices
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, t)| {
+                let corner = |k: usize| Point3::from(positions[t[k] as usize]);
+                ([corner(0), corner(1), corner(2)], i as u32)
+            })
+            .collect();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * triangles.len() / MAX_LEAF_TRIANGLES + 1),
+            triangles,
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.build(0, bvh.triangles.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let triangles = &mut self.triangles[start..end];
+        let aabb = triangles
+            .iter()
+            .map(|(t, _)| triangle_aabb(t))
+            .fold(None, |a: Option<Aabb>, b| {
+                Some(a.map_or(b, |a| a.union(&b)))
+            })
+            .expect("nodes are never empty");
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            aabb,
+            start: start as u32,
+            count: (end - start) as u32,
+        });
+        if triangles.len() <= MAX_LEAF_TRIANGLES {
+            return node;
+        }
+
+        let mid = match split(triangles) {
+            Some(mid) => start + mid,
+            None => return node,
+        };
+        self.build(start, mid);
+        let right = self.build(mid, end);
+        self.nodes[node].start = right as u32;
+        self.nodes[node].count = 0;
+        node
+    }
+
+    /// Closest triangle along the ray, both faces count.
+    pub fn intersect(&self, ray: &Ray) -> Option<TriangleHit> {
+        let inverse_direction = Vector3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let mut closest: Option<TriangleHit> = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let max_distance = closest.map_or(f32::INFINITY, |hit| hit.distance);
+            if slab_distance(&node.aabb, ray, inverse_direction, max_distance).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                let leaf = node.start as usize..(node.start + node.count) as usize;
+                for (corners, triangle) in &self.triangles[leaf] {
+                    if let Some((distance, u, v)) = intersect_triangle(ray, corners) {
+                        if distance < closest.map_or(f32::INFINITY, |hit| hit.distance) {
+                            closest = Some(TriangleHit {
+                                triangle: *triangle,
+                                barycentrics: [1.0 - u - v, u, v],
+                                distance,
+                            });
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // visit the nearer child first, it goes on top of the stack
+            let (left, right) = (index + 1, node.start as usize);
+            let entry = |child: usize| {
+                slab_distance(
+                    &self.nodes[child].aabb,
+                    ray,
+                    inverse_direction,
+                    max_distance,
+                )
+            };
+            match (entry(left), entry(right)) {
+                (Some(l), Some(r)) if l <= r => stack.extend_from_slice(&[right, left]),
+                (Some(_), Some(_)) => stack.extend_from_slice(&[left, right]),
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+        closest
+    }
+}
+
+/// Partitions `triangles` by the cheapest binned SAH split of the centroids
+/// along their longest axis. `None` if the centroids can't be separated or
+/// a small node is cheaper as a leaf.
+fn split(triangles: &mut [([Point3<f32>; 3], u32)]) -> Option<usize> {
+    let centroid = |t: &[Point3<f32>; 3]| Point3::centroid(t);
+    let centroids = triangles
+        .iter()
+        .map(|(t, _)| centroid(t))
+        .fold(None, |aabb: Option<Aabb>, p| {
+            Some(aabb.map_or(Aabb { min: p, max: p }, |aabb| aabb.extend(p)))
+        })?;
+    let extent = centroids.max - centroids.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    if extent[axis] <= 0.0 {
+        return None;
+    }
+    let bin = |t: &[Point3<f32>; 3]| {
+        let offset = (centroid(t)[axis] - centroids.min[axis]) / extent[axis];
+        ((offset * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+    };
+
+    let mut bins: [(Option<Aabb>, usize); SAH_BINS] = [(None, 0); SAH_BINS];
+    for (t, _) in triangles.iter() {
+        let (aabb, count) = &mut bins[bin(t)];
+        let bounds = triangle_aabb(t);
+        *aabb = Some(aabb.map_or(bounds, |aabb| aabb.union(&bounds)));
+        *count += 1;
+    }
+
+    // cost of splitting after each bin, sweeping from both sides
+    let area = |aabb: Option<Aabb>| {
+        aabb.map_or(0.0, |aabb| {
+            let d = aabb.max - aabb.min;
+            d.x * d.y + d.y * d.z + d.z * d.x
+        })
+    };
+    let merge = |a: Option<Aabb>, b: Option<Aabb>| match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(&b)),
+        (a, b) => a.or(b),
+    };
+    let mut right_costs = [0.0; SAH_BINS];
+    let (mut aabb, mut count) = (None, 0);
+    for i in (1..SAH_BINS).rev() {
+        aabb = merge(aabb, bins[i].0);
+        count += bins[i].1;
+        right_costs[i] = area(aabb) * count as f32;
+    }
+    let (mut aabb, mut count) = (None, 0);
+    let mut best: Option<(f32, usize)> = None;
+    for i in 0..SAH_BINS - 1 {
+        aabb = merge(aabb, bins[i].0);
+        count += bins[i].1;
+        let cost = area(aabb) * count as f32 + right_costs[i + 1];
+        if cost < best.map_or(f32::INFINITY, |(best_cost, _)| best_cost) {
+            best = Some((cost, i));
+        }
+    }
+    let (cost, split_bin) = best?;
+    let parent = area(bins.iter().fold(None, |aabb, &(bin, _)| merge(aabb, bin)));
+    if triangles.len() <= MAX_LEAF_TRIANGLES * 4 && cost >= parent * triangles.len() as f32 {
+        return None;
+    }
+
+    // partition in place around the chosen bin
+    let mut mid = 0;
+    for i in 0..triangles.len() {
+        if bin(&triangles[i].0) <= split_bin {
+            triangles.swap(i, mid);
+            mid += 1;
+        }
+    }
+    if mid == 0 || mid == triangles.len() {
+        None
+    } else {
+        Some(mid)
+    }
+}
+
+fn triangle_aabb([a, b, c]: &[Point3<f32>; 3]) -> Aabb {
+    Aabb { min: *a, max: *a }.extend(*b).extend(*c)
+}
+
+/// Entry distance of the ray into the box, if it enters before
+/// `max_distance`.
+fn slab_distance(
+    aabb: &Aabb,
+    ray: &Ray,
+    inverse_direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<f32> {
+    let mut near = 0.0f32;
+    let mut far = max_distance;
+    for axis in 0..3 {
+        let origin = ray.origin[axis];
+        if ray.direction[axis] == 0.0 {
+            // parallel to the slab, 0 * inf would give NaN on its boundary
+            if !(aabb.min[axis]..=aabb.max[axis]).contains(&origin) {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (aabb.min[axis] - origin) * inverse_direction[axis];
+        let t1 = (aabb.max[axis] - origin) * inverse_direction[axis];
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    if near <= far {
+        Some(near)
+    } else {
+        None
+    }
+}
+
+/// Möller-Trumbore, returns the ray parameter and the barycentrics of the
+/// second and third corner. Edges get a little slack so rays through shared
+/// edges and vertices can't slip between the triangles.
+fn intersect_triangle(ray: &Ray, [a, b, c]: &[Point3<f32>; 3]) -> Option<(f32, f32, f32)> {
+    const EPSILON: f32 = 1e-9;
+    const EDGE_EPSILON: f32 = 1e-6;
+
+    let (ab, ac) = (b - a, c - a);
+    let p = ray.direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let to_origin = ray.origin - a;
+    let u = to_origin.dot(p) * inverse;
+    if !(-EDGE_EPSILON..=1.0 + EDGE_EPSILON).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(ab);
+    let v = ray.direction.dot(q) * inverse;
+    if v < -EDGE_EPSILON || u + v > 1.0 + EDGE_EPSILON {
+        return None;
+    }
+    let t = ac.dot(q) * inverse;
+    if t > 0.0 {
+        Some((t, u, v))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{SquareMatrix, Vector3};
+
+    /// Unit square in the xy plane at `z`, split along its diagonal.
+    fn quad(z: f32) -> (Vec<[f32; 3]>, Vec<u16>) {
+        let positions = vec![[0.0, 0.0, z], [1.0, 0.0, z], [1.0, 1.0, z], [0.0, 1.0, z]];
+        (positions, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray {
+            origin: Point3::from(origin),
+            direction: Vector3::from(direction),
+        }
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let (positions, indices) = quad(0.0);
+        let bvh = Bvh::new(&positions, &indices);
+
+        let hit = bvh
+            .intersect(&ray([0.75, 0.25, 2.0], [0.0, 0.0, -1.0]))
+            .expect("ray through the first triangle");
+        assert_eq!(hit.triangle, 0);
+        assert!((hit.distance - 2.0).abs() < 1e-6);
+        let point = hit
+            .barycentrics
+            .iter()
+            .zip(&indices[0..3])
+            .fold(Vector3::new(0.0, 0.0, 0.0), |p, (w, &i)| {
+                p + Vector3::from(positions[i as usize]) * *w
+            });
+        assert!((point - Vector3::new(0.75, 0.25, 0.0)).magnitude() < 1e-6);
+
+        assert!(bvh
+            .intersect(&ray([1.5, 0.5, 2.0], [0.0, 0.0, -1.0]))
+            .is_none());
+        // pointing away from the quad
+        assert!(bvh
+            .intersect(&ray([0.5, 0.5, 2.0], [0.0, 0.0, 1.0]))
+            .is_none());
+        assert!(Bvh::new(&[], &[])
+            .intersect(&ray([0.0; 3], [0.0, 0.0, 1.0]))
+            .is_none());
+    }
+
+    #[test]
+    fn closest_hit() {
+        // enough stacked quads to split into several leaves
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for layer in 0..16 {
+            let (p, i) = quad(layer as f32);
+            let base = positions.len() as u16;
+            positions.extend(p);
+            indices.extend(i.into_iter().map(|i| base + i));
+        }
+        let bvh = Bvh::new(&positions, &indices);
+        assert!(bvh.nodes.len() > 1);
+
+        let hit = bvh
+            .intersect(&ray([0.25, 0.75, 20.0], [0.0, 0.0, -1.0]))
+            .unwrap();
+        assert_eq!(hit.triangle, 31);
+        assert!((hit.distance - 5.0).abs() < 1e-6);
+
+        let hit = bvh
+            .intersect(&ray([0.25, 0.75, -3.0], [0.0, 0.0, 1.0]))
+            .unwrap();
+        assert_eq!(hit.triangle, 1);
+        assert!((hit.distance - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shared_edges_and_vertices() {
+        let (positions, indices) = quad(0.0);
+        let bvh = Bvh::new(&positions, &indices);
+        // the diagonal both triangles share, and the corners on it
+        for origin in &[[0.5, 0.5, 1.0], [0.0, 0.0, 1.0], [1.0, 1.0, 1.0]] {
+            assert!(
+                bvh.intersect(&ray(*origin, [0.0, 0.0, -1.0])).is_some(),
+                "ray at {:?} slipped through",
+                origin
+            );
+        }
+    }
+
+    #[test]
+    fn ray_parallel_to_slab() {
+        let (positions, indices) = quad(0.0);
+        let bvh = Bvh::new(&positions, &indices);
+        // the box is flat in z, so the ray runs inside the z slab, and along
+        // the boundary of the x slab
+        let hit = bvh.intersect(&ray([0.0, 0.5, 1.0], [0.0, 0.0, -1.0]));
+        assert!(hit.is_some());
+        // in the plane of the quad, parallel to it
+        assert!(bvh
+            .intersect(&ray([-1.0, 0.5, 0.0], [1.0, 0.0, 0.0]))
+            .is_none());
+        // parallel to the x and y slabs, outside the x slab
+        assert!(bvh
+            .intersect(&ray([2.0, 0.5, 1.0], [0.0, 0.0, -1.0]))
+            .is_none());
+    }
+
+    #[test]
+    fn scaled_instance() {
+        // what `PbrState::pick` does: the world ray goes into the instance's
+        // space, and the hit distance stays comparable across instances
+        let (positions, indices) = quad(0.0);
+        let bvh = Bvh::new(&positions, &indices);
+        let model = Matrix4::from_translation(Vector3::new(0.0, 0.0, -4.0))
+            * Matrix4::from_nonuniform_scale(3.0, 3.0, 0.5);
+        let world_ray = ray([2.5, 0.5, 6.0], [0.0, 0.0, -1.0]);
+
+        let local_ray = world_ray.transform(&model.invert().unwrap());
+        let hit = bvh
+            .intersect(&local_ray)
+            .expect("hit inside the scaled quad");
+        assert!((hit.distance - 10.0).abs() < 1e-5);
+        let world_hit = world_ray.at(hit.distance);
+        assert!((world_hit - Point3::new(2.5, 0.5, -4.0)).magnitude() < 1e-5);
+
+        // outside the unscaled quad, inside the scaled one
+        assert!(bvh.intersect(&world_ray).is_none());
+    }
+}
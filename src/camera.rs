@@ -1,6 +1,7 @@
-use crate::render_types::OPENGL_TO_WGPU_MATRIX;
+use crate::{bounds::Frustum, bvh::Ray, render_types::OPENGL_TO_WGPU_MATRIX};
 use winit::event::*;
 
+#[derive(Clone)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     target: cgmath::Point3<f32>,
@@ -18,6 +19,51 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
 
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.build_view_projection_matrix())
+    }
+
+    /// Fraction of the viewport height covered by a length of `size` facing
+    /// the camera at `center`.
+    pub fn screen_size(&self, size: f32, center: cgmath::Point3<f32>) -> f32 {
+        use cgmath::InnerSpace;
+        let distance = (center - self.eye).magnitude().max(self.znear);
+        size / (2.0 * distance * (self.fovy.to_radians() / 2.0).tan())
+    }
+
+    /// World space ray from the near plane through the pixel at `cursor`,
+    /// with a unit length direction.
+    pub fn ray(
+        &self,
+        cursor: winit::dpi::PhysicalPosition<f64>,
+        viewport: winit::dpi::PhysicalSize<u32>,
+    ) -> Ray {
+        use cgmath::{InnerSpace, SquareMatrix};
+
+        let x = (2.0 * cursor.x / viewport.width as f64 - 1.0) as f32;
+        let y = (1.0 - 2.0 * cursor.y / viewport.height as f64) as f32;
+        let inverse = self
+            .build_view_projection_matrix()
+            .invert()
+            .expect("view projection is invertible");
+        let unproject =
+            |z| cgmath::Point3::from_homogeneous(inverse * cgmath::Vector4::new(x, y, z, 1.0));
+        let near = unproject(0.0);
+        Ray {
+            origin: near,
+            direction: (unproject(1.0) - near).normalize(),
+        }
+    }
+
+    /// Between `self` at `t = 0` and `other` at `t = 1`.
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        Camera {
+            eye: self.eye + (other.eye - self.eye) * t,
+            target: self.target + (other.target - self.target) * t,
+            ..other.clone()
+        }
+    }
+
     pub fn new(sc_width: u32, sc_height: u32) -> Self {
         Camera {
             eye: (0.0, 0.0, 5.0).into(),
@@ -33,6 +79,7 @@ impl Camera {
 
 #[derive(Default)]
 pub struct CameraController {
+    /// World units per second.
     speed: f32,
     is_up_pressed: bool,
     is_down_pressed: bool,
@@ -94,23 +141,24 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera) {
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
         use cgmath::InnerSpace;
 
+        let distance = self.speed * dt;
         let forward = (camera.target - camera.eye).normalize();
         let right = forward.cross(camera.up);
 
         if self.is_forward_pressed {
-            camera.eye += forward * self.speed;
+            camera.eye += forward * distance;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward * self.speed;
+            camera.eye -= forward * distance;
         }
         if self.is_right_pressed {
-            camera.eye += right * self.speed;
+            camera.eye += right * distance;
         }
         if self.is_left_pressed {
-            camera.eye -= right * self.speed;
+            camera.eye -= right * distance;
         }
     }
 }
@@ -0,0 +1,90 @@
+use crate::render::ROW_ALIGNMENT;
+use anyhow::{anyhow, bail, Context, Result};
+
+/// A copy of a colour texture into a mappable buffer, recorded but not read
+/// yet.
+pub struct TextureReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_row_bytes: u32,
+    bgra: bool,
+}
+
+impl TextureReadback {
+    /// Records the copy of a whole 8-bit RGBA or BGRA texture.
+    pub fn record(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+    ) -> Result<Self> {
+        let bgra = match format {
+            wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8UnormSrgb | wgpu::TextureFormat::Bgra8Unorm => true,
+            format => bail!("Can't read back {:?} textures", format),
+        };
+        let row_bytes = 4 * width;
+        let alignment = ROW_ALIGNMENT as u32;
+        let padding = (alignment - row_bytes % alignment) % alignment;
+        let padded_row_bytes = row_bytes + padding;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_buffer"),
+            size: (padded_row_bytes * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                bytes_per_row: padded_row_bytes,
+                rows_per_image: height,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+        Ok(TextureReadback {
+            buffer,
+            width,
+            height,
+            padded_row_bytes,
+            bgra,
+        })
+    }
+
+    /// Blocks until the copy is done, once the commands recording it are
+    /// submitted.
+    pub fn finish(self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        let size = (self.padded_row_bytes * self.height) as wgpu::BufferAddress;
+        let mapping = self.buffer.map_read(0, size);
+        device.poll(wgpu::Maintain::Wait);
+        let mapping = futures::executor::block_on(mapping)
+            .map_err(|_| anyhow!("Failed to map the readback buffer"))?;
+
+        let row_bytes = 4 * self.width as usize;
+        let mut pixels: Vec<u8> = mapping
+            .as_slice()
+            .chunks(self.padded_row_bytes as usize)
+            .flat_map(|row| &row[..row_bytes])
+            .copied()
+            .collect();
+        if self.bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .context("Readback doesn't match the image size")
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::{Duration, Instant};
+
+/// Longest frame the simulation catches up on, so a stall doesn't run
+/// hundreds of steps afterwards.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// What a frame has to do, see `Clock::tick`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tick {
+    /// Real time since the previous frame.
+    pub delta: Duration,
+    /// Fixed steps to simulate this frame.
+    pub steps: u32,
+    /// How far the frame is between the last two steps, for interpolating
+    /// what's drawn.
+    pub alpha: f32,
+}
+
+/// Splits real time into fixed simulation steps, so the simulation doesn't
+/// depend on the frame rate.
+pub struct Clock {
+    /// Simulated time per step.
+    pub step: Duration,
+    last: Option<Instant>,
+    accumulator: Duration,
+    paused: bool,
+    queued_steps: u32,
+}
+
+impl Clock {
+    pub fn new(step: Duration) -> Self {
+        Clock {
+            step,
+            last: None,
+            accumulator: Duration::default(),
+            paused: false,
+            queued_steps: 0,
+        }
+    }
+
+    pub fn step_seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.accumulator = Duration::default();
+    }
+
+    /// Runs one step on the next tick, meant for while paused.
+    pub fn single_step(&mut self) {
+        self.queued_steps += 1;
+    }
+
+    /// Measures the real time since the last tick and advances by it.
+    pub fn tick(&mut self) -> Tick {
+        let now = Instant::now();
+        let delta = self.last.map_or(Duration::default(), |last| now - last);
+        self.last = Some(now);
+        self.advance(delta)
+    }
+
+    /// Like `tick`, but advances by `delta` whatever the real time, to
+    /// record frames at a fixed rate.
+    pub fn tick_by(&mut self, delta: Duration) -> Tick {
+        self.last = Some(Instant::now());
+        self.advance(delta)
+    }
+
+    /// Advances by `delta` regardless of the real time, e.g. `step` to run
+    /// exactly one step.
+    pub fn advance(&mut self, delta: Duration) -> Tick {
+        let queued = std::mem::take(&mut self.queued_steps);
+        if self.paused {
+            // show the last step as it is
+            return Tick {
+                delta,
+                steps: queued,
+                alpha: 1.0,
+            };
+        }
+        self.accumulator += delta.min(MAX_FRAME_TIME);
+        let mut steps = queued;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        Tick {
+            delta,
+            steps,
+            alpha: self.accumulator.as_secs_f32() / self.step.as_secs_f32(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(10);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn assert_alpha(tick: Tick, alpha: f32) {
+        assert!((tick.alpha - alpha).abs() < 1e-4, "{:?}", tick);
+    }
+
+    #[test]
+    fn accumulates_fixed_steps() {
+        let mut clock = Clock::new(STEP);
+        let tick = clock.tick_by(ms(25));
+        assert_eq!(tick.steps, 2);
+        assert_alpha(tick, 0.5);
+        // the leftover 5ms carries over into the next frame
+        let tick = clock.tick_by(ms(7));
+        assert_eq!(tick.steps, 1);
+        assert_alpha(tick, 0.2);
+        let tick = clock.tick_by(ms(3));
+        assert_eq!(tick.steps, 0);
+        assert_alpha(tick, 0.5);
+    }
+
+    #[test]
+    fn same_deltas_give_same_steps() {
+        let deltas = [ms(16), ms(17), ms(33), ms(1), ms(16)];
+        let run = || {
+            let mut clock = Clock::new(STEP);
+            deltas
+                .iter()
+                .map(|&delta| clock.tick_by(delta).steps)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(), vec![1, 2, 3, 0, 2]);
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn pause_stops_steps_and_drops_the_remainder() {
+        let mut clock = Clock::new(STEP);
+        clock.tick_by(ms(15));
+        clock.toggle_pause();
+        assert!(clock.is_paused());
+        let tick = clock.tick_by(ms(100));
+        assert_eq!(tick.steps, 0);
+        assert_alpha(tick, 1.0);
+
+        clock.toggle_pause();
+        // the 5ms left before pausing is gone
+        let tick = clock.tick_by(ms(5));
+        assert_eq!(tick.steps, 0);
+        assert_alpha(tick, 0.5);
+    }
+
+    #[test]
+    fn single_step_runs_one_step() {
+        let mut clock = Clock::new(STEP);
+        clock.toggle_pause();
+        clock.single_step();
+        assert_eq!(clock.tick_by(ms(100)).steps, 1);
+        assert_eq!(clock.tick_by(ms(100)).steps, 0);
+
+        // while running, queued steps add to the measured ones
+        clock.toggle_pause();
+        clock.single_step();
+        assert_eq!(clock.tick_by(ms(10)).steps, 2);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut clock = Clock::new(STEP);
+        let tick = clock.tick_by(Duration::from_secs(5));
+        assert_eq!(tick.steps, 25);
+        assert_eq!(tick.delta, Duration::from_secs(5));
+        assert_alpha(tick, 0.0);
+    }
+}
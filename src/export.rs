@@ -0,0 +1,706 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::{collections::BTreeSet, path::Path};
+
+use crate::model::{
+    ChannelOutputs, ImageSource, Material, MeshInstances, Model, Primitive, TextureRef,
+    TextureTransform,
+};
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+/// Builds an object from the entries that are present, so optional glTF
+/// properties are left out instead of written as `null`.
+fn object(entries: Vec<(&str, Option<Value>)>) -> Value {
+    Value::Object(
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key.to_string(), value)))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+/// The single binary buffer of the exported document, with its views and
+/// accessors.
+#[derive(Default)]
+struct BufferBuilder {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl BufferBuilder {
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // accessors need their offset aligned to the component size
+        while self.data.len() % 4 != 0 {
+            self.data.push(0);
+        }
+        let offset = self.data.len();
+        self.data.extend_from_slice(bytes);
+        self.views.push(object(vec![
+            ("buffer", Some(json!(0))),
+            ("byteOffset", Some(json!(offset))),
+            ("byteLength", Some(json!(bytes.len()))),
+            ("target", target.map(Value::from)),
+        ]));
+        self.views.len() - 1
+    }
+
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        component_type: u32,
+        count: usize,
+        ty: &str,
+        bounds: Option<(Vec<f32>, Vec<f32>)>,
+        target: Option<u32>,
+    ) -> usize {
+        let view = self.push_view(bytes, target);
+        let (min, max) = match bounds {
+            Some((min, max)) => (Some(json!(min)), Some(json!(max))),
+            None => (None, None),
+        };
+        self.accessors.push(object(vec![
+            ("bufferView", Some(json!(view))),
+            ("componentType", Some(json!(component_type))),
+            ("count", Some(json!(count))),
+            ("type", Some(json!(ty))),
+            ("min", min),
+            ("max", max),
+        ]));
+        self.accessors.len() - 1
+    }
+
+    /// `components` floats per element, `with_bounds` for the accessors the
+    /// spec requires min and max on (positions and animation inputs).
+    fn push_floats(
+        &mut self,
+        values: &[f32],
+        components: usize,
+        ty: &str,
+        with_bounds: bool,
+        target: Option<u32>,
+    ) -> usize {
+        let bounds = if with_bounds {
+            let mut min = vec![std::f32::MAX; components];
+            let mut max = vec![std::f32::MIN; components];
+            for element in values.chunks_exact(components) {
+                for (i, &value) in element.iter().enumerate() {
+                    min[i] = min[i].min(value);
+                    max[i] = max[i].max(value);
+                }
+            }
+            Some((min, max))
+        } else {
+            None
+        };
+        self.push_accessor(
+            bytemuck::cast_slice(values),
+            FLOAT,
+            values.len() / components,
+            ty,
+            bounds,
+            target,
+        )
+    }
+}
+
+fn flatten<A: AsRef<[f32]>>(values: &[A]) -> Vec<f32> {
+    values
+        .iter()
+        .flat_map(|value| value.as_ref().iter().copied())
+        .collect()
+}
+
+fn export_primitive(primitive: &Primitive, builder: &mut BufferBuilder) -> Value {
+    let mut attributes = Map::new();
+    let position = builder.push_floats(
+        &flatten(&primitive.positions),
+        3,
+        "VEC3",
+        true,
+        Some(ARRAY_BUFFER),
+    );
+    attributes.insert("POSITION".into(), json!(position));
+    if let Some(normals) = &primitive.normals {
+        let normal = builder.push_floats(&flatten(normals), 3, "VEC3", false, Some(ARRAY_BUFFER));
+        attributes.insert("NORMAL".into(), json!(normal));
+    }
+    if let Some(tex_coords) = &primitive.tex_coords {
+        let tex_coord =
+            builder.push_floats(&flatten(tex_coords), 2, "VEC2", false, Some(ARRAY_BUFFER));
+        attributes.insert("TEXCOORD_0".into(), json!(tex_coord));
+    }
+    if let Some(tangents) = &primitive.tangents {
+        let tangent = builder.push_floats(&flatten(tangents), 4, "VEC4", false, Some(ARRAY_BUFFER));
+        attributes.insert("TANGENT".into(), json!(tangent));
+    }
+    if let Some(joints) = &primitive.joints {
+        let flat: Vec<u16> = joints
+            .iter()
+            .flat_map(|joint| joint.iter().copied())
+            .collect();
+        let joint = builder.push_accessor(
+            bytemuck::cast_slice(&flat),
+            UNSIGNED_SHORT,
+            joints.len(),
+            "VEC4",
+            None,
+            Some(ARRAY_BUFFER),
+        );
+        attributes.insert("JOINTS_0".into(), json!(joint));
+    }
+    if let Some(weights) = &primitive.weights {
+        let weight = builder.push_floats(&flatten(weights), 4, "VEC4", false, Some(ARRAY_BUFFER));
+        attributes.insert("WEIGHTS_0".into(), json!(weight));
+    }
+
+    let indices = primitive.indices.as_ref().map(|indices| {
+        json!(builder.push_accessor(
+            bytemuck::cast_slice(indices),
+            UNSIGNED_INT,
+            indices.len(),
+            "SCALAR",
+            None,
+            Some(ELEMENT_ARRAY_BUFFER),
+        ))
+    });
+    object(vec![
+        ("attributes", Some(Value::Object(attributes))),
+        ("indices", indices),
+        ("material", primitive.material.map(Value::from)),
+    ])
+}
+
+fn export_texture_ref(
+    texture: &Option<TextureRef>,
+    extensions: &mut BTreeSet<&'static str>,
+) -> Option<Value> {
+    let texture = texture.as_ref()?;
+    let transform = if texture.transform != TextureTransform::default() {
+        extensions.insert("KHR_texture_transform");
+        let TextureTransform {
+            offset,
+            rotation,
+            scale,
+        } = texture.transform;
+        Some(json!({
+            "KHR_texture_transform": {
+                "offset": offset,
+                "rotation": rotation,
+                "scale": scale,
+            }
+        }))
+    } else {
+        None
+    };
+    Some(object(vec![
+        ("index", Some(json!(texture.texture))),
+        ("texCoord", Some(json!(texture.tex_coord))),
+        ("extensions", transform),
+    ]))
+}
+
+fn export_material(material: &Material, extensions: &mut BTreeSet<&'static str>) -> Value {
+    let mut material_extensions = Map::new();
+    if let Some(clearcoat) = &material.clearcoat {
+        extensions.insert("KHR_materials_clearcoat");
+        material_extensions.insert(
+            "KHR_materials_clearcoat".into(),
+            object(vec![
+                ("clearcoatFactor", Some(json!(clearcoat.factor))),
+                (
+                    "clearcoatTexture",
+                    export_texture_ref(&clearcoat.texture, extensions),
+                ),
+                (
+                    "clearcoatRoughnessFactor",
+                    Some(json!(clearcoat.roughness_factor)),
+                ),
+                (
+                    "clearcoatRoughnessTexture",
+                    export_texture_ref(&clearcoat.roughness_texture, extensions),
+                ),
+                (
+                    "clearcoatNormalTexture",
+                    export_texture_ref(&clearcoat.normal_texture, extensions),
+                ),
+            ]),
+        );
+    }
+    if (material.emissive_strength - 1.0).abs() > std::f32::EPSILON {
+        extensions.insert("KHR_materials_emissive_strength");
+        material_extensions.insert(
+            "KHR_materials_emissive_strength".into(),
+            json!({ "emissiveStrength": material.emissive_strength }),
+        );
+    }
+    if material.unlit {
+        extensions.insert("KHR_materials_unlit");
+        material_extensions.insert("KHR_materials_unlit".into(), json!({}));
+    }
+
+    let pbr = object(vec![
+        ("baseColorFactor", Some(json!(material.base_color_factor))),
+        (
+            "baseColorTexture",
+            export_texture_ref(&material.base_color_texture, extensions),
+        ),
+        ("metallicFactor", Some(json!(material.metallic_factor))),
+        ("roughnessFactor", Some(json!(material.roughness_factor))),
+        (
+            "metallicRoughnessTexture",
+            export_texture_ref(&material.metallic_roughness_texture, extensions),
+        ),
+    ]);
+    object(vec![
+        ("name", material.name.as_ref().map(|name| json!(name))),
+        ("pbrMetallicRoughness", Some(pbr)),
+        (
+            "normalTexture",
+            export_texture_ref(&material.normal_texture, extensions),
+        ),
+        (
+            "occlusionTexture",
+            export_texture_ref(&material.occlusion_texture, extensions),
+        ),
+        ("emissiveFactor", Some(json!(material.emissive_factor))),
+        (
+            "emissiveTexture",
+            export_texture_ref(&material.emissive_texture, extensions),
+        ),
+        (
+            "extensions",
+            Some(Value::Object(material_extensions)).filter(|ext| ext != &json!({})),
+        ),
+    ])
+}
+
+/// Writes the instance attributes as they were read, relative to the node.
+fn export_mesh_instances(instances: &MeshInstances, builder: &mut BufferBuilder) -> Value {
+    let mut attributes = Map::new();
+    if let Some(translations) = &instances.translations {
+        let accessor = builder.push_floats(&flatten(translations), 3, "VEC3", false, None);
+        attributes.insert("TRANSLATION".into(), json!(accessor));
+    }
+    if let Some(rotations) = &instances.rotations {
+        let accessor = builder.push_floats(&flatten(rotations), 4, "VEC4", false, None);
+        attributes.insert("ROTATION".into(), json!(accessor));
+    }
+    if let Some(scales) = &instances.scales {
+        let accessor = builder.push_floats(&flatten(scales), 3, "VEC3", false, None);
+        attributes.insert("SCALE".into(), json!(accessor));
+    }
+    json!({ "EXT_mesh_gpu_instancing": { "attributes": attributes } })
+}
+
+/// Builds the glTF document and the contents of its single buffer. The
+/// buffer `uri` is left to the caller.
+fn build_document(model: &Model) -> Result<(Value, Vec<u8>)> {
+    let mut builder = BufferBuilder::default();
+    let mut extensions = BTreeSet::new();
+
+    let nodes: Vec<Value> = model
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let instancing = model
+                .instances
+                .iter()
+                .find(|instances| instances.node == index)
+                .map(|instances| {
+                    extensions.insert("EXT_mesh_gpu_instancing");
+                    export_mesh_instances(instances, &mut builder)
+                });
+            object(vec![
+                ("name", node.name.as_ref().map(|name| json!(name))),
+                ("mesh", node.mesh.map(Value::from)),
+                ("skin", node.skin.map(Value::from)),
+                (
+                    "children",
+                    Some(json!(node.children)).filter(|_| !node.children.is_empty()),
+                ),
+                ("translation", Some(json!(node.translation))),
+                ("rotation", Some(json!(node.rotation))),
+                ("scale", Some(json!(node.scale))),
+                ("extensions", instancing),
+            ])
+        })
+        .collect();
+
+    let meshes: Vec<Value> = model
+        .meshes
+        .iter()
+        .map(|mesh| {
+            let primitives: Vec<Value> = mesh
+                .primitives
+                .iter()
+                .map(|primitive| export_primitive(primitive, &mut builder))
+                .collect();
+            object(vec![
+                ("name", mesh.name.as_ref().map(|name| json!(name))),
+                ("primitives", Some(json!(primitives))),
+            ])
+        })
+        .collect();
+
+    let skins: Vec<Value> = model
+        .skins
+        .iter()
+        .map(|skin| {
+            let matrices: Vec<f32> = skin
+                .inverse_bind_matrices
+                .iter()
+                .flat_map(|m| m.iter().flat_map(|column| column.iter().copied()))
+                .collect();
+            let inverse_bind_matrices = builder.push_floats(&matrices, 16, "MAT4", false, None);
+            object(vec![
+                ("joints", Some(json!(skin.joints))),
+                ("skeleton", skin.skeleton.map(Value::from)),
+                ("inverseBindMatrices", Some(json!(inverse_bind_matrices))),
+            ])
+        })
+        .collect();
+
+    let animations: Vec<Value> = model
+        .animations
+        .iter()
+        .map(|animation| -> Result<Value> {
+            let mut samplers = Vec::new();
+            let mut channels = Vec::new();
+            for channel in &animation.channels {
+                let input = builder.push_floats(&channel.inputs, 1, "SCALAR", true, None);
+                let (output, target_path) = match &channel.outputs {
+                    ChannelOutputs::Translations(values) => (
+                        builder.push_floats(&flatten(values), 3, "VEC3", false, None),
+                        "translation",
+                    ),
+                    ChannelOutputs::Rotations(values) => (
+                        builder.push_floats(&flatten(values), 4, "VEC4", false, None),
+                        "rotation",
+                    ),
+                    ChannelOutputs::Scales(values) => (
+                        builder.push_floats(&flatten(values), 3, "VEC3", false, None),
+                        "scale",
+                    ),
+                    ChannelOutputs::MorphTargetWeights(values) => (
+                        builder.push_floats(values, 1, "SCALAR", false, None),
+                        "weights",
+                    ),
+                };
+                channels.push(json!({
+                    "sampler": samplers.len(),
+                    "target": { "node": channel.node, "path": target_path },
+                }));
+                samplers.push(json!({
+                    "input": input,
+                    "output": output,
+                    "interpolation": serde_json::to_value(channel.interpolation)?,
+                }));
+            }
+            Ok(object(vec![
+                ("name", animation.name.as_ref().map(|name| json!(name))),
+                ("samplers", Some(json!(samplers))),
+                ("channels", Some(json!(channels))),
+            ]))
+        })
+        .collect::<Result<_>>()?;
+
+    let images: Vec<Value> = model
+        .images
+        .iter()
+        .map(|image| {
+            let (uri, view) = match &image.source {
+                ImageSource::Uri(uri) => (Some(json!(uri)), None),
+                ImageSource::Bytes(bytes) => (None, Some(json!(builder.push_view(bytes, None)))),
+            };
+            object(vec![
+                ("name", image.name.as_ref().map(|name| json!(name))),
+                ("uri", uri),
+                ("bufferView", view),
+                ("mimeType", image.mime_type.as_ref().map(|mime| json!(mime))),
+            ])
+        })
+        .collect();
+
+    let samplers: Vec<Value> = model
+        .samplers
+        .iter()
+        .map(|sampler| {
+            object(vec![
+                ("name", sampler.name.as_ref().map(|name| json!(name))),
+                (
+                    "magFilter",
+                    sampler.mag_filter.map(|filter| json!(filter.as_gl_enum())),
+                ),
+                (
+                    "minFilter",
+                    sampler.min_filter.map(|filter| json!(filter.as_gl_enum())),
+                ),
+                ("wrapS", Some(json!(sampler.wrap_s.as_gl_enum()))),
+                ("wrapT", Some(json!(sampler.wrap_t.as_gl_enum()))),
+            ])
+        })
+        .collect();
+
+    let textures: Vec<Value> = model
+        .textures
+        .iter()
+        .map(|texture| {
+            object(vec![
+                ("name", texture.name.as_ref().map(|name| json!(name))),
+                ("sampler", texture.sampler.map(Value::from)),
+                ("source", Some(json!(texture.image))),
+            ])
+        })
+        .collect();
+
+    let materials: Vec<Value> = model
+        .materials
+        .iter()
+        .map(|material| export_material(material, &mut extensions))
+        .collect();
+
+    let scenes: Vec<Value> = model
+        .scenes
+        .iter()
+        .map(|roots| {
+            let nodes: Vec<usize> = roots.iter().map(|root| root.index).collect();
+            json!({ "nodes": nodes })
+        })
+        .collect();
+
+    let non_empty = |values: Vec<Value>| {
+        if values.is_empty() {
+            None
+        } else {
+            Some(Value::Array(values))
+        }
+    };
+    let document = object(vec![
+        (
+            "asset",
+            Some(json!({ "version": "2.0", "generator": "skinning" })),
+        ),
+        (
+            "extensionsUsed",
+            Some(json!(extensions)).filter(|_| !extensions.is_empty()),
+        ),
+        ("scene", model.scene.map(Value::from)),
+        ("scenes", non_empty(scenes)),
+        ("nodes", non_empty(nodes)),
+        ("meshes", non_empty(meshes)),
+        ("skins", non_empty(skins)),
+        ("animations", non_empty(animations)),
+        ("materials", non_empty(materials)),
+        ("samplers", non_empty(samplers)),
+        ("textures", non_empty(textures)),
+        ("images", non_empty(images)),
+        ("accessors", non_empty(builder.accessors)),
+        ("bufferViews", non_empty(builder.views)),
+    ]);
+    Ok((document, builder.data))
+}
+
+fn write_glb(path: &Path, mut document: Value, mut data: Vec<u8>) -> Result<()> {
+    if !data.is_empty() {
+        document["buffers"] = json!([{ "byteLength": data.len() }]);
+    }
+    let mut json = serde_json::to_vec(&document)?;
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+
+    let bin_chunk_len = if data.is_empty() { 0 } else { 8 + data.len() };
+    let total_len = 12 + 8 + json.len() + bin_chunk_len;
+    let mut glb = Vec::with_capacity(total_len);
+    glb.extend_from_slice(GLB_MAGIC);
+    glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+    glb.extend_from_slice(&(total_len as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&GLB_CHUNK_JSON.to_le_bytes());
+    glb.extend_from_slice(&json);
+    if !data.is_empty() {
+        glb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&GLB_CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&data);
+    }
+    std::fs::write(path, glb)?;
+    Ok(())
+}
+
+fn write_gltf(path: &Path, mut document: Value, data: Vec<u8>) -> Result<()> {
+    if !data.is_empty() {
+        let bin_path = path.with_extension("bin");
+        let uri = match bin_path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => bail!("{}: not a valid file name", path.display()),
+        };
+        document["buffers"] = json!([{ "byteLength": data.len(), "uri": uri }]);
+        std::fs::write(bin_path, data)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(&document)?)?;
+    Ok(())
+}
+
+/// Copies the images referenced by relative URIs from `base_dir`, the
+/// directory of the source document, to `out_dir` so the URIs still resolve.
+fn copy_images(model: &Model, base_dir: &Path, out_dir: &Path) -> Result<()> {
+    for image in &model.images {
+        let uri = match &image.source {
+            ImageSource::Uri(uri) if !uri.starts_with("data:") => uri,
+            _ => continue,
+        };
+        let (from, to) = (base_dir.join(uri), out_dir.join(uri));
+        if to.exists() && from.canonicalize()? == to.canonicalize()? {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&from, &to)
+            .with_context(|| format!("Could not copy image {}", from.display()))?;
+    }
+    Ok(())
+}
+
+/// Writes a single `.glb` when the extension asks for it, otherwise a `.gltf`
+/// with the buffer in a `.bin` file next to it. Image files are copied next
+/// to the export, their URIs are relative to `base_dir`.
+pub fn export_gltf<P: AsRef<Path>>(model: &Model, base_dir: &Path, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let (document, data) = build_document(model)?;
+    copy_images(
+        model,
+        base_dir,
+        path.parent().unwrap_or_else(|| Path::new("")),
+    )?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("glb") => write_glb(path, document, data),
+        _ => write_gltf(path, document, data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::load_gltf;
+    use std::path::PathBuf;
+
+    /// Names the first part of the model that didn't survive the round trip.
+    fn compare(expected: &Model, actual: &Model) -> Option<&'static str> {
+        if expected.nodes != actual.nodes {
+            Some("nodes")
+        } else if expected.meshes != actual.meshes {
+            Some("meshes")
+        } else if expected.skins != actual.skins {
+            Some("skins")
+        } else if expected.animations != actual.animations {
+            Some("animations")
+        } else if expected.materials != actual.materials {
+            Some("materials")
+        } else if expected.samplers != actual.samplers {
+            Some("samplers")
+        } else if expected.textures != actual.textures {
+            Some("textures")
+        } else if expected.images != actual.images {
+            Some("images")
+        } else if expected.scenes != actual.scenes || expected.scene != actual.scene {
+            Some("scenes")
+        } else if expected.instances != actual.instances {
+            Some("instances")
+        } else {
+            None
+        }
+    }
+
+    fn assets() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/assets")
+    }
+
+    fn load_asset(name: &str) -> Model {
+        load_gltf(assets().join(name)).unwrap()
+    }
+
+    /// Exports `name` as both `.gltf` and `.glb` into a directory other than
+    /// the source's, loads the exports back and checks they match.
+    fn round_trip(name: &str) -> Model {
+        let model = load_asset(name);
+        round_trip_model(name, &model);
+        model
+    }
+
+    /// Like `round_trip`, for a model changed after loading, exported as
+    /// `name`.
+    fn round_trip_model(name: &str, model: &Model) {
+        let out_dir = std::env::temp_dir().join(format!(
+            "skinning-round-trip-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        for ext in &["gltf", "glb"] {
+            let exported = out_dir.join(Path::new(name).with_extension(ext));
+            export_gltf(model, &assets(), &exported).unwrap();
+            let reloaded = load_gltf(&exported).unwrap();
+            assert_eq!(
+                compare(model, &reloaded),
+                None,
+                "{} differs after the round trip through {}",
+                name,
+                exported.display()
+            );
+            for image in &reloaded.images {
+                image.bytes(&out_dir).unwrap();
+            }
+        }
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn textured_round_trip() {
+        let model = round_trip("textured.gltf");
+        assert_eq!(model.textures[0].sampler, Some(0));
+        assert_eq!(
+            model.images[0].source,
+            ImageSource::Uri("checker.png".into())
+        );
+    }
+
+    #[test]
+    fn skinned_round_trip() {
+        let model = round_trip("skinned.gltf");
+        assert_eq!(model.skins[0].skeleton, Some(1));
+        assert_eq!(model.animations[0].channels.len(), 1);
+    }
+
+    #[test]
+    fn default_scene_round_trip() {
+        let mut model = load_asset("textured.gltf");
+        assert_eq!(model.scene, Some(0));
+        model.scenes.push(model.scenes[0].clone());
+        model.scene = Some(1);
+        round_trip_model("default-scene.gltf", &model);
+    }
+
+    #[test]
+    fn instanced_round_trip() {
+        let model = round_trip("instanced.gltf");
+        assert_eq!(model.instances[0].transforms.len(), 3);
+        assert!(model.instances[0].rotations.is_none());
+    }
+}
@@ -1,70 +1,754 @@
-use crate::render_types::VertexTexNormal;
+use crate::{
+    bounds::Bounds,
+    bvh::Bvh,
+    optimize::{self, OptimizeStats},
+    render_types::{VertexPosition, VertexTexNormalTangent},
+    simplify, tangents,
+};
+use anyhow::{ensure, Result};
+
+/// Levels generated past the full resolution mesh.
+const MAX_LODS: usize = 5;
+/// The most `MeshData::ico_sphere` can subdivide within 16 bit indices.
+const MAX_ICO_SPHERE_SUBDIVISIONS: u32 = 6;
+
+/// Range of the shared index buffer holding one level of detail.
+#[derive(Debug, Clone, Copy)]
+pub struct Lod {
+    pub first_index: u32,
+    pub num_indices: u32,
+    /// Largest deviation from the full resolution mesh, in mesh units.
+    pub error: f32,
+}
+
+/// How a mesh is prepared before it's uploaded, set per asset.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadOptions {
+    /// Weld the vertices and reorder them for the vertex caches.
+    pub optimize: bool,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions { optimize: true }
+    }
+}
 
 pub struct Geometry {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_vertices: u32,
     pub num_indices: u32,
+    /// Finest first, the first level covers `num_indices`.
+    pub lods: Vec<Lod>,
+    pub bounds: Bounds,
+    /// Over the triangles of the first level.
+    pub bvh: Bvh,
+    /// Set if the mesh went through the optimizer on upload.
+    pub optimize_stats: Option<OptimizeStats>,
 }
 
 impl Geometry {
     pub fn new<T>(device: &wgpu::Device, vertices: &[T], indices: &[u16]) -> Self
     where
-        T: bytemuck::Pod + bytemuck::Zeroable,
+        T: bytemuck::Pod + bytemuck::Zeroable + VertexPosition,
     {
+        let lods = vec![Lod {
+            first_index: 0,
+            num_indices: indices.len() as u32,
+            error: 0.0,
+        }];
+        Geometry::with_levels(device, vertices, indices, lods)
+    }
+
+    fn with_levels<T>(
+        device: &wgpu::Device,
+        vertices: &[T],
+        indices: &[u16],
+        lods: Vec<Lod>,
+    ) -> Self
+    where
+        T: bytemuck::Pod + bytemuck::Zeroable + VertexPosition,
+    {
+        let positions: Vec<_> = vertices.iter().map(VertexPosition::position).collect();
+        let bounds = Bounds::from_points(&positions);
+        let bvh = Bvh::new(&positions, &indices[..lods[0].num_indices as usize]);
+
         let vertex_buffer = device
             .create_buffer_with_data(bytemuck::cast_slice(&vertices), wgpu::BufferUsage::VERTEX);
         let num_vertices = vertices.len() as u32;
 
         let index_buffer = device
             .create_buffer_with_data(bytemuck::cast_slice(&indices), wgpu::BufferUsage::INDEX);
-        let num_indices = indices.len() as u32;
 
         Geometry {
             vertex_buffer,
             index_buffer,
             num_vertices,
-            num_indices,
+            num_indices: lods[0].num_indices,
+            lods,
+            bounds,
+            bvh,
+            optimize_stats: None,
         }
     }
 
+    /// Uploads the mesh with a chain of simplified levels, all levels index
+    /// the same vertices and sit back to back in one index buffer. The mesh
+    /// is optimized first if `options` ask for it.
+    pub fn with_lods(
+        device: &wgpu::Device,
+        vertices: &[VertexTexNormalTangent],
+        indices: &[u16],
+        options: UploadOptions,
+    ) -> Self {
+        let optimized;
+        let (vertices, indices, optimize_stats) = if options.optimize {
+            let (vertices, indices, stats) = optimize::optimize_mesh(vertices, indices);
+            optimized = (vertices, indices);
+            (&optimized.0[..], &optimized.1[..], Some(stats))
+        } else {
+            (vertices, indices, None)
+        };
+
+        let positions: Vec<_> = vertices.iter().map(|v| v.position).collect();
+        let tex_coords: Vec<_> = vertices.iter().map(|v| v.tex_coord).collect();
+        let chain = simplify::build_lod_chain(&positions, &tex_coords, indices, MAX_LODS + 1);
+
+        let mut all_indices = Vec::with_capacity(indices.len() * 2);
+        let lods = chain
+            .into_iter()
+            .map(|level| {
+                let lod = Lod {
+                    first_index: all_indices.len() as u32,
+                    num_indices: level.indices.len() as u32,
+                    error: level.error,
+                };
+                all_indices.extend(level.indices);
+                lod
+            })
+            .collect();
+
+        Geometry {
+            optimize_stats,
+            ..Geometry::with_levels(device, vertices, &all_indices, lods)
+        }
+    }
+
+    /// The level drawn for `level`, meshes with a shorter chain keep their
+    /// coarsest one.
+    pub fn lod(&self, level: usize) -> &Lod {
+        &self.lods[level.min(self.lods.len() - 1)]
+    }
+
     pub fn create_sphere_pbr(device: &wgpu::Device) -> Self {
-        use std::f32::consts::PI;
+        MeshData::uv_sphere(1.0, 64, 64)
+            .expect("a 64x64 sphere fits 16 bit indices")
+            .upload_with_lods(device, UploadOptions::default())
+            .expect("a uv sphere has no mirrored uvs to split")
+    }
+}
 
-        let mut vertices = vec![];
-        let mut indices = vec![];
-
-        const XS: u16 = 64;
-        const YS: u16 = 64;
-
-        for y in 0..=YS {
-            for x in 0..=XS {
-                let xs = (x as f32) / (XS as f32);
-                let ys = (y as f32) / (YS as f32);
-                let xp = (xs * 2. * PI).cos() * (ys * PI).sin();
-                let yp = (ys * PI).cos();
-                let zp = (xs * 2. * PI).sin() * (ys * PI).sin();
-                vertices.push(VertexTexNormal {
-                    position: [xp, yp, zp],
-                    tex_coord: [xs, ys],
-                    normal: [xp, yp, zp],
-                })
+/// Point of a profile revolved around +Y, `normal` is in the (radius, y)
+/// plane.
+#[derive(Clone, Copy)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2],
+    v: f32,
+}
+
+/// Triangle list on the CPU, counter clockwise seen from the outside with the
+/// uv origin at the top left. The generators are centered on the origin with
+/// +Y up, and reject sizes whose vertices don't fit 16 bit indices.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u16>,
+}
+
+/// Checks the vertex count of a generator's parameters before it runs, the
+/// index arithmetic in `push_grid` and `push_lathe` can't overflow after.
+fn check_vertex_count(name: &str, counts: &[u16], vertices: usize) -> Result<()> {
+    ensure!(
+        counts.iter().all(|&count| count > 0),
+        "{} needs at least one subdivision, got {:?}",
+        name,
+        counts
+    );
+    ensure!(
+        vertices <= u16::MAX as usize + 1,
+        "{} with {:?} subdivisions has {} vertices, more than 16 bit indices can address",
+        name,
+        counts,
+        vertices
+    );
+    Ok(())
+}
+
+/// Vertices of `segments + 1` columns around a lathe profile of `points`.
+fn lathe_vertices(points: usize, segments: u16) -> usize {
+    points * (segments as usize + 1)
+}
+
+impl MeshData {
+    fn push_vertex(&mut self, position: [f32; 3], normal: [f32; 3], tex_coord: [f32; 2]) -> u16 {
+        debug_assert!(self.positions.len() <= u16::MAX as usize);
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.tex_coords.push(tex_coord);
+        (self.positions.len() - 1) as u16
+    }
+
+    /// Subdivided rectangle facing `normal`, `right` and `up` span it and
+    /// must satisfy `right x up = normal`.
+    fn push_grid(
+        &mut self,
+        center: [f32; 3],
+        [right, up, normal]: [[f32; 3]; 3],
+        [width, height]: [f32; 2],
+        [columns, rows]: [u16; 2],
+    ) {
+        let base = self.positions.len() as u16;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let s = column as f32 / columns as f32;
+                let t = row as f32 / rows as f32;
+                let (x, y) = ((s - 0.5) * width, (0.5 - t) * height);
+                let position = [
+                    center[0] + right[0] * x + up[0] * y,
+                    center[1] + right[1] * x + up[1] * y,
+                    center[2] + right[2] * x + up[2] * y,
+                ];
+                self.push_vertex(position, normal, [s, t]);
+            }
+        }
+        for row in 0..rows {
+            for column in 0..columns {
+                let top_left = base + row * (columns + 1) + column;
+                let bottom_left = top_left + columns + 1;
+                self.indices.extend_from_slice(&[
+                    top_left,
+                    bottom_left,
+                    bottom_left + 1,
+                    top_left,
+                    bottom_left + 1,
+                    top_left + 1,
+                ]);
             }
         }
+    }
 
-        for y in 0..YS {
-            if y & 1 == 0 {
-                for x in 0..=XS {
-                    indices.push(y * (XS + 1) + x);
-                    indices.push((y + 1) * (XS + 1) + x);
+    /// Revolves `profile` around +Y. The profile runs top to bottom along the
+    /// outside, triangles collapsing onto the axis are left out.
+    fn push_lathe(&mut self, profile: &[ProfilePoint], segments: u16) {
+        use std::f32::consts::PI;
+
+        let base = self.positions.len() as u16;
+        for point in profile {
+            for segment in 0..=segments {
+                let s = segment as f32 / segments as f32;
+                let (sin, cos) = (s * 2.0 * PI).sin_cos();
+                self.push_vertex(
+                    [point.radius * cos, point.y, point.radius * sin],
+                    [
+                        point.normal[0] * cos,
+                        point.normal[1],
+                        point.normal[0] * sin,
+                    ],
+                    [s, point.v],
+                );
+            }
+        }
+        for (row, pair) in profile.windows(2).enumerate() {
+            for segment in 0..segments {
+                let top = base + row as u16 * (segments + 1) + segment;
+                let bottom = top + segments + 1;
+                if pair[0].radius != 0.0 {
+                    self.indices.extend_from_slice(&[top, top + 1, bottom]);
+                }
+                if pair[1].radius != 0.0 {
+                    self.indices
+                        .extend_from_slice(&[top + 1, bottom + 1, bottom]);
                 }
+            }
+        }
+    }
+
+    /// Flat cap facing +Y or -Y with a planar uv mapping.
+    fn push_disc(&mut self, y: f32, radius: f32, segments: u16, facing_up: bool) {
+        use std::f32::consts::PI;
+
+        let (normal, flip) = if facing_up {
+            ([0.0, 1.0, 0.0], 1.0)
+        } else {
+            ([0.0, -1.0, 0.0], -1.0)
+        };
+        let center = self.push_vertex([0.0, y, 0.0], normal, [0.5, 0.5]);
+        for segment in 0..=segments {
+            let (sin, cos) = (segment as f32 / segments as f32 * 2.0 * PI).sin_cos();
+            self.push_vertex(
+                [radius * cos, y, radius * sin],
+                normal,
+                [0.5 + 0.5 * cos, 0.5 + 0.5 * sin * flip],
+            );
+        }
+        for segment in 0..segments {
+            let (a, b) = (center + 1 + segment, center + 2 + segment);
+            let triangle = if facing_up {
+                [center, b, a]
             } else {
-                for x in (0..=XS).rev() {
-                    indices.push((y + 1) * (XS + 1) + x);
-                    indices.push(y * (XS + 1) + x);
+                [center, a, b]
+            };
+            self.indices.extend_from_slice(&triangle);
+        }
+    }
+
+    /// Box with one uv square per face.
+    pub fn cube(size: [f32; 3]) -> Self {
+        let [x, y, z] = [size[0] / 2.0, size[1] / 2.0, size[2] / 2.0];
+        // center, (right, up, normal), face size
+        let faces = [
+            (
+                [x, 0.0, 0.0],
+                [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+                [size[2], size[1]],
+            ),
+            (
+                [-x, 0.0, 0.0],
+                [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]],
+                [size[2], size[1]],
+            ),
+            (
+                [0.0, y, 0.0],
+                [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+                [size[0], size[2]],
+            ),
+            (
+                [0.0, -y, 0.0],
+                [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]],
+                [size[0], size[2]],
+            ),
+            (
+                [0.0, 0.0, z],
+                [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+                [size[0], size[1]],
+            ),
+            (
+                [0.0, 0.0, -z],
+                [[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]],
+                [size[0], size[1]],
+            ),
+        ];
+        let mut mesh = MeshData::default();
+        for &(center, axes, face_size) in faces.iter() {
+            mesh.push_grid(center, axes, face_size, [1, 1]);
+        }
+        mesh
+    }
+
+    /// Grid in the XZ plane facing +Y, `subdivisions` cells along X and Z.
+    pub fn plane(size: [f32; 2], subdivisions: [u16; 2]) -> Result<Self> {
+        let [columns, rows] = subdivisions;
+        check_vertex_count(
+            "Plane",
+            &subdivisions,
+            (columns as usize + 1) * (rows as usize + 1),
+        )?;
+        let mut mesh = MeshData::default();
+        mesh.push_grid(
+            [0.0; 3],
+            [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+            size,
+            subdivisions,
+        );
+        Ok(mesh)
+    }
+
+    /// Latitude-longitude sphere, `segments` around Y and `rings` from pole to
+    /// pole.
+    pub fn uv_sphere(radius: f32, segments: u16, rings: u16) -> Result<Self> {
+        use std::f32::consts::PI;
+
+        check_vertex_count(
+            "UV sphere",
+            &[segments, rings],
+            lathe_vertices(rings as usize + 1, segments),
+        )?;
+        let profile: Vec<_> = (0..=rings)
+            .map(|ring| {
+                let v = ring as f32 / rings as f32;
+                let (sin, cos) = (v * PI).sin_cos();
+                ProfilePoint {
+                    radius: if ring == 0 || ring == rings {
+                        0.0
+                    } else {
+                        radius * sin
+                    },
+                    y: radius * cos,
+                    normal: [sin, cos],
+                    v,
                 }
+            })
+            .collect();
+        let mut mesh = MeshData::default();
+        mesh.push_lathe(&profile, segments);
+        Ok(mesh)
+    }
+
+    /// Subdivided icosahedron, evenly sized triangles without the pinching
+    /// of `uv_sphere` at the poles. Vertices are split along the uv seam.
+    pub fn ico_sphere(radius: f32, subdivisions: u32) -> Result<Self> {
+        use std::{collections::HashMap, f32::consts::PI};
+
+        // 10 * 4^n + 2 points before the uv seam splits some of them
+        ensure!(
+            subdivisions <= MAX_ICO_SPHERE_SUBDIVISIONS,
+            "Ico sphere with {} subdivisions has more vertices than 16 bit indices can address, \
+             at most {} are supported",
+            subdivisions,
+            MAX_ICO_SPHERE_SUBDIVISIONS
+        );
+        // poles on the Y axis and two staggered rings of five in between
+        let (ring_y, ring_radius) = (1.0 / 5f32.sqrt(), 2.0 / 5f32.sqrt());
+        let mut points: Vec<[f32; 3]> = vec![[0.0, 1.0, 0.0], [0.0, -1.0, 0.0]];
+        for &(y, offset) in [(ring_y, 0.0), (-ring_y, PI / 5.0)].iter() {
+            for i in 0..5 {
+                let (sin, cos) = (i as f32 * 2.0 * PI / 5.0 + offset).sin_cos();
+                points.push([ring_radius * cos, y, ring_radius * sin]);
             }
         }
-        Geometry::new(&device, &vertices, &indices)
+        let normalize = |[x, y, z]: [f32; 3]| {
+            let length = (x * x + y * y + z * z).sqrt();
+            [x / length, y / length, z / length]
+        };
+        let (upper, lower) = (|i: usize| 2 + i % 5, |i: usize| 7 + i % 5);
+        let mut triangles: Vec<[usize; 3]> = (0..5)
+            .flat_map(|i| {
+                vec![
+                    [0, upper(i + 1), upper(i)],
+                    [upper(i), upper(i + 1), lower(i)],
+                    [upper(i + 1), lower(i + 1), lower(i)],
+                    [1, lower(i), lower(i + 1)],
+                ]
+            })
+            .collect();
+        for _ in 0..subdivisions {
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, points: &mut Vec<[f32; 3]>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let (pa, pb) = (points[a], points[b]);
+                    points.push(normalize([pa[0] + pb[0], pa[1] + pb[1], pa[2] + pb[2]]));
+                    points.len() - 1
+                })
+            };
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let ab = midpoint(a, b, &mut points);
+                    let bc = midpoint(b, c, &mut points);
+                    let ca = midpoint(c, a, &mut points);
+                    vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // same mapping as `uv_sphere`, a triangle crossing the seam gets its
+        // low side moved past u = 1 and the poles take the u of the triangle
+        let mut mesh = MeshData::default();
+        let mut vertices: HashMap<(usize, u32), u16> = HashMap::new();
+        for triangle in &triangles {
+            let mut uvs: Vec<[f32; 2]> = triangle
+                .iter()
+                .map(|&i| {
+                    let [x, y, z] = points[i];
+                    let u = z.atan2(x) / (2.0 * PI);
+                    let v = if y.abs() < 1.0 {
+                        y.acos() / PI
+                    } else if y > 0.0 {
+                        0.0
+                    } else {
+                        1.0
+                    };
+                    [if u < 0.0 { u + 1.0 } else { u }, v]
+                })
+                .collect();
+            let is_pole = |i: usize| points[triangle[i]][1].abs() > 1.0 - 1e-6;
+            let non_pole: Vec<usize> = (0..3).filter(|&i| !is_pole(i)).collect();
+            let (min_u, max_u) = non_pole.iter().fold((1.0f32, 0.0f32), |(min, max), &i| {
+                (min.min(uvs[i][0]), max.max(uvs[i][0]))
+            });
+            if max_u - min_u > 0.5 {
+                for &i in &non_pole {
+                    if uvs[i][0] < 0.5 {
+                        uvs[i][0] += 1.0;
+                    }
+                }
+            }
+            for i in (0..3).filter(|&i| is_pole(i)) {
+                uvs[i][0] =
+                    non_pole.iter().map(|&j| uvs[j][0]).sum::<f32>() / non_pole.len() as f32;
+            }
+            for (&point, &uv) in triangle.iter().zip(&uvs) {
+                let index = *vertices.entry((point, uv[0].to_bits())).or_insert_with(|| {
+                    let [x, y, z] = points[point];
+                    mesh.push_vertex([x * radius, y * radius, z * radius], points[point], uv)
+                });
+                mesh.indices.push(index);
+            }
+        }
+        Ok(mesh)
+    }
+
+    /// Capped cylinder along Y.
+    pub fn cylinder(radius: f32, height: f32, segments: u16) -> Result<Self> {
+        check_vertex_count(
+            "Cylinder",
+            &[segments],
+            lathe_vertices(2, segments) + 2 * (segments as usize + 2),
+        )?;
+        let half = height / 2.0;
+        let side = |y: f32, v: f32| ProfilePoint {
+            radius,
+            y,
+            normal: [1.0, 0.0],
+            v,
+        };
+        let mut mesh = MeshData::default();
+        mesh.push_lathe(&[side(half, 0.0), side(-half, 1.0)], segments);
+        mesh.push_disc(half, radius, segments, true);
+        mesh.push_disc(-half, radius, segments, false);
+        Ok(mesh)
+    }
+
+    /// Cone along Y with the apex on top and a capped base.
+    pub fn cone(radius: f32, height: f32, segments: u16) -> Result<Self> {
+        check_vertex_count(
+            "Cone",
+            &[segments],
+            lathe_vertices(2, segments) + segments as usize + 2,
+        )?;
+        let half = height / 2.0;
+        let slant = (radius * radius + height * height).sqrt();
+        let normal = [height / slant, radius / slant];
+        let mut mesh = MeshData::default();
+        mesh.push_lathe(
+            &[
+                ProfilePoint {
+                    radius: 0.0,
+                    y: half,
+                    normal,
+                    v: 0.0,
+                },
+                ProfilePoint {
+                    radius,
+                    y: -half,
+                    normal,
+                    v: 1.0,
+                },
+            ],
+            segments,
+        );
+        mesh.push_disc(-half, radius, segments, false);
+        Ok(mesh)
+    }
+
+    /// Torus around Y, `major_radius` to the center of the tube.
+    pub fn torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u16,
+        minor_segments: u16,
+    ) -> Result<Self> {
+        use std::f32::consts::PI;
+
+        check_vertex_count(
+            "Torus",
+            &[major_segments, minor_segments],
+            lathe_vertices(minor_segments as usize + 1, major_segments),
+        )?;
+        // starts on the outer equator, heading down
+        let profile: Vec<_> = (0..=minor_segments)
+            .map(|segment| {
+                let v = segment as f32 / minor_segments as f32;
+                let (sin, cos) = (v * 2.0 * PI).sin_cos();
+                ProfilePoint {
+                    radius: major_radius + minor_radius * cos,
+                    y: -minor_radius * sin,
+                    normal: [cos, -sin],
+                    v,
+                }
+            })
+            .collect();
+        let mut mesh = MeshData::default();
+        mesh.push_lathe(&profile, major_segments);
+        Ok(mesh)
+    }
+
+    /// Cylinder of `height` between two hemispheres, so the total height is
+    /// `height + 2 * radius`. `rings` per hemisphere, v follows the arc
+    /// length of the profile so the texture doesn't stretch on the sides.
+    pub fn capsule(radius: f32, height: f32, segments: u16, rings: u16) -> Result<Self> {
+        use std::f32::consts::PI;
+
+        check_vertex_count(
+            "Capsule",
+            &[segments, rings],
+            lathe_vertices(2 * (rings as usize + 1), segments),
+        )?;
+        let half = height / 2.0;
+        let length = PI * radius + height;
+        let hemisphere = |top: bool| {
+            (0..=rings).map(move |ring| {
+                let angle =
+                    ring as f32 / rings as f32 * PI / 2.0 + if top { 0.0 } else { PI / 2.0 };
+                let (sin, cos) = angle.sin_cos();
+                let on_axis = (top && ring == 0) || (!top && ring == rings);
+                let arc = angle * radius + if top { 0.0 } else { height };
+                ProfilePoint {
+                    radius: if on_axis { 0.0 } else { radius * sin },
+                    y: radius * cos + if top { half } else { -half },
+                    normal: [sin, cos],
+                    v: arc / length,
+                }
+            })
+        };
+        let profile: Vec<_> = hemisphere(true).chain(hemisphere(false)).collect();
+        let mut mesh = MeshData::default();
+        mesh.push_lathe(&profile, segments);
+        Ok(mesh)
+    }
+
+    /// Interleaved vertices with MikkTSpace tangents and the indices into
+    /// them. Fails if the vertices split for their tangents don't fit 16 bit
+    /// indices anymore.
+    pub fn vertices(&self) -> Result<(Vec<VertexTexNormalTangent>, Vec<u16>)> {
+        let indices: Vec<u32> = self.indices.iter().map(|&i| i as u32).collect();
+        let generated =
+            tangents::generate_tangents(&self.positions, &self.normals, &self.tex_coords, &indices);
+        ensure!(
+            generated.vertices.len() <= u16::MAX as usize + 1,
+            "Mesh has {} vertices after splitting them for their tangents, more than 16 bit \
+             indices can address",
+            generated.vertices.len()
+        );
+        let vertices = generated
+            .vertices
+            .iter()
+            .zip(&generated.tangents)
+            .map(|(&source, &tangent)| VertexTexNormalTangent {
+                position: self.positions[source as usize],
+                tex_coord: self.tex_coords[source as usize],
+                normal: self.normals[source as usize],
+                tangent,
+            })
+            .collect();
+        let indices = generated.indices.iter().map(|&i| i as u16).collect();
+        Ok((vertices, indices))
+    }
+
+    pub fn upload_with_lods(
+        &self,
+        device: &wgpu::Device,
+        options: UploadOptions,
+    ) -> Result<Geometry> {
+        let (vertices, indices) = self.vertices()?;
+        Ok(Geometry::with_lods(device, &vertices, &indices, options))
+    }
+}
+
+/// Splits a triangle list into pieces that fit 16 bit indices. Every piece
+/// has the source vertex of each of its vertices and its own indices.
+pub fn split_indices(vertex_count: usize, indices: &[u32]) -> Vec<(Vec<u32>, Vec<u16>)> {
+    use std::collections::HashMap;
+
+    const MAX_VERTICES: usize = u16::MAX as usize + 1;
+    if vertex_count <= MAX_VERTICES {
+        return vec![(
+            (0..vertex_count as u32).collect(),
+            indices.iter().map(|&i| i as u16).collect(),
+        )];
+    }
+
+    let mut pieces = Vec::new();
+    let (mut vertices, mut local) = (Vec::new(), Vec::new());
+    let mut lookup: HashMap<u32, u16> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let added = triangle.iter().filter(|i| !lookup.contains_key(i)).count();
+        if vertices.len() + added > MAX_VERTICES {
+            pieces.push((std::mem::take(&mut vertices), std::mem::take(&mut local)));
+            lookup.clear();
+        }
+        for &source in triangle {
+            let index = *lookup.entry(source).or_insert_with(|| {
+                vertices.push(source);
+                (vertices.len() - 1) as u16
+            });
+            local.push(index);
+        }
+    }
+    if !local.is_empty() {
+        pieces.push((vertices, local));
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_counts_match_the_checks() {
+        let meshes = [
+            (MeshData::plane([1.0; 2], [3, 5]).unwrap(), 4 * 6),
+            (MeshData::uv_sphere(1.0, 8, 4).unwrap(), 5 * 9),
+            (MeshData::cylinder(1.0, 1.0, 8).unwrap(), 2 * 9 + 2 * 10),
+            (MeshData::cone(1.0, 1.0, 8).unwrap(), 2 * 9 + 10),
+            (MeshData::torus(1.0, 0.5, 8, 4).unwrap(), 5 * 9),
+            (MeshData::capsule(1.0, 1.0, 8, 3).unwrap(), 8 * 9),
+        ];
+        for (mesh, vertices) in meshes.iter() {
+            assert_eq!(mesh.positions.len(), *vertices);
+            assert!(mesh.indices.iter().all(|&i| (i as usize) < *vertices));
+        }
+    }
+
+    #[test]
+    fn oversized_generators_are_rejected() {
+        assert!(MeshData::plane([1.0; 2], [255, 255]).is_ok());
+        assert!(MeshData::plane([1.0; 2], [256, 256]).is_err());
+        assert!(MeshData::plane([1.0; 2], [0, 4]).is_err());
+        assert!(MeshData::uv_sphere(1.0, u16::MAX, u16::MAX).is_err());
+        assert!(MeshData::cylinder(1.0, 1.0, u16::MAX).is_err());
+        assert!(MeshData::cone(1.0, 1.0, 0).is_err());
+        assert!(MeshData::torus(1.0, 0.5, 1024, 1024).is_err());
+        assert!(MeshData::capsule(1.0, 1.0, 1024, 64).is_err());
+        assert!(MeshData::ico_sphere(1.0, MAX_ICO_SPHERE_SUBDIVISIONS + 1).is_err());
+    }
+
+    #[test]
+    fn largest_ico_sphere_fits() {
+        let mesh = MeshData::ico_sphere(1.0, MAX_ICO_SPHERE_SUBDIVISIONS).unwrap();
+        assert!(mesh.positions.len() <= u16::MAX as usize + 1);
+    }
+
+    #[test]
+    fn split_indices_fit_16_bits() {
+        // a strip of triangles over more vertices than 16 bits address
+        let vertex_count = 70_000u32;
+        let indices: Vec<u32> = (0..vertex_count - 2)
+            .flat_map(|i| vec![i, i + 1, i + 2])
+            .collect();
+        let pieces = split_indices(vertex_count as usize, &indices);
+        assert_eq!(pieces.len(), 2);
+        let mut corners = Vec::new();
+        for (vertices, local) in &pieces {
+            assert!(vertices.len() <= u16::MAX as usize + 1);
+            corners.extend(local.iter().map(|&i| vertices[i as usize]));
+        }
+        assert_eq!(corners, indices);
     }
 }
@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+/// Largest possible `color_delta`, between black and white.
+const MAX_DELTA: f32 = 35215.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    /// Per pixel colour difference that is still a match, from 0 to 1.
+    pub threshold: f32,
+    /// Share of the pixels allowed to differ before the image fails.
+    pub max_mismatched: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            threshold: 0.1,
+            max_mismatched: 0.001,
+        }
+    }
+}
+
+pub enum Outcome {
+    Passed { mismatched: usize },
+    Failed { mismatched: usize },
+    Recorded,
+}
+
+/// Perceived difference of two pixels in YIQ space, after blending both
+/// onto white, as in pixelmatch.
+fn color_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let yiq = |p: &Rgba<u8>| {
+        let alpha = p[3] as f32 / 255.0;
+        let blend = |c: u8| 255.0 + (c as f32 - 255.0) * alpha;
+        let (r, g, b) = (blend(p[0]), blend(p[1]), blend(p[2]));
+        (
+            r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_2,
+            r * 0.595_978 - g * 0.274_176_1 - b * 0.321_801_9,
+            r * 0.211_470_2 - g * 0.522_617_1 + b * 0.311_146_9,
+        )
+    };
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+/// Counts the pixels that differ by more than the threshold, and draws them
+/// red over a faded copy of the reference.
+fn diff(actual: &RgbaImage, reference: &RgbaImage, threshold: f32) -> (usize, RgbaImage) {
+    let max_delta = MAX_DELTA * threshold * threshold;
+    let mut mismatched = 0;
+    let image = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, r) = (actual.get_pixel(x, y), reference.get_pixel(x, y));
+        if color_delta(a, r) > max_delta {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (r[0] as u32 * 299 + r[1] as u32 * 587 + r[2] as u32 * 114) / 1000;
+            let faded = 255 - (255 - luma) / 10;
+            Rgba([faded as u8, faded as u8, faded as u8, 255])
+        }
+    });
+    (mismatched, image)
+}
+
+/// Compares `actual` against `<dir>/<name>.png`, or writes it there when
+/// `update` is set. Failing images are saved to `<dir>/failures` along with
+/// their diff.
+pub fn check(
+    name: &str,
+    actual: &RgbaImage,
+    dir: &Path,
+    update: bool,
+    tolerance: &Tolerance,
+) -> Result<Outcome> {
+    let reference_path = dir.join(format!("{}.png", name));
+    if update {
+        std::fs::create_dir_all(dir)?;
+        actual
+            .save(&reference_path)
+            .with_context(|| format!("Failed to save {}", reference_path.display()))?;
+        return Ok(Outcome::Recorded);
+    }
+
+    let reference = image::open(&reference_path)
+        .with_context(|| {
+            format!(
+                "Failed to load {}, record it with --update",
+                reference_path.display()
+            )
+        })?
+        .to_rgba();
+    let failures = dir.join("failures");
+    let save_failure = |suffix: &str, image: &RgbaImage| -> Result<()> {
+        std::fs::create_dir_all(&failures)?;
+        let path = failures.join(format!("{}.{}.png", name, suffix));
+        image
+            .save(&path)
+            .with_context(|| format!("Failed to save {}", path.display()))
+    };
+
+    if reference.dimensions() != actual.dimensions() {
+        save_failure("actual", actual)?;
+        bail!(
+            "{} is {}x{} but the frame is {}x{}",
+            reference_path.display(),
+            reference.width(),
+            reference.height(),
+            actual.width(),
+            actual.height()
+        );
+    }
+    let (mismatched, diff_image) = diff(actual, &reference, tolerance.threshold);
+    let allowed = (tolerance.max_mismatched * (actual.width() * actual.height()) as f32) as usize;
+    if mismatched > allowed {
+        save_failure("actual", actual)?;
+        save_failure("diff", &diff_image)?;
+        Ok(Outcome::Failed { mismatched })
+    } else {
+        Ok(Outcome::Passed { mismatched })
+    }
+}
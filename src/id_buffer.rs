@@ -0,0 +1,171 @@
+use crate::{
+    render::ROW_ALIGNMENT,
+    render_graph::{GraphPass, PassAttachments, RenderGraph, TransientDesc},
+    staging::StagingBelt,
+};
+use futures::FutureExt;
+use std::{future::Future, pin::Pin};
+
+type ReadMapping =
+    Pin<Box<dyn Future<Output = Result<wgpu::BufferReadMapping, wgpu::BufferAsyncErr>>>>;
+
+/// Transients the instance and primitive ids are drawn into.
+pub const ID_INSTANCE: &str = "id_instance";
+pub const ID_PRIMITIVE: &str = "id_primitive";
+
+/// What the id buffer holds at one pixel. Instance ids are offset by one so
+/// that zero is the background.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdSample {
+    pub instance: Option<u32>,
+    pub primitive: u32,
+}
+
+/// Instance and primitive ids written next to the colour by a render pass,
+/// with an asynchronous readback of a single pixel. Only one readback is in
+/// flight at a time, requests made meanwhile are dropped.
+pub struct IdBuffer {
+    readback_buffer: wgpu::Buffer,
+    requested: Option<[u32; 2]>,
+    pending: Option<ReadMapping>,
+}
+
+impl IdBuffer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        IdBuffer {
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("id_readback_buffer"),
+                size: 2 * ROW_ALIGNMENT,
+                usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            }),
+            requested: None,
+            pending: None,
+        }
+    }
+
+    /// Drops a request made for the old size of the target.
+    pub fn resize(&mut self) {
+        self.requested = None;
+    }
+
+    /// Declares the id targets in `graph`, and copies the requested pixel
+    /// out of them once the pass writing them is done.
+    pub fn add_to<'a>(&'a self, graph: &mut RenderGraph<'a>) {
+        let desc = TransientDesc {
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            multisampled: false,
+        };
+        graph.add_transient(ID_INSTANCE, desc);
+        graph.add_transient(ID_PRIMITIVE, desc);
+        graph.add_pass(
+            "id_readback",
+            &[ID_INSTANCE, ID_PRIMITIVE],
+            &[],
+            IdReadback { id_buffer: self },
+        );
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.requested.is_none() && self.pending.is_none()
+    }
+
+    /// Asks for the pixel at `position` to be copied out after the next pass.
+    pub fn request(&mut self, position: [u32; 2]) {
+        if self.is_idle() {
+            self.requested = Some(position);
+        }
+    }
+
+    /// Records the copy of the requested pixel out of the instance and
+    /// primitive targets.
+    fn copy_requested(&self, encoder: &mut wgpu::CommandEncoder, targets: [&wgpu::Texture; 2]) {
+        let [x, y] = match self.requested {
+            Some(position) => position,
+            None => return,
+        };
+        for (i, texture) in targets.iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                wgpu::TextureCopyView {
+                    texture,
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                },
+                wgpu::BufferCopyView {
+                    buffer: &self.readback_buffer,
+                    offset: i as wgpu::BufferAddress * ROW_ALIGNMENT,
+                    bytes_per_row: ROW_ALIGNMENT as u32,
+                    rows_per_image: 1,
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+            );
+        }
+    }
+
+    /// Starts mapping the copy once the commands recording it are submitted,
+    /// returns whether there was one.
+    pub fn begin_readback(&mut self) -> bool {
+        if self.requested.take().is_none() {
+            return false;
+        }
+        self.pending = Some(Box::pin(
+            self.readback_buffer.map_read(0, 2 * ROW_ALIGNMENT),
+        ));
+        true
+    }
+
+    /// The sample once the GPU is done with it, without blocking.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<IdSample> {
+        device.poll(wgpu::Maintain::Poll);
+        let mapping = self.pending.as_mut()?.now_or_never()?;
+        self.pending = None;
+        let mapping = match mapping {
+            Ok(mapping) => mapping,
+            Err(_) => {
+                eprintln!("Failed to map the id readback buffer");
+                return None;
+            }
+        };
+        let bytes = mapping.as_slice();
+        let read = |offset: usize| {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[offset..offset + 4]);
+            u32::from_le_bytes(word)
+        };
+        let instance = read(0);
+        Some(IdSample {
+            instance: instance.checked_sub(1),
+            primitive: read(ROW_ALIGNMENT as usize),
+        })
+    }
+}
+
+/// Runs the copy of `IdBuffer::copy_requested` as part of a graph.
+struct IdReadback<'a> {
+    id_buffer: &'a IdBuffer,
+}
+
+impl GraphPass for IdReadback<'_> {
+    fn execute(
+        &self,
+        _device: &wgpu::Device,
+        attachments: &PassAttachments,
+        _staging: &mut StagingBelt,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.id_buffer.copy_requested(
+            encoder,
+            [
+                attachments.texture(ID_INSTANCE),
+                attachments.texture(ID_PRIMITIVE),
+            ],
+        );
+    }
+}
@@ -1,24 +1,146 @@
+use anyhow::Context;
 use futures::executor::block_on;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::WindowBuilder,
 };
 
-// mod model;
+mod bounds;
+mod bvh;
 mod camera;
+mod capture;
+mod clock;
 mod const_mesh;
+mod export;
 mod geometry;
+mod golden;
+mod id_buffer;
+mod model;
+mod obj;
+mod optimize;
 mod pipelines;
 mod render;
+mod render_graph;
 mod render_types;
+mod simplify;
+mod staging;
+mod tangents;
+mod terrain;
 mod texture;
 
 use camera::{Camera, CameraController};
+use clock::{Clock, Tick};
 use const_mesh::{CIRCLE_INDICES, CIRCLE_VERTICES, PENTAGON_INDICES, PENTAGON_VERTICES};
-use pipelines::pbr::{Pbr, PbrRenderPass, PbrState};
+use geometry::{MeshData, UploadOptions};
+use pipelines::pbr::{IdPick, Pbr, PbrRenderPass, PbrState};
 use pipelines::simple::{Simple, SimpleRenderPass, SimpleState};
-use render::Graphics;
+use render::{Frame, Graphics, GraphicsConfig, NoAdapter, SAMPLE_COUNTS};
+use render_graph::RenderGraph;
+use render_types::{MaterialInfoRaw, TransformRaw};
+
+struct Asset {
+    source: AssetSource,
+    upload: UploadOptions,
+}
+
+enum AssetSource {
+    // with the directory relative image URIs start from
+    Gltf(model::Model, std::path::PathBuf),
+    Obj(obj::ObjModel),
+    Primitive(MeshData),
+    Terrain(Vec<MeshData>),
+}
+
+fn primitive_mesh(name: &str) -> anyhow::Result<MeshData> {
+    match name {
+        "cube" => Ok(MeshData::cube([1.5, 1.5, 1.5])),
+        "plane" => MeshData::plane([2.0, 2.0], [8, 8]),
+        "uv-sphere" => MeshData::uv_sphere(1.0, 32, 16),
+        "ico-sphere" => MeshData::ico_sphere(1.0, 3),
+        "cylinder" => MeshData::cylinder(0.8, 1.6, 32),
+        "cone" => MeshData::cone(0.8, 1.6, 32),
+        "torus" => MeshData::torus(0.75, 0.25, 48, 24),
+        "capsule" => MeshData::capsule(0.5, 1.0, 32, 8),
+        _ => anyhow::bail!("Unknown primitive '{}'", name),
+    }
+}
+
+fn base_dir(path: &str) -> std::path::PathBuf {
+    std::path::Path::new(path)
+        .parent()
+        .map_or_else(Default::default, std::path::Path::to_path_buf)
+}
+
+fn load_asset(path: &str, export_path: Option<&String>, upload: UploadOptions) -> Option<Asset> {
+    if path.to_lowercase().ends_with(".obj") {
+        return match obj::load_obj(path) {
+            Ok(model) => {
+                println!(
+                    "Loaded {}: {} meshes, {} materials",
+                    path,
+                    model.meshes.len(),
+                    model.materials.len()
+                );
+                for (i, mesh) in model.meshes.iter().enumerate() {
+                    let name = mesh.name.as_deref().unwrap_or("unnamed");
+                    println!("  mesh {} ({}): {} vertices", i, name, mesh.vertices.len());
+                }
+                Some(Asset {
+                    source: AssetSource::Obj(model),
+                    upload,
+                })
+            }
+            Err(err) => {
+                eprintln!("Failed to load {}: {:#}", path, err);
+                None
+            }
+        };
+    }
+    match model::load_gltf(&path) {
+        Ok(model) => {
+            println!(
+                "Loaded {}: {} meshes, {} skins, {} animations, {} instanced nodes",
+                path,
+                model.meshes.len(),
+                model.skins.len(),
+                model.animations.len(),
+                model.instances.len()
+            );
+            let worlds = model.world_transforms();
+            for (i, skin) in model.skins.iter().enumerate() {
+                let joints: Vec<_> = skin.joints.iter().map(|&joint| worlds[joint]).collect();
+                if let Some(bounds) = skin.bounds(&joints) {
+                    println!(
+                        "  skin {} rest pose bounds: {:?} to {:?}",
+                        i, bounds.aabb.min, bounds.aabb.max
+                    );
+                }
+            }
+            if let Some(export_path) = export_path {
+                match export::export_gltf(&model, &base_dir(path), export_path) {
+                    Ok(()) => println!("Exported {}", export_path),
+                    Err(err) => eprintln!("Failed to export {}: {}", export_path, err),
+                }
+            }
+            Some(Asset {
+                source: AssetSource::Gltf(model, base_dir(path)),
+                upload,
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Numbered frames saved at a fixed simulated rate, whatever the real one.
+struct Recording {
+    dir: std::path::PathBuf,
+    frame_rate: u32,
+    frame: u32,
+}
 
 struct State {
     graphics: Graphics,
@@ -29,26 +151,40 @@ struct State {
     camera: Camera,
     camera_controller: CameraController,
     model_angle: f32,
+    // radians per second
     model_speed: f32,
+    clock: Clock,
+    // the simulation as of the step before, drawn interpolated towards now
+    previous_camera: Camera,
+    previous_model_angle: f32,
     size: winit::dpi::PhysicalSize<u32>,
     clear_color: wgpu::Color,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    hovered: Option<IdPick>,
     is_pbr: bool,
+    recording: Option<Recording>,
+    record_dir: std::path::PathBuf,
+    record_frame_rate: u32,
+    // where the frame being captured goes
+    capture_path: Option<std::path::PathBuf>,
+    // the glTF model shown and the directory its image URIs start from,
+    // exported with the viewer's changes by F9
+    gltf_source: Option<(model::Model, std::path::PathBuf)>,
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
+    fn new(graphics: Graphics, asset: Option<&Asset>) -> Self {
         let tree_diffuse_bytes = include_bytes!("../res/happy-tree.png");
         let face_diffuse_bytes = include_bytes!("../res/face.jpg");
 
         let model_angle = 0.0;
-        let model_speed = 0.02;
-        let size = window.inner_size();
+        let model_speed = 1.2;
+        let size = winit::dpi::PhysicalSize::new(graphics.sc_desc.width, graphics.sc_desc.height);
 
-        let graphics = Graphics::new(window).await;
         let device = &graphics.device;
         let sc_desc = &graphics.sc_desc;
 
-        let camera_controller = CameraController::new(0.2);
+        let camera_controller = CameraController::new(12.0);
         let camera = Camera::new(sc_desc.width, sc_desc.height);
 
         let simple = Simple::new(&device, &sc_desc);
@@ -71,27 +207,85 @@ impl State {
         let pbr_state = PbrState::new(&device, &sc_desc, &graphics.queue, &pbr, &camera);
         let is_pbr = true;
 
-        Self {
+        let mut state = Self {
             graphics,
             simple,
             simple_state,
             pbr,
             pbr_state,
+            previous_camera: camera.clone(),
             camera,
             camera_controller,
             model_angle,
             model_speed,
+            clock: Clock::new(std::time::Duration::from_secs(1) / 60),
+            previous_model_angle: model_angle,
             size,
             clear_color,
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            hovered: None,
+            recording: None,
+            record_dir: "frames".into(),
+            record_frame_rate: 30,
+            capture_path: None,
+            gltf_source: None,
             is_pbr,
+        };
+        if let Some(asset) = asset {
+            if let Err(err) = state.set_asset(asset) {
+                eprintln!("{:#}", err);
+            }
+        }
+        state
+    }
+
+    /// Replaces the PBR meshes with the asset's.
+    fn set_asset(&mut self, asset: &Asset) -> anyhow::Result<()> {
+        let device = &self.graphics.device;
+        let (pbr, pbr_state) = (&self.pbr, &mut self.pbr_state);
+        let upload = asset.upload;
+        self.gltf_source = None;
+        match &asset.source {
+            AssetSource::Gltf(model, base_dir) => {
+                pbr_state
+                    .set_model(device, &self.graphics.queue, pbr, model, base_dir, upload)
+                    .context("Failed to upload glTF")?;
+                self.gltf_source = Some((model.clone(), base_dir.clone()));
+            }
+            AssetSource::Obj(model) => pbr_state
+                .set_obj(device, &self.graphics.queue, pbr, model, upload)
+                .context("Failed to upload OBJ")?,
+            AssetSource::Primitive(mesh) => {
+                let geometry = mesh
+                    .upload_with_lods(device, upload)
+                    .context("Failed to upload primitive")?;
+                pbr_state.set_geometry(device, pbr, geometry);
+            }
+            AssetSource::Terrain(chunks) => pbr_state
+                .set_terrain(device, pbr, chunks, upload)
+                .context("Failed to upload terrain")?,
         }
+        for (i, mesh) in pbr_state.meshes.iter().enumerate() {
+            if let Some(stats) = &mesh.geometry.optimize_stats {
+                println!("  mesh {} optimized: {}", i, stats);
+            }
+            let lods: Vec<String> = mesh
+                .geometry
+                .lods
+                .iter()
+                .map(|lod| format!("{} ({:.4})", lod.num_indices / 3, lod.error))
+                .collect();
+            println!("  mesh {} LOD triangles: {}", i, lods.join(", "));
+        }
+        Ok(())
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.graphics.resize(new_size);
-        self.pbr_state
-            .resize(&self.graphics.device, &self.graphics.sc_desc);
+        if !self.graphics.is_minimized() {
+            self.pbr_state.resize(&self.graphics.sc_desc);
+        }
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
@@ -102,6 +296,29 @@ impl State {
                 WindowEvent::CursorMoved { position, .. } => {
                     self.clear_color.r = position.x as f64 / (self.size.width as f64);
                     self.clear_color.g = position.y as f64 / (self.size.height as f64);
+                    self.cursor_position =
+                        winit::dpi::PhysicalPosition::new(position.x as f64, position.y as f64);
+                    return true;
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } if self.is_pbr => {
+                    let ray = self.camera.ray(self.cursor_position, self.size);
+                    let pick = self.pbr_state.pick(&ray);
+                    match pick {
+                        Some(pick) => println!(
+                            "Picked instance {} mesh {} triangle {} at {:?} (barycentrics {:?})",
+                            pick.instance,
+                            pick.mesh,
+                            pick.triangle,
+                            ray.at(pick.distance),
+                            pick.barycentrics
+                        ),
+                        None => println!("Picked nothing"),
+                    }
+                    self.pbr_state.selected = pick.map(|pick| pick.instance);
                     return true;
                 }
                 WindowEvent::KeyboardInput { input, .. } => match input {
@@ -130,6 +347,122 @@ impl State {
                     } => {
                         self.is_pbr = !self.is_pbr;
                     }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::L),
+                        ..
+                    } => {
+                        self.pbr_state.lod_enabled = !self.pbr_state.lod_enabled;
+                        println!(
+                            "LODs {}",
+                            if self.pbr_state.lod_enabled {
+                                "on"
+                            } else {
+                                "off"
+                            }
+                        );
+                    }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::M),
+                        ..
+                    } => {
+                        // cycles through the counts the adapter supports
+                        let counts = self.graphics.sample_counts();
+                        let current = counts
+                            .iter()
+                            .position(|&count| count == self.graphics.sample_count)
+                            .unwrap_or(0);
+                        let sample_count = counts[(current + 1) % counts.len()];
+                        self.set_sample_count(sample_count);
+                        println!("MSAA {}x", sample_count);
+                    }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    } => {
+                        self.clock.toggle_pause();
+                        println!(
+                            "{}",
+                            if self.clock.is_paused() {
+                                "Paused, N steps"
+                            } else {
+                                "Resumed"
+                            }
+                        );
+                    }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::N),
+                        ..
+                    } if self.clock.is_paused() => self.clock.single_step(),
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        ..
+                    } => {
+                        if self.recording.is_some() {
+                            println!("Already recording every frame");
+                        } else {
+                            let millis = std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .map_or(0, |time| time.as_millis());
+                            self.capture_path = Some(format!("screenshot-{}.png", millis).into());
+                        }
+                    }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F10),
+                        ..
+                    } => self.toggle_recording(),
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F9),
+                        ..
+                    } => self.export_live(),
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Insert),
+                        ..
+                    } if self.is_pbr => self.spawn_instance(),
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::PageUp),
+                        ..
+                    } if self.is_pbr => self.move_selected(cgmath::Vector3::unit_y()),
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::PageDown),
+                        ..
+                    } if self.is_pbr => self.move_selected(-cgmath::Vector3::unit_y()),
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Delete),
+                        ..
+                    } if self.is_pbr => {
+                        if let Some(selected) = self.pbr_state.selected {
+                            self.pbr_state.remove_instance(selected);
+                            println!("Removed instance {}", selected);
+                        }
+                    }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::C),
+                        ..
+                    } => {
+                        self.pbr_state.culling_enabled = !self.pbr_state.culling_enabled;
+                        println!(
+                            "Frustum culling {}, {} of {} instances visible",
+                            if self.pbr_state.culling_enabled {
+                                "on"
+                            } else {
+                                "off"
+                            },
+                            self.pbr_state.visible_instances.0.len(),
+                            self.pbr_state.instances.len()
+                        );
+                    }
                     _ => {}
                 },
                 _ => {}
@@ -138,25 +471,172 @@ impl State {
         }
     }
 
+    fn set_sample_count(&mut self, sample_count: u32) {
+        let device = &self.graphics.device;
+        let sc_desc = &self.graphics.sc_desc;
+        self.simple.set_sample_count(device, sc_desc, sample_count);
+        self.pbr.set_sample_count(device, sc_desc, sample_count);
+        self.pbr_state.set_sample_count(sample_count);
+        self.graphics.set_sample_count(sample_count);
+    }
+
     fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
+        let tick = match &self.recording {
+            Some(recording) => self
+                .clock
+                .tick_by(std::time::Duration::from_secs(1) / recording.frame_rate),
+            None => self.clock.tick(),
+        };
+        self.advance(tick);
+    }
+
+    /// Exports the glTF model as it's shown, with instances moved, spawned
+    /// or removed and the current material factors.
+    fn export_live(&self) {
+        let (model, base_dir) = match &self.gltf_source {
+            Some(source) => source,
+            None => {
+                println!("Only glTF models can be exported");
+                return;
+            }
+        };
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let path = format!("export-{}.glb", millis);
+        let live = self.pbr_state.live_model(model);
+        match export::export_gltf(&live, base_dir, &path) {
+            Ok(()) => println!("Exported {}", path),
+            Err(err) => eprintln!("Failed to export {}: {:#}", path, err),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            println!(
+                "Recorded {} frames to {}",
+                recording.frame,
+                recording.dir.display()
+            );
+            return;
+        }
+        if let Err(err) = std::fs::create_dir_all(&self.record_dir) {
+            eprintln!("Failed to create {}: {}", self.record_dir.display(), err);
+            return;
+        }
+        println!(
+            "Recording to {} at {} fps",
+            self.record_dir.display(),
+            self.record_frame_rate
+        );
+        self.recording = Some(Recording {
+            dir: self.record_dir.clone(),
+            frame_rate: self.record_frame_rate,
+            frame: 0,
+        });
+    }
+
+    /// Adds an instance under the cursor, with the material info of the
+    /// selected one if there is one.
+    fn spawn_instance(&mut self) {
+        use cgmath::EuclideanSpace;
+
+        let ray = self.camera.ray(self.cursor_position, self.size);
+        let (group, material_info) =
+            self.pbr_state
+                .selected
+                .map_or((0, SPAWN_MATERIAL_INFO), |selected| {
+                    (
+                        self.pbr_state.instance_group(selected).unwrap_or(0),
+                        self.pbr_state.instances.material_infos[selected],
+                    )
+                });
+        let transform = TransformRaw {
+            model: cgmath::Matrix4::from_translation(ray.at(SPAWN_DISTANCE).to_vec()),
+        };
+        let index = self.pbr_state.add_instance(
+            &self.graphics.device,
+            &self.pbr,
+            group,
+            transform,
+            material_info,
+        );
+        println!("Spawned instance {}", index);
+    }
+
+    /// Moves the selected instance by `offset` in world space.
+    fn move_selected(&mut self, offset: cgmath::Vector3<f32>) {
+        let selected = match self.pbr_state.selected {
+            Some(selected) => selected,
+            None => return,
+        };
+        let transform = TransformRaw {
+            model: cgmath::Matrix4::from_translation(offset)
+                * self.pbr_state.instances.transforms[selected].model,
+        };
+        let material_info = self.pbr_state.instances.material_infos[selected];
+        self.pbr_state
+            .update_instance(selected, transform, material_info);
+    }
+
+    /// Simulates the steps of `tick` and prepares the frame drawn after them.
+    fn advance(&mut self, tick: Tick) {
+        let dt = self.clock.step_seconds();
+        for _ in 0..tick.steps {
+            self.previous_camera = self.camera.clone();
+            self.previous_model_angle = self.model_angle;
+            self.camera_controller.update_camera(&mut self.camera, dt);
+            if !self.is_pbr {
+                self.model_angle += self.model_speed * dt;
+            }
+        }
+
+        let camera = self.previous_camera.lerp(&self.camera, tick.alpha);
         if self.is_pbr {
-            self.pbr_state.mvp.update_view_proj(&self.camera);
+            self.pbr_state.mvp.update_view_proj(&camera);
+            self.pbr_state
+                .update_visible_instances(&camera, self.graphics.sc_desc.height);
+            if let Some(hovered) = self.pbr_state.poll_id_pick(&self.graphics.device) {
+                if hovered != self.hovered {
+                    match hovered {
+                        Some(pick) => println!(
+                            "Hovering instance {} mesh {} triangle {} of LOD {}",
+                            pick.instance, pick.mesh, pick.triangle, pick.level
+                        ),
+                        None => println!("Hovering nothing"),
+                    }
+                    self.hovered = hovered;
+                }
+            }
+            self.pbr_state
+                .request_id_pick(self.cursor_position, &self.graphics.sc_desc);
         } else {
-            self.model_angle += self.model_speed;
-            self.simple_state.update_uniforms(
-                &self.graphics.device,
-                &self.graphics.queue,
-                &self.camera,
-                self.model_angle,
-            );
+            let model_angle = self.previous_model_angle
+                + (self.model_angle - self.previous_model_angle) * tick.alpha;
+            self.simple_state.update_uniforms(&camera, model_angle);
         }
     }
 
-    fn render(&mut self) {
+    fn render(&mut self) -> anyhow::Result<()> {
+        if let (Some(recording), None) = (&self.recording, &self.capture_path) {
+            let path = recording
+                .dir
+                .join(format!("frame-{:05}.png", recording.frame));
+            self.capture_path = Some(path);
+        }
+        if self.capture_path.is_some() {
+            self.graphics.request_capture();
+        }
+
         if self.is_pbr {
-            self.graphics
-                .render(&self.pbr.pipeline, &PbrRenderPass::new(&mut self.pbr_state));
+            let frame = {
+                let mut graph = RenderGraph::new();
+                PbrRenderPass::new(&mut self.pbr_state, &self.pbr).add_to(&mut graph);
+                self.graphics.execute(&graph)?
+            };
+            if frame == Frame::Presented {
+                self.pbr_state.begin_id_readback();
+            }
         } else {
             self.graphics.render(
                 &self.simple.pipeline,
@@ -164,25 +644,316 @@ impl State {
                     clear_color: self.clear_color,
                     state: &self.simple_state,
                 },
+            )?;
+        }
+
+        // nothing to take if the frame was skipped, it's captured next time
+        if let Some(image) = self.graphics.take_capture() {
+            let path = self
+                .capture_path
+                .take()
+                .expect("captures are requested with a path");
+            let saved = image.and_then(|image| {
+                image
+                    .save(&path)
+                    .with_context(|| format!("Failed to save {}", path.display()))
+            });
+            match (saved, &mut self.recording) {
+                (Ok(()), Some(recording)) => recording.frame += 1,
+                (Ok(()), None) => println!("Saved {}", path.display()),
+                (Err(err), _) => {
+                    eprintln!("Capture failed: {:#}", err);
+                    self.recording = None;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How far in front of the camera Insert spawns an instance.
+const SPAWN_DISTANCE: f32 = 10.0;
+const SPAWN_MATERIAL_INFO: MaterialInfoRaw = MaterialInfoRaw {
+    info: cgmath::Vector4::new(0.5, 0.5, 1.0, 0.0),
+};
+
+/// Size of the golden images, changing it invalidates every reference.
+const GOLDEN_SIZE: (u32, u32) = (320, 240);
+
+/// Exit code of `--golden` when there is no adapter to render with, so
+/// callers can skip instead of failing.
+const EXIT_NO_ADAPTER: i32 = 77;
+
+/// Renders the golden scenes offscreen and compares them to the references
+/// in `dir`, returns whether they all matched. The references are recorded
+/// on a software Vulkan adapter (e.g. lavapipe through `VK_ICD_FILENAMES`
+/// with `--backend vulkan`), hardware adapters may differ by more than the
+/// tolerance.
+fn run_golden(
+    dir: &str,
+    update: bool,
+    gltf_paths: &[String],
+    config: &GraphicsConfig,
+) -> anyhow::Result<bool> {
+    let mut scenes = vec![
+        ("simple-textured".to_string(), None, false),
+        ("pbr-spheres".to_string(), None, true),
+    ];
+    for path in gltf_paths {
+        let stem = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("model");
+        let asset = load_asset(path, None, UploadOptions::default())
+            .with_context(|| format!("Failed to load {}", path))?;
+        scenes.push((format!("gltf-{}", stem), Some(asset), true));
+    }
+
+    let tolerance = golden::Tolerance::default();
+    let mut passed = true;
+    for (name, asset, is_pbr) in &scenes {
+        let graphics = block_on(Graphics::headless(GOLDEN_SIZE.0, GOLDEN_SIZE.1, config))?;
+        let info = graphics.adapter.get_info();
+        if info.device_type != wgpu::DeviceType::Cpu {
+            eprintln!(
+                "{}: rendering on {} ({:?}), references come from a software adapter",
+                name, info.name, info.device_type
             );
         }
+        let mut state = State::new(graphics, None);
+        if let Some(asset) = asset {
+            state
+                .set_asset(asset)
+                .with_context(|| format!("{}: the scene didn't upload", name))?;
+        }
+        state.is_pbr = *is_pbr;
+        let step = state.clock.step;
+        let tick = state.clock.advance(step);
+        state.advance(tick);
+        state.render()?;
+        let image = state.graphics.read_image()?;
+        match golden::check(name, &image, dir.as_ref(), update, &tolerance)? {
+            golden::Outcome::Passed { mismatched } => {
+                println!("{}: ok ({} pixels differ)", name, mismatched)
+            }
+            golden::Outcome::Failed { mismatched } => {
+                println!("{}: FAILED, {} pixels differ", name, mismatched);
+                passed = false;
+            }
+            golden::Outcome::Recorded => println!("{}: recorded", name),
+        }
+    }
+    Ok(passed)
+}
+
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let mut parts = size.split('x').map(str::parse);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => {
+            Some((width, height))
+        }
+        _ => None,
     }
 }
 
+/// Draws a single frame offscreen and saves it.
+fn render_headless(
+    asset: Option<&Asset>,
+    out_path: &str,
+    (width, height): (u32, u32),
+    sample_count: u32,
+    config: &GraphicsConfig,
+) -> anyhow::Result<()> {
+    let graphics = block_on(Graphics::headless(width, height, config))?;
+    graphics.check_sample_count(sample_count)?;
+    let mut state = State::new(graphics, asset);
+    state.set_sample_count(sample_count);
+    // a single fixed step, so the frame doesn't depend on timing
+    let step = state.clock.step;
+    let tick = state.clock.advance(step);
+    state.advance(tick);
+    state.render()?;
+    let image = state.graphics.read_image()?;
+    image
+        .save(out_path)
+        .with_context(|| format!("Failed to save {}", out_path))?;
+    println!("Saved {}x{} frame to {}", width, height, out_path);
+    Ok(())
+}
+
 fn main() {
-    // let _ = model::load_gltf("res/skin.gltf");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // skinning [--backend primary|secondary|vulkan|metal|dx12|dx11|gl]
+    //     [--power default|low|high] [--present-mode fifo|mailbox|immediate]
+    //     [--format bgra8-srgb|bgra8|rgba8-srgb|rgba8]
+    let config = match GraphicsConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // skinning --golden <reference dir> [--update] [file.gltf|file.glb]...
+    if args.first().map(String::as_str) == Some("--golden") {
+        let dir = match args.get(1) {
+            Some(dir) => dir,
+            None => {
+                eprintln!(
+                    "usage: skinning --golden <reference dir> [--update] [file.gltf|file.glb]..."
+                );
+                std::process::exit(1);
+            }
+        };
+        let update = args.iter().any(|arg| arg == "--update");
+        let is_option = |arg: &String| GraphicsConfig::OPTIONS.iter().any(|&(flag, _)| flag == arg);
+        let gltf_paths: Vec<String> = args[2..]
+            .iter()
+            .zip(&args[1..])
+            .filter(|(arg, previous)| !arg.starts_with("--") && !is_option(previous))
+            .map(|(arg, _)| arg.clone())
+            .collect();
+        match run_golden(dir, update, &gltf_paths, &config) {
+            Ok(passed) => std::process::exit(!passed as i32),
+            Err(err) if err.is::<NoAdapter>() => {
+                eprintln!("Golden images skipped: {:#}", err);
+                std::process::exit(EXIT_NO_ADAPTER);
+            }
+            Err(err) => {
+                eprintln!("Golden images failed: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let export_path = args
+        .iter()
+        .position(|arg| arg == "--export")
+        .and_then(|i| args.get(i + 1));
+    // skinning <asset> --no-optimize
+    let upload = UploadOptions {
+        optimize: !args.iter().any(|arg| arg == "--no-optimize"),
+    };
+    // skinning --primitive cube|plane|uv-sphere|ico-sphere|cylinder|cone|torus|capsule
+    let primitive = args
+        .iter()
+        .position(|arg| arg == "--primitive")
+        .map(|i| args.get(i + 1).map(String::as_str).unwrap_or_default());
+    // skinning --terrain <heightmap>
+    let terrain = args
+        .iter()
+        .position(|arg| arg == "--terrain")
+        .map(|i| args.get(i + 1).map(String::as_str).unwrap_or_default());
+    let asset = match (primitive, terrain) {
+        (Some(name), _) => match primitive_mesh(name) {
+            Ok(mesh) => Some(Asset {
+                source: AssetSource::Primitive(mesh),
+                upload,
+            }),
+            Err(err) => {
+                eprintln!("Failed to build primitive: {:#}", err);
+                None
+            }
+        },
+        (None, Some(path)) => match terrain::Heightmap::load(path).and_then(|heightmap| {
+            terrain::build_chunks(&heightmap, &terrain::TerrainConfig::default())
+        }) {
+            Ok(chunks) => {
+                println!("Terrain {}: {} chunks", path, chunks.len());
+                Some(Asset {
+                    source: AssetSource::Terrain(chunks),
+                    upload,
+                })
+            }
+            Err(err) => {
+                eprintln!("Failed to build terrain: {:#}", err);
+                None
+            }
+        },
+        (None, None) => args
+            .first()
+            .filter(|arg| !arg.starts_with("--"))
+            .and_then(|path| load_asset(path, export_path, upload)),
+    };
+
+    // skinning --msaa 1|2|4|8
+    let sample_count = match args.iter().position(|arg| arg == "--msaa") {
+        Some(i) => match args.get(i + 1).and_then(|count| count.parse().ok()) {
+            Some(count) if SAMPLE_COUNTS.contains(&count) => count,
+            _ => {
+                eprintln!("--msaa takes one of {:?}", SAMPLE_COUNTS);
+                std::process::exit(1);
+            }
+        },
+        None => 1,
+    };
+
+    // skinning [asset] --headless <out.png> [WIDTHxHEIGHT]
+    if let Some(i) = args.iter().position(|arg| arg == "--headless") {
+        let out_path = match args.get(i + 1) {
+            Some(out_path) => out_path,
+            None => {
+                eprintln!("usage: skinning [asset] --headless <out.png> [WIDTHxHEIGHT]");
+                std::process::exit(1);
+            }
+        };
+        let size = args.get(i + 2).and_then(|size| parse_size(size));
+        let size = size.unwrap_or((800, 600));
+        if let Err(err) = render_headless(asset.as_ref(), out_path, size, sample_count, &config) {
+            eprintln!("Headless render failed: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .build(&event_loop)
         .expect("Failed to build window");
 
-    let mut state = block_on(State::new(&window));
+    let graphics = match block_on(Graphics::new(&window, &config)) {
+        Ok(graphics) => graphics,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = graphics.check_sample_count(sample_count) {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+    let mut state = State::new(graphics, asset.as_ref());
+    if sample_count > 1 {
+        state.set_sample_count(sample_count);
+    }
+    // skinning --record <dir> [--record-fps <n>], F10 toggles it too
+    if let Some(fps) = args
+        .iter()
+        .position(|arg| arg == "--record-fps")
+        .and_then(|i| args.get(i + 1))
+    {
+        match fps.parse() {
+            Ok(fps) if fps > 0 => state.record_frame_rate = fps,
+            _ => eprintln!("Invalid --record-fps '{}'", fps),
+        }
+    }
+    if let Some(dir) = args
+        .iter()
+        .position(|arg| arg == "--record")
+        .and_then(|i| args.get(i + 1))
+    {
+        state.record_dir = dir.into();
+        state.toggle_recording();
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::RedrawRequested(_) => {
             state.update();
-            state.render();
+            if let Err(err) = state.render() {
+                eprintln!("Rendering failed: {:#}", err);
+                *control_flow = ControlFlow::Exit;
+            }
         }
         Event::MainEventsCleared => {
             window.request_redraw();
@@ -1,18 +1,613 @@
-use anyhow::{anyhow, Result};
-use std::path::Path;
+use anyhow::Result;
+use serde_json::Value;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
-use gltf::{buffer::Buffer, buffer::Source, scene::Node};
+use crate::{
+    bounds::{Aabb, Bounds},
+    render_types::VertexTexNormalTangent,
+    tangents,
+};
 
-fn visit_node(node: &Node) {
-    let name = node.name().unwrap_or("--");
-    println!("Node {} with index {}", name, node.index());
+use gltf::{
+    accessor::{Accessor, DataType, Dimensions},
+    animation::{Interpolation, Property},
+    buffer::{Source, View},
+    image,
+    mesh::{Mode, Semantic},
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
 
-    for node in node.children() {
-        println!("With children");
-        visit_node(&node);
+/// One step of the path from the document root to a glTF object.
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Buffer(usize),
+    Node(usize, Option<String>),
+    Mesh(usize, Option<String>),
+    Primitive(usize),
+    Attribute(String),
+    Indices,
+    Accessor(usize),
+    Skin(usize),
+    Animation(usize),
+    Channel(usize),
+    Material(usize),
+    Texture(String),
+    Image(usize),
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let named = |f: &mut fmt::Formatter, kind, index, name: &Option<String>| match name {
+            Some(name) => write!(f, "{}[{} \"{}\"]", kind, index, name),
+            None => write!(f, "{}[{}]", kind, index),
+        };
+        match self {
+            Segment::Buffer(index) => write!(f, "buffers[{}]", index),
+            Segment::Node(index, name) => named(f, "nodes", index, name),
+            Segment::Mesh(index, name) => named(f, "meshes", index, name),
+            Segment::Primitive(index) => write!(f, "primitives[{}]", index),
+            Segment::Attribute(semantic) => write!(f, "attributes[{}]", semantic),
+            Segment::Indices => write!(f, "indices"),
+            Segment::Accessor(index) => write!(f, "accessors[{}]", index),
+            Segment::Skin(index) => write!(f, "skins[{}]", index),
+            Segment::Animation(index) => write!(f, "animations[{}]", index),
+            Segment::Channel(index) => write!(f, "channels[{}]", index),
+            Segment::Material(index) => write!(f, "materials[{}]", index),
+            Segment::Texture(slot) => write!(f, "{}", slot),
+            Segment::Image(index) => write!(f, "images[{}]", index),
+        }
+    }
+}
+
+/// Location of the object that failed to load, e.g.
+/// `nodes[2 "Body"]/meshes[0]/primitives[1]/attributes[JOINTS_0]/accessors[7]`.
+#[derive(Debug, Clone, Default)]
+pub struct GltfPath(Vec<Segment>);
+
+impl GltfPath {
+    fn join(&self, segment: Segment) -> Self {
+        let mut path = self.clone();
+        path.0.push(segment);
+        path
+    }
+}
+
+impl fmt::Display for GltfPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum GltfError {
+    UnsupportedBufferSource {
+        path: GltfPath,
+    },
+    Base64 {
+        path: GltfPath,
+        source: base64::DecodeError,
+    },
+    Io {
+        path: GltfPath,
+        file: PathBuf,
+        source: std::io::Error,
+    },
+    BufferTooShort {
+        path: GltfPath,
+        expected: usize,
+        actual: usize,
+    },
+    MissingBufferView {
+        path: GltfPath,
+    },
+    EmptyAccessor {
+        path: GltfPath,
+    },
+    AccessorOutOfBounds {
+        path: GltfPath,
+        end: usize,
+        len: usize,
+    },
+    UnsupportedFormat {
+        path: GltfPath,
+        data_type: DataType,
+        dimensions: Dimensions,
+    },
+    MissingAttribute {
+        path: GltfPath,
+        semantic: String,
+    },
+    UnsupportedMode {
+        path: GltfPath,
+        mode: Mode,
+    },
+    CountMismatch {
+        path: GltfPath,
+        count: usize,
+        expected: usize,
+    },
+    IndexOutOfRange {
+        path: GltfPath,
+        index: u32,
+        vertex_count: usize,
+    },
+    WeightsNotNormalized {
+        path: GltfPath,
+        vertex: usize,
+        sum: f32,
+    },
+    JointOutOfRange {
+        path: GltfPath,
+        vertex: usize,
+        joint: u16,
+        joint_count: usize,
+    },
+    TextureOutOfRange {
+        path: GltfPath,
+        texture: usize,
+        texture_count: usize,
+    },
+    MissingTextureIndex {
+        path: GltfPath,
+    },
+    MissingAccessor {
+        path: GltfPath,
+        accessor: usize,
+    },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::UnsupportedBufferSource { path } => {
+                write!(f, "{}: unsupported buffer source", path)
+            }
+            GltfError::Base64 { path, source } => {
+                write!(f, "{}: base64 decode error: {}", path, source)
+            }
+            GltfError::Io { path, file, source } => {
+                write!(f, "{}: could not read {}: {}", path, file.display(), source)
+            }
+            GltfError::BufferTooShort {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: buffer holds {} bytes, expected {}",
+                path, actual, expected
+            ),
+            GltfError::MissingBufferView { path } => {
+                write!(f, "{}: accessor has no buffer view", path)
+            }
+            GltfError::EmptyAccessor { path } => write!(f, "{}: accessor is empty", path),
+            GltfError::AccessorOutOfBounds { path, end, len } => write!(
+                f,
+                "{}: accessor reads up to byte {} of a {} byte range",
+                path, end, len
+            ),
+            GltfError::UnsupportedFormat {
+                path,
+                data_type,
+                dimensions,
+            } => write!(
+                f,
+                "{}: unsupported accessor format {:?} {:?}",
+                path, data_type, dimensions
+            ),
+            GltfError::MissingAttribute { path, semantic } => {
+                write!(f, "{}: missing required attribute {}", path, semantic)
+            }
+            GltfError::UnsupportedMode { path, mode } => {
+                write!(f, "{}: {:?} primitives aren't supported", path, mode)
+            }
+            GltfError::CountMismatch {
+                path,
+                count,
+                expected,
+            } => write!(f, "{}: has {} elements, expected {}", path, count, expected),
+            GltfError::IndexOutOfRange {
+                path,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "{}: index {} out of range for {} vertices",
+                path, index, vertex_count
+            ),
+            GltfError::WeightsNotNormalized { path, vertex, sum } => write!(
+                f,
+                "{}: weights of vertex {} sum to {} instead of 1",
+                path, vertex, sum
+            ),
+            GltfError::JointOutOfRange {
+                path,
+                vertex,
+                joint,
+                joint_count,
+            } => write!(
+                f,
+                "{}: vertex {} references joint {} of a skin with {} joints",
+                path, vertex, joint, joint_count
+            ),
+            GltfError::TextureOutOfRange {
+                path,
+                texture,
+                texture_count,
+            } => write!(
+                f,
+                "{}: texture {} out of range for {} textures",
+                path, texture, texture_count
+            ),
+            GltfError::MissingTextureIndex { path } => {
+                write!(f, "{}: texture reference has no index", path)
+            }
+            GltfError::MissingAccessor { path, accessor } => {
+                write!(f, "{}: accessor {} does not exist", path, accessor)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Base64 { source, .. } => Some(source),
+            GltfError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+type GltfResult<T> = std::result::Result<T, GltfError>;
+
+// tolerance for the sum of skinning weights, quantized weights are rarely exact
+const WEIGHT_SUM_EPSILON: f32 = 0.01;
+
+/// A triangle list, strips and fans are converted when they're loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Primitive {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub tex_coords: Option<Vec<[f32; 2]>>,
+    /// `TANGENT`, or MikkTSpace tangents when the primitive has normals and
+    /// uvs but no tangents
+    pub tangents: Option<Vec<[f32; 4]>>,
+    pub joints: Option<Vec<[u16; 4]>>,
+    pub weights: Option<Vec<[f32; 4]>>,
+    pub indices: Option<Vec<u32>>,
+    pub material: Option<usize>,
+    /// Bind pose bounds of `positions`.
+    pub bounds: Bounds,
+}
+
+/// Triangle list corners of a strip or fan, with the winding of the first
+/// triangle kept for every triangle.
+fn triangle_list(mode: Mode, corners: &[u32]) -> Vec<u32> {
+    let triangles = corners.len().saturating_sub(2);
+    (0..triangles)
+        .flat_map(|i| match mode {
+            Mode::TriangleFan => [corners[i + 1], corners[i + 2], corners[0]],
+            _ if i % 2 == 0 => [corners[i], corners[i + 1], corners[i + 2]],
+            _ => [corners[i + 1], corners[i], corners[i + 2]],
+        })
+        .collect()
+}
+
+impl Primitive {
+    /// Interleaved vertices and the triangle list indexing them. Primitives
+    /// without normals are flat shaded, and ones without tangents get
+    /// MikkTSpace tangents, from zeroed uvs if they have none.
+    pub fn vertices(&self) -> (Vec<VertexTexNormalTangent>, Vec<u32>) {
+        let mut positions = self.positions.clone();
+        let mut tex_coords = self
+            .tex_coords
+            .clone()
+            .unwrap_or_else(|| vec![[0.0; 2]; positions.len()]);
+        let mut indices = self
+            .indices
+            .clone()
+            .unwrap_or_else(|| (0..positions.len() as u32).collect());
+        let mut normals = match &self.normals {
+            Some(normals) => normals.clone(),
+            None => {
+                // every corner gets its own vertex with the face normal
+                positions = indices.iter().map(|&i| positions[i as usize]).collect();
+                tex_coords = indices.iter().map(|&i| tex_coords[i as usize]).collect();
+                indices = (0..positions.len() as u32).collect();
+                positions
+                    .chunks_exact(3)
+                    .flat_map(|triangle| {
+                        use cgmath::InnerSpace;
+                        let a = cgmath::Vector3::from(triangle[0]);
+                        let b = cgmath::Vector3::from(triangle[1]);
+                        let c = cgmath::Vector3::from(triangle[2]);
+                        let normal = (b - a).cross(c - a);
+                        let normal = if normal.magnitude2() > 0.0 {
+                            normal.normalize()
+                        } else {
+                            cgmath::Vector3::unit_z()
+                        };
+                        let normal: [f32; 3] = normal.into();
+                        vec![normal; 3]
+                    })
+                    .collect()
+            }
+        };
+        let tangents = match (&self.tangents, &self.normals) {
+            (Some(tangents), Some(_)) => tangents.clone(),
+            _ => {
+                let generated =
+                    tangents::generate_tangents(&positions, &normals, &tex_coords, &indices);
+                positions = generated.remap(&positions);
+                normals = generated.remap(&normals);
+                tex_coords = generated.remap(&tex_coords);
+                indices = generated.indices;
+                generated.tangents
+            }
+        };
+        let vertices = positions
+            .iter()
+            .zip(&tex_coords)
+            .zip(&normals)
+            .zip(&tangents)
+            .map(
+                |(((&position, &tex_coord), &normal), &tangent)| VertexTexNormalTangent {
+                    position,
+                    tex_coord,
+                    normal,
+                    tangent,
+                },
+            )
+            .collect();
+        (vertices, indices)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub name: Option<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    pub skeleton: Option<usize>,
+    pub inverse_bind_matrices: Vec<[[f32; 4]; 4]>,
+    /// Box around the vertices each joint influences, in the joint's space.
+    /// `None` for joints that don't move any vertex.
+    pub joint_bounds: Vec<Option<Aabb>>,
+}
+
+impl Skin {
+    /// Conservative bounds of the skinned meshes for the given world
+    /// transforms of `joints`: a skinned vertex is a weighted average of its
+    /// joints' transforms, so it stays inside the union of their boxes.
+    pub fn bounds(&self, joint_matrices: &[cgmath::Matrix4<f32>]) -> Option<Bounds> {
+        self.joint_bounds
+            .iter()
+            .zip(joint_matrices)
+            .filter_map(|(aabb, matrix)| aabb.map(|aabb| aabb.transform(matrix)))
+            .fold(None, |union: Option<Aabb>, aabb| {
+                Some(union.map_or(aabb, |union| union.union(&aabb)))
+            })
+            .map(Bounds::from_aabb)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelOutputs {
+    Translations(Vec<[f32; 3]>),
+    Rotations(Vec<[f32; 4]>),
+    Scales(Vec<[f32; 3]>),
+    MorphTargetWeights(Vec<f32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    pub inputs: Vec<f32>,
+    pub outputs: ChannelOutputs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+/// `KHR_texture_transform`, applied to the UVs as translation * rotation * scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTransform {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        TextureTransform {
+            offset: [0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+impl TextureTransform {
+    /// The two rows of the 3x3 UV matrix, the third one is always (0, 0, 1).
+    pub fn rows(&self) -> [[f32; 3]; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [sx, sy] = self.scale;
+        let [ox, oy] = self.offset;
+        [[cos * sx, sin * sy, ox], [-sin * sx, cos * sy, oy]]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureRef {
+    pub texture: usize,
+    pub tex_coord: u32,
+    pub transform: TextureTransform,
+}
+
+/// `KHR_materials_clearcoat`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clearcoat {
+    pub factor: f32,
+    pub texture: Option<TextureRef>,
+    pub roughness_factor: f32,
+    pub roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub occlusion_texture: Option<TextureRef>,
+    pub emissive_factor: [f32; 3],
+    /// `KHR_materials_emissive_strength`
+    pub emissive_strength: f32,
+    pub emissive_texture: Option<TextureRef>,
+    pub clearcoat: Option<Clearcoat>,
+    /// `KHR_materials_unlit`
+    pub unlit: bool,
+}
+
+/// The glTF default material, for primitives that don't name one.
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: None,
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_factor: [0.0; 3],
+            emissive_strength: 1.0,
+            emissive_texture: None,
+            clearcoat: None,
+            unlit: false,
+        }
+    }
+}
+
+/// `EXT_mesh_gpu_instancing`, the world transform of every instance of the
+/// node's mesh, column major. The attributes are kept as read, relative to
+/// the node, for the exporter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshInstances {
+    pub node: usize,
+    pub mesh: usize,
+    pub transforms: Vec<[[f32; 4]; 4]>,
+    pub translations: Option<Vec<[f32; 3]>>,
+    pub rotations: Option<Vec<[f32; 4]>>,
+    pub scales: Option<Vec<[f32; 3]>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: Option<String>,
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+    pub children: Vec<usize>,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageSource {
+    Uri(String),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+    pub source: ImageSource,
+}
+
+impl Image {
+    /// The encoded image. URIs other than data URIs are files relative to
+    /// `base`, the directory of the document.
+    pub fn bytes(&self, base: &Path) -> Result<std::borrow::Cow<'_, [u8]>> {
+        use anyhow::Context;
+        use std::borrow::Cow;
+
+        match &self.source {
+            ImageSource::Bytes(bytes) => Ok(Cow::Borrowed(bytes)),
+            ImageSource::Uri(uri) if uri.starts_with("data:") => {
+                let (_, data) = uri
+                    .split_once(";base64,")
+                    .ok_or_else(|| anyhow::anyhow!("Image data URI isn't base64"))?;
+                Ok(Cow::Owned(base64::decode(data)?))
+            }
+            ImageSource::Uri(uri) => {
+                let file = base.join(uri);
+                let bytes = std::fs::read(&file)
+                    .with_context(|| format!("Could not read image {}", file.display()))?;
+                Ok(Cow::Owned(bytes))
+            }
+        }
     }
 }
 
+/// Filters are left unset when the document leaves them to the renderer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampler {
+    pub name: Option<String>,
+    pub mag_filter: Option<MagFilter>,
+    pub min_filter: Option<MinFilter>,
+    pub wrap_s: WrappingMode,
+    pub wrap_t: WrappingMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    pub name: Option<String>,
+    pub image: usize,
+    /// `None` for the default sampler, repeating with unset filters.
+    pub sampler: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub nodes: Vec<Node>,
+    pub images: Vec<Image>,
+    pub samplers: Vec<Sampler>,
+    pub textures: Vec<Texture>,
+    pub materials: Vec<Material>,
+    pub meshes: Vec<Mesh>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<Animation>,
+    pub scenes: Vec<Vec<JointNode>>,
+    /// The scene shown when loading, `None` if the document doesn't say.
+    pub scene: Option<usize>,
+    pub instances: Vec<MeshInstances>,
+}
+
 const DATA_BASE64: &str = "data:application/gltf-buffer;base64,";
 
 struct GltfBuffers(Vec<u8>, Vec<(usize, usize)>); // buffer, (start, end)
@@ -29,77 +624,642 @@ fn sum_buffer_sizes(gltf: &gltf::Gltf) -> usize {
     gltf.buffers().map(|buffer| buffer.length()).sum()
 }
 
-fn collect_buffers(gltf: &gltf::Gltf) -> GltfBuffers {
-    let buffers = gltf.buffers();
+/// Buffers may be embedded as data URIs, live in the GLB binary chunk or in
+/// files next to the document.
+fn collect_buffers(gltf: &gltf::Gltf, base: &Path) -> GltfResult<GltfBuffers> {
+    let mut buffers = gltf.buffers();
     let buffer_cap = sum_buffer_sizes(gltf);
     let buffer_count = buffers.len();
-    let (buffer, slices) = buffers
-        .filter_map(|buffer| match buffer.source() {
-            Source::Uri(data) if data.starts_with(DATA_BASE64) => Some(&data[DATA_BASE64.len()..]),
-            _ => unimplemented!(),
-        })
-        .fold(
-            (
-                Vec::with_capacity(buffer_cap),
-                Vec::with_capacity(buffer_count),
-            ),
-            |(mut v, mut slices), data| {
-                let start = v.len();
-                base64::decode_config_buf(data, base64::STANDARD, &mut v)
-                    .expect("base 64 decode error");
-                slices.push((start, v.len()));
-                (v, slices)
-            },
-        );
-    GltfBuffers(buffer, slices)
-}
-
-fn load_geometry(mesh: &gltf::Mesh, buffers: &GltfBuffers) -> Result<()> {
-    for (prim_index, primitive) in mesh.primitives().enumerate() {
-        let reader = primitive.reader(|index| buffers.buffer(&index));
-        if let Some(indices) = reader.read_indices() {
-            // we don't really need this, just to visualize
-            let indices = indices.into_u32();
-            let len = indices.len();
-            let (_, indices) =
-                indices.fold((0, Vec::with_capacity(len / 3)), |(mut i, mut v), index| {
-                    if i == 0 {
-                        v.push([0, 0, 0]);
-                    }
-                    v.last_mut().expect("at least one element")[i] = index;
-                    i += 1;
-                    i %= 3;
-                    (i, v)
+    let (buffer, slices) = buffers.try_fold(
+        (
+            Vec::with_capacity(buffer_cap),
+            Vec::with_capacity(buffer_count),
+        ),
+        |(mut v, mut slices), buffer| {
+            let path = GltfPath::default().join(Segment::Buffer(buffer.index()));
+            let start = v.len();
+            match buffer.source() {
+                Source::Uri(data) if data.starts_with(DATA_BASE64) => {
+                    let data = &data[DATA_BASE64.len()..];
+                    base64::decode_config_buf(data, base64::STANDARD, &mut v).map_err(
+                        |source| GltfError::Base64 {
+                            path: path.clone(),
+                            source,
+                        },
+                    )?;
+                }
+                Source::Uri(uri) if !uri.contains(':') => {
+                    let file = base.join(uri);
+                    let bytes = std::fs::read(&file).map_err(|source| GltfError::Io {
+                        path: path.clone(),
+                        file,
+                        source,
+                    })?;
+                    v.extend_from_slice(&bytes);
+                }
+                Source::Bin => match &gltf.blob {
+                    Some(blob) => v.extend_from_slice(blob),
+                    None => return Err(GltfError::UnsupportedBufferSource { path }),
+                },
+                Source::Uri(_) => return Err(GltfError::UnsupportedBufferSource { path }),
+            }
+            if v.len() - start < buffer.length() {
+                return Err(GltfError::BufferTooShort {
+                    path,
+                    expected: buffer.length(),
+                    actual: v.len() - start,
                 });
-            for (i, index) in indices.iter().enumerate() {
-                println!("index {}: {:?}", i, index);
             }
+            slices.push((start, v.len()));
+            Ok((v, slices))
+        },
+    )?;
+    Ok(GltfBuffers(buffer, slices))
+}
+
+fn check_view_range(
+    view: &View,
+    buffers: &GltfBuffers,
+    offset: usize,
+    stride: usize,
+    count: usize,
+    element_size: usize,
+    path: &GltfPath,
+) -> GltfResult<()> {
+    let len = buffers.buffer(&view.buffer()).map_or(0, |data| data.len());
+    let view_end = view.offset() + view.length();
+    if view_end > len {
+        return Err(GltfError::AccessorOutOfBounds {
+            path: path.join(Segment::Buffer(view.buffer().index())),
+            end: view_end,
+            len,
+        });
+    }
+    let end = offset + stride * (count - 1) + element_size;
+    if end > view.length() {
+        return Err(GltfError::AccessorOutOfBounds {
+            path: path.clone(),
+            end,
+            len: view.length(),
+        });
+    }
+    Ok(())
+}
+
+/// Makes sure every byte the accessor iterators will touch is inside its buffer.
+fn check_accessor(accessor: &Accessor, buffers: &GltfBuffers, path: &GltfPath) -> GltfResult<()> {
+    let path = path.join(Segment::Accessor(accessor.index()));
+    let count = accessor.count();
+    if count == 0 {
+        return Err(GltfError::EmptyAccessor { path });
+    }
+    let size = accessor.size();
+    match (accessor.view(), accessor.sparse()) {
+        (Some(view), _) => {
+            let stride = view.stride().unwrap_or(size);
+            check_view_range(
+                &view,
+                buffers,
+                accessor.offset(),
+                stride,
+                count,
+                size,
+                &path,
+            )?;
+        }
+        (None, None) => return Err(GltfError::MissingBufferView { path }),
+        (None, Some(_)) => {}
+    }
+    if let Some(sparse) = accessor.sparse() {
+        let sparse_count = sparse.count() as usize;
+        if sparse_count == 0 {
+            return Err(GltfError::EmptyAccessor { path });
         }
-        if let Some(positions) = reader.read_positions() {
-            for (i, pos) in positions.enumerate() {
-                println!("prim {}, pos {:?}", i, pos);
+        let indices = sparse.indices();
+        let index_size = indices.index_type().size();
+        let view = indices.view();
+        let stride = view.stride().unwrap_or(index_size);
+        let offset = indices.offset() as usize;
+        check_view_range(
+            &view,
+            buffers,
+            offset,
+            stride,
+            sparse_count,
+            index_size,
+            &path,
+        )?;
+
+        let values = sparse.values();
+        let view = values.view();
+        let stride = view.stride().unwrap_or(size);
+        let offset = values.offset() as usize;
+        check_view_range(&view, buffers, offset, stride, sparse_count, size, &path)?;
+    }
+    Ok(())
+}
+
+fn check_format(
+    accessor: &Accessor,
+    allowed: &[(DataType, Dimensions)],
+    path: &GltfPath,
+) -> GltfResult<()> {
+    let format = (accessor.data_type(), accessor.dimensions());
+    if allowed.contains(&format) {
+        Ok(())
+    } else {
+        Err(GltfError::UnsupportedFormat {
+            path: path.join(Segment::Accessor(accessor.index())),
+            data_type: format.0,
+            dimensions: format.1,
+        })
+    }
+}
+
+fn check_count(accessor: &Accessor, expected: usize, path: &GltfPath) -> GltfResult<()> {
+    if accessor.count() != expected {
+        Err(GltfError::CountMismatch {
+            path: path.join(Segment::Accessor(accessor.index())),
+            count: accessor.count(),
+            expected,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn view_data<'b>(view: &View, buffers: &'b GltfBuffers) -> &'b [u8] {
+    let data = buffers.buffer(&view.buffer()).unwrap_or(&[]);
+    &data[view.offset()..view.offset() + view.length()]
+}
+
+/// Converts a single component to `f32`, normalized integers are mapped the
+/// way `KHR_mesh_quantization` and the core spec describe.
+fn dequantize(bytes: &[u8], data_type: DataType, normalized: bool) -> f32 {
+    match data_type {
+        DataType::I8 if normalized => (bytes[0] as i8 as f32 / 127.0).max(-1.0),
+        DataType::I8 => bytes[0] as i8 as f32,
+        DataType::U8 if normalized => bytes[0] as f32 / 255.0,
+        DataType::U8 => bytes[0] as f32,
+        DataType::I16 if normalized => {
+            (i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32767.0).max(-1.0)
+        }
+        DataType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+        DataType::U16 if normalized => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 65535.0,
+        DataType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+        DataType::U32 => read_integer(bytes, data_type) as f32,
+        DataType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+}
+
+fn read_integer(bytes: &[u8], data_type: DataType) -> u32 {
+    match data_type {
+        DataType::I8 | DataType::U8 => bytes[0] as u32,
+        DataType::I16 | DataType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+        _ => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+}
+
+/// Reads all components of an accessor into a flat vector, applying the
+/// sparse substitutions on top of the base values (or zeros when the accessor
+/// has no buffer view). Expects `check_accessor` to have passed.
+fn read_components<T: Copy + Default>(
+    accessor: &Accessor,
+    buffers: &GltfBuffers,
+    path: &GltfPath,
+    convert: impl Fn(&[u8], DataType) -> T,
+) -> GltfResult<Vec<T>> {
+    let data_type = accessor.data_type();
+    let components = accessor.dimensions().multiplicity();
+    let component_size = data_type.size();
+    let count = accessor.count();
+
+    let read_element = |data: &[u8], element: &mut [T]| {
+        for (i, component) in element.iter_mut().enumerate() {
+            *component = convert(&data[i * component_size..], data_type);
+        }
+    };
+
+    let mut values = vec![T::default(); count * components];
+    if let Some(view) = accessor.view() {
+        let data = &view_data(&view, buffers)[accessor.offset()..];
+        let stride = view.stride().unwrap_or_else(|| accessor.size());
+        for (i, element) in values.chunks_mut(components).enumerate() {
+            read_element(&data[i * stride..], element);
+        }
+    }
+
+    if let Some(sparse) = accessor.sparse() {
+        let indices = sparse.indices();
+        let index_size = indices.index_type().size();
+        let index_view = indices.view();
+        let index_stride = index_view.stride().unwrap_or(index_size);
+        let index_data = &view_data(&index_view, buffers)[indices.offset() as usize..];
+
+        let sparse_values = sparse.values();
+        let value_view = sparse_values.view();
+        let value_stride = value_view.stride().unwrap_or_else(|| accessor.size());
+        let value_data = &view_data(&value_view, buffers)[sparse_values.offset() as usize..];
+
+        let index_type = match index_size {
+            1 => DataType::U8,
+            2 => DataType::U16,
+            _ => DataType::U32,
+        };
+        for i in 0..sparse.count() as usize {
+            let index = read_integer(&index_data[i * index_stride..], index_type);
+            if index as usize >= count {
+                return Err(GltfError::IndexOutOfRange {
+                    path: path.join(Segment::Accessor(accessor.index())),
+                    index,
+                    vertex_count: count,
+                });
             }
+            let start = index as usize * components;
+            read_element(
+                &value_data[i * value_stride..],
+                &mut values[start..start + components],
+            );
         }
-        if let Some(normals) = reader.read_normals() {
-            println!("{:?}", normals);
+    }
+    Ok(values)
+}
+
+fn read_floats(
+    accessor: &Accessor,
+    buffers: &GltfBuffers,
+    path: &GltfPath,
+) -> GltfResult<Vec<f32>> {
+    let normalized = accessor.normalized();
+    read_components(accessor, buffers, path, |bytes, data_type| {
+        dequantize(bytes, data_type, normalized)
+    })
+}
+
+fn read_integers(
+    accessor: &Accessor,
+    buffers: &GltfBuffers,
+    path: &GltfPath,
+) -> GltfResult<Vec<u32>> {
+    read_components(accessor, buffers, path, read_integer)
+}
+
+fn to_vec2(values: Vec<f32>) -> Vec<[f32; 2]> {
+    values.chunks_exact(2).map(|c| [c[0], c[1]]).collect()
+}
+
+fn to_vec3(values: Vec<f32>) -> Vec<[f32; 3]> {
+    values.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect()
+}
+
+fn to_vec4(values: Vec<f32>) -> Vec<[f32; 4]> {
+    values
+        .chunks_exact(4)
+        .map(|c| [c[0], c[1], c[2], c[3]])
+        .collect()
+}
+
+fn load_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &GltfBuffers,
+    quantized: bool,
+    path: &GltfPath,
+) -> GltfResult<Primitive> {
+    use DataType::{F32, I16, I8, U16, U32, U8};
+    use Dimensions::{Scalar, Vec2, Vec3, Vec4};
+
+    // KHR_mesh_quantization widens the component types allowed for
+    // geometry, the values are dequantized to f32 below
+    let (position_types, normal_types, tangent_types, tex_coord_types): (&[_], &[_], &[_], &[_]) =
+        if quantized {
+            (
+                &[
+                    (F32, Vec3),
+                    (I8, Vec3),
+                    (U8, Vec3),
+                    (I16, Vec3),
+                    (U16, Vec3),
+                ],
+                &[(F32, Vec3), (I8, Vec3), (I16, Vec3)],
+                &[(F32, Vec4), (I8, Vec4), (I16, Vec4)],
+                &[
+                    (F32, Vec2),
+                    (I8, Vec2),
+                    (U8, Vec2),
+                    (I16, Vec2),
+                    (U16, Vec2),
+                ],
+            )
+        } else {
+            (
+                &[(F32, Vec3)],
+                &[(F32, Vec3)],
+                &[(F32, Vec4)],
+                &[(F32, Vec2), (U8, Vec2), (U16, Vec2)],
+            )
+        };
+
+    match primitive.mode() {
+        Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan => {}
+        mode => {
+            return Err(GltfError::UnsupportedMode {
+                path: path.clone(),
+                mode,
+            })
         }
-        if let Some(tangents) = reader.read_tangents() {
-            println!("{:?}", tangents);
+    }
+
+    let attribute_path = |semantic: &Semantic| path.join(Segment::Attribute(semantic.to_string()));
+
+    let positions =
+        primitive
+            .get(&Semantic::Positions)
+            .ok_or_else(|| GltfError::MissingAttribute {
+                path: path.clone(),
+                semantic: Semantic::Positions.to_string(),
+            })?;
+    let vertex_count = positions.count();
+
+    for (semantic, accessor) in primitive.attributes() {
+        let path = attribute_path(&semantic);
+        check_accessor(&accessor, buffers, &path)?;
+        check_count(&accessor, vertex_count, &path)?;
+        match semantic {
+            Semantic::Positions => check_format(&accessor, position_types, &path)?,
+            Semantic::Normals => check_format(&accessor, normal_types, &path)?,
+            Semantic::Tangents => check_format(&accessor, tangent_types, &path)?,
+            Semantic::TexCoords(_) => check_format(&accessor, tex_coord_types, &path)?,
+            Semantic::Joints(_) => check_format(&accessor, &[(U8, Vec4), (U16, Vec4)], &path)?,
+            Semantic::Weights(_) => {
+                check_format(&accessor, &[(F32, Vec4), (U8, Vec4), (U16, Vec4)], &path)?
+            }
+            _ => {}
         }
-        if let Some(weights) = reader.read_weights(0) {
-            for (i, weight) in weights.into_f32().enumerate() {
-                println!("weight {}: {:?}", i, weight);
+    }
+    if let Some(indices) = primitive.indices() {
+        let path = path.join(Segment::Indices);
+        check_accessor(&indices, buffers, &path)?;
+        check_format(
+            &indices,
+            &[(U8, Scalar), (U16, Scalar), (U32, Scalar)],
+            &path,
+        )?;
+    }
+
+    let has_joints = primitive.get(&Semantic::Joints(0)).is_some();
+    let has_weights = primitive.get(&Semantic::Weights(0)).is_some();
+    if has_joints != has_weights {
+        let semantic = if has_joints {
+            Semantic::Weights(0)
+        } else {
+            Semantic::Joints(0)
+        };
+        return Err(GltfError::MissingAttribute {
+            path: path.clone(),
+            semantic: semantic.to_string(),
+        });
+    }
+
+    let read_attribute = |semantic: Semantic| {
+        let path = attribute_path(&semantic);
+        primitive
+            .get(&semantic)
+            .map(|accessor| read_floats(&accessor, buffers, &path))
+            .transpose()
+    };
+
+    let positions = to_vec3(read_floats(
+        &positions,
+        buffers,
+        &attribute_path(&Semantic::Positions),
+    )?);
+    let normals = read_attribute(Semantic::Normals)?.map(to_vec3);
+    let tex_coords = read_attribute(Semantic::TexCoords(0))?.map(to_vec2);
+    let tangents = read_attribute(Semantic::Tangents)?.map(to_vec4);
+    let weights = read_attribute(Semantic::Weights(0))?.map(to_vec4);
+    let joints = primitive
+        .get(&Semantic::Joints(0))
+        .map(|accessor| {
+            let path = attribute_path(&Semantic::Joints(0));
+            read_integers(&accessor, buffers, &path)
+        })
+        .transpose()?
+        .map(|joints| {
+            joints
+                .chunks_exact(4)
+                .map(|c| [c[0] as u16, c[1] as u16, c[2] as u16, c[3] as u16])
+                .collect::<Vec<_>>()
+        });
+    let indices = primitive
+        .indices()
+        .map(|accessor| read_integers(&accessor, buffers, &path.join(Segment::Indices)))
+        .transpose()?;
+
+    if let Some(weights) = &weights {
+        let path = attribute_path(&Semantic::Weights(0));
+        for (vertex, weight) in weights.iter().enumerate() {
+            let sum: f32 = weight.iter().sum();
+            if (sum - 1.0).abs() > WEIGHT_SUM_EPSILON {
+                return Err(GltfError::WeightsNotNormalized { path, vertex, sum });
             }
         }
-        if let Some(joints) = reader.read_joints(0) {
-            for (i, joint) in joints.into_u16().enumerate() {
-                println!("joint {}: {:?}", i, joint);
+    }
+    if let Some(indices) = &indices {
+        if let Some(&index) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+            return Err(GltfError::IndexOutOfRange {
+                path: path.join(Segment::Indices),
+                index,
+                vertex_count,
+            });
+        }
+    }
+    // everything downstream draws triangle lists
+    let indices = match primitive.mode() {
+        Mode::Triangles => indices,
+        mode => {
+            let corners = indices.unwrap_or_else(|| (0..vertex_count as u32).collect());
+            Some(triangle_list(mode, &corners))
+        }
+    };
+
+    let generated = match (&tangents, &normals, &tex_coords) {
+        (None, Some(normals), Some(tex_coords)) => {
+            let sequential: Vec<u32>;
+            let corners = match &indices {
+                Some(indices) => indices,
+                None => {
+                    sequential = (0..vertex_count as u32).collect();
+                    &sequential
+                }
+            };
+            Some(tangents::generate_tangents(
+                &positions, normals, tex_coords, corners,
+            ))
+        }
+        _ => None,
+    };
+    let (positions, normals, tex_coords, joints, weights, tangents, indices) = match generated {
+        // vertices split for their tangents take the other attributes along,
+        // a primitive without indices has no shared vertices to split
+        Some(generated) if generated.vertices.len() > vertex_count => (
+            generated.remap(&positions),
+            normals.map(|normals| generated.remap(&normals)),
+            tex_coords.map(|tex_coords| generated.remap(&tex_coords)),
+            joints.map(|joints| generated.remap(&joints)),
+            weights.map(|weights| generated.remap(&weights)),
+            Some(generated.tangents),
+            Some(generated.indices),
+        ),
+        Some(generated) => (
+            positions,
+            normals,
+            tex_coords,
+            joints,
+            weights,
+            Some(generated.tangents),
+            indices,
+        ),
+        None => (
+            positions, normals, tex_coords, joints, weights, tangents, indices,
+        ),
+    };
+
+    let bounds = Bounds::from_points(&positions);
+    Ok(Primitive {
+        positions,
+        normals,
+        tex_coords,
+        tangents,
+        joints,
+        weights,
+        indices,
+        material: primitive.material().index(),
+        bounds,
+    })
+}
+
+fn load_mesh(mesh: &gltf::Mesh, buffers: &GltfBuffers, quantized: bool) -> GltfResult<Mesh> {
+    let path = GltfPath::default().join(Segment::Mesh(mesh.index(), mesh.name().map(String::from)));
+    let primitives = mesh
+        .primitives()
+        .map(|primitive| {
+            let path = path.join(Segment::Primitive(primitive.index()));
+            load_primitive(&primitive, buffers, quantized, &path)
+        })
+        .collect::<GltfResult<_>>()?;
+    Ok(Mesh {
+        name: mesh.name().map(String::from),
+        primitives,
+    })
+}
+
+/// Joint indices are only meaningful relative to the skin of the node
+/// instancing the mesh, so they're checked per node rather than per mesh.
+fn check_skinned_nodes(gltf: &gltf::Gltf, meshes: &[Mesh]) -> GltfResult<()> {
+    for node in gltf.nodes() {
+        let (mesh, skin) = match (node.mesh(), node.skin()) {
+            (Some(mesh), Some(skin)) => (mesh, skin),
+            _ => continue,
+        };
+        let joint_count = skin.joints().count();
+        let node_path =
+            GltfPath::default().join(Segment::Node(node.index(), node.name().map(String::from)));
+        let mesh_path = node_path.join(Segment::Mesh(mesh.index(), mesh.name().map(String::from)));
+        for (prim_index, primitive) in meshes[mesh.index()].primitives.iter().enumerate() {
+            let path = mesh_path.join(Segment::Primitive(prim_index));
+            let joints = primitive
+                .joints
+                .as_ref()
+                .ok_or_else(|| GltfError::MissingAttribute {
+                    path: path.clone(),
+                    semantic: Semantic::Joints(0).to_string(),
+                })?;
+            for (vertex, joint) in joints.iter().enumerate() {
+                if let Some(&joint) = joint.iter().find(|&&j| j as usize >= joint_count) {
+                    return Err(GltfError::JointOutOfRange {
+                        path: path.join(Segment::Attribute(Semantic::Joints(0).to_string())),
+                        vertex,
+                        joint,
+                        joint_count,
+                    });
+                }
             }
         }
     }
     Ok(())
 }
 
+/// Fills `Skin::joint_bounds` from every node skinning a mesh, the vertices
+/// are moved into each influencing joint's space by its inverse bind matrix.
+fn compute_joint_bounds(gltf: &gltf::Gltf, meshes: &[Mesh], skins: &mut [Skin]) {
+    for node in gltf.nodes() {
+        let (mesh, skin) = match (node.mesh(), node.skin()) {
+            (Some(mesh), Some(skin)) => (&meshes[mesh.index()], &mut skins[skin.index()]),
+            _ => continue,
+        };
+        let inverse_binds: Vec<cgmath::Matrix4<f32>> = skin
+            .inverse_bind_matrices
+            .iter()
+            .map(|&m| cgmath::Matrix4::from(m))
+            .collect();
+        for primitive in &mesh.primitives {
+            let (joints, weights) = match (&primitive.joints, &primitive.weights) {
+                (Some(joints), Some(weights)) => (joints, weights),
+                _ => continue,
+            };
+            let vertices = primitive.positions.iter().zip(joints).zip(weights);
+            for ((&position, joints), weights) in vertices {
+                let position = cgmath::Point3::from(position);
+                for (&joint, &weight) in joints.iter().zip(weights) {
+                    if weight <= 0.0 {
+                        continue;
+                    }
+                    let joint = joint as usize;
+                    let p = cgmath::Transform::transform_point(&inverse_binds[joint], position);
+                    let bounds = &mut skin.joint_bounds[joint];
+                    *bounds = Some(match bounds {
+                        Some(aabb) => aabb.extend(p),
+                        None => Aabb { min: p, max: p },
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Model {
+    /// World transform of every node in the rest pose, nodes outside of all
+    /// scenes are left at identity.
+    pub fn world_transforms(&self) -> Vec<cgmath::Matrix4<f32>> {
+        fn walk(
+            nodes: &[Node],
+            roots: &[JointNode],
+            parent: cgmath::Matrix4<f32>,
+            worlds: &mut [cgmath::Matrix4<f32>],
+        ) {
+            for root in roots {
+                let node = &nodes[root.index];
+                let [x, y, z, w] = node.rotation;
+                let local = cgmath::Matrix4::from_translation(node.translation.into())
+                    * cgmath::Matrix4::from(cgmath::Quaternion::new(w, x, y, z))
+                    * cgmath::Matrix4::from_nonuniform_scale(
+                        node.scale[0],
+                        node.scale[1],
+                        node.scale[2],
+                    );
+                worlds[root.index] = parent * local;
+                walk(nodes, &root.children, worlds[root.index], worlds);
+            }
+        }
+        use cgmath::SquareMatrix;
+        let mut worlds = vec![cgmath::Matrix4::identity(); self.nodes.len()];
+        for scene in &self.scenes {
+            walk(&self.nodes, scene, cgmath::Matrix4::identity(), &mut worlds);
+        }
+        worlds
+    }
+}
+
 // struct JointRoot {
 //     node: JointNode,
 //     mesh_index: usize,
@@ -107,22 +1267,22 @@ fn load_geometry(mesh: &gltf::Mesh, buffers: &GltfBuffers) -> Result<()> {
 
 // transform: gltf::scene::Transform,
 
-#[derive(Debug)]
-struct JointNode {
-    index: usize,
-    children: Vec<JointNode>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointNode {
+    pub index: usize,
+    pub children: Vec<JointNode>,
 }
 
 impl From<&gltf::Node<'_>> for JointNode {
     fn from(node: &gltf::Node) -> Self {
         JointNode {
             index: node.index(),
-            children: gather_children(&node, node.transform()),
+            children: gather_children(&node),
         }
     }
 }
 
-fn gather_children(node: &gltf::Node, parent: gltf::scene::Transform) -> Vec<JointNode> {
+fn gather_children(node: &gltf::Node) -> Vec<JointNode> {
     node.children().map(|node| JointNode::from(&node)).collect()
 }
 
@@ -130,76 +1290,1064 @@ fn transform_forest(scene: gltf::scene::Scene) -> Vec<JointNode> {
     scene.nodes().map(|node| JointNode::from(&node)).collect()
 }
 
-fn to_global_transform(parent: gltf::scene::Transform) -> [[f32; 4]; 4] {
-    parent.matrix()
-}
+fn load_animation(animation: &gltf::Animation, buffers: &GltfBuffers) -> GltfResult<Animation> {
+    use DataType::{F32, I16, I8, U16, U8};
+    use Dimensions::{Scalar, Vec3, Vec4};
 
-use gltf::animation::{util::ReadOutputs, Animation};
+    let path = GltfPath::default().join(Segment::Animation(animation.index()));
+    let channels = animation
+        .channels()
+        .enumerate()
+        .map(|(index, channel)| {
+            let path = path.join(Segment::Channel(index));
+            let sampler = channel.sampler();
+            let (input, output) = (sampler.input(), sampler.output());
+            check_accessor(&input, buffers, &path)?;
+            check_accessor(&output, buffers, &path)?;
+            check_format(&input, &[(F32, Scalar)], &path)?;
 
-fn load_animation(animation: &Animation<'_>, buffers: &GltfBuffers) {
-    for channel in animation.channels() {
-        let reader = channel.reader(|id| buffers.buffer(&id));
-        if let Some(inputs) = reader.read_inputs() {
-            println!("anim inputs");
-            for (i, input) in inputs.enumerate() {
-                println!("\tindex {} {:?}", i, input);
+            let inputs = read_floats(&input, buffers, &path)?;
+            let property = channel.target().property();
+            let outputs = match property {
+                Property::Translation => {
+                    check_format(&output, &[(F32, Vec3)], &path)?;
+                    ChannelOutputs::Translations(to_vec3(read_floats(&output, buffers, &path)?))
+                }
+                Property::Rotation => {
+                    let formats = [
+                        (F32, Vec4),
+                        (I8, Vec4),
+                        (U8, Vec4),
+                        (I16, Vec4),
+                        (U16, Vec4),
+                    ];
+                    check_format(&output, &formats, &path)?;
+                    ChannelOutputs::Rotations(to_vec4(read_floats(&output, buffers, &path)?))
+                }
+                Property::Scale => {
+                    check_format(&output, &[(F32, Vec3)], &path)?;
+                    ChannelOutputs::Scales(to_vec3(read_floats(&output, buffers, &path)?))
+                }
+                Property::MorphTargetWeights => {
+                    let formats = [
+                        (F32, Scalar),
+                        (I8, Scalar),
+                        (U8, Scalar),
+                        (I16, Scalar),
+                        (U16, Scalar),
+                    ];
+                    check_format(&output, &formats, &path)?;
+                    ChannelOutputs::MorphTargetWeights(read_floats(&output, buffers, &path)?)
+                }
+            };
+
+            // morph weights hold one value per target, so only keyframed
+            // transforms can be checked against the input count
+            let keyframes = match sampler.interpolation() {
+                Interpolation::CubicSpline => inputs.len() * 3,
+                _ => inputs.len(),
+            };
+            if !matches!(outputs, ChannelOutputs::MorphTargetWeights(_)) {
+                check_count(&output, keyframes, &path)?;
             }
+
+            Ok(Channel {
+                node: channel.target().node().index(),
+                interpolation: sampler.interpolation(),
+                inputs,
+                outputs,
+            })
+        })
+        .collect::<GltfResult<_>>()?;
+    Ok(Animation {
+        name: animation.name().map(String::from),
+        channels,
+    })
+}
+
+fn load_skin(skin: &gltf::Skin, buffers: &GltfBuffers) -> GltfResult<Skin> {
+    use cgmath::SquareMatrix;
+
+    let path = GltfPath::default().join(Segment::Skin(skin.index()));
+    let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+    let inverse_bind_matrices = match skin.inverse_bind_matrices() {
+        Some(accessor) => {
+            check_accessor(&accessor, buffers, &path)?;
+            check_format(&accessor, &[(DataType::F32, Dimensions::Mat4)], &path)?;
+            check_count(&accessor, joints.len(), &path)?;
+            // column major, like the gltf::Skin reader returns them
+            read_floats(&accessor, buffers, &path)?
+                .chunks_exact(16)
+                .map(|m| {
+                    [
+                        [m[0], m[1], m[2], m[3]],
+                        [m[4], m[5], m[6], m[7]],
+                        [m[8], m[9], m[10], m[11]],
+                        [m[12], m[13], m[14], m[15]],
+                    ]
+                })
+                .collect()
         }
-        if let Some(outputs) = reader.read_outputs() {
-            match outputs {
-                ReadOutputs::Translations(trans) => {}
-                ReadOutputs::Rotations(rots) => {
-                    for (i, rot) in rots.into_f32().enumerate() {
-                        println!("Rotations {}, {:?}", i, rot);
-                    }
-                }
-                ReadOutputs::Scales(scales) => {}
-                ReadOutputs::MorphTargetWeights(morphs) => {}
+        None => vec![cgmath::Matrix4::identity().into(); joints.len()],
+    };
+    Ok(Skin {
+        joint_bounds: vec![None; joints.len()],
+        joints,
+        skeleton: skin.skeleton().map(|node| node.index()),
+        inverse_bind_matrices,
+    })
+}
+
+fn load_instancing_attribute(
+    gltf: &gltf::Gltf,
+    attributes: &Value,
+    semantic: &str,
+    quantized: bool,
+    buffers: &GltfBuffers,
+    path: &GltfPath,
+) -> GltfResult<Option<Vec<f32>>> {
+    use DataType::{F32, I16, I8, U16, U8};
+    use Dimensions::{Vec3, Vec4};
+
+    let index = match attributes.get(semantic).and_then(Value::as_u64) {
+        Some(index) => index as usize,
+        None => return Ok(None),
+    };
+    let path = path.join(Segment::Attribute(semantic.to_string()));
+    let accessor = gltf
+        .accessors()
+        .nth(index)
+        .ok_or_else(|| GltfError::MissingAccessor {
+            path: path.clone(),
+            accessor: index,
+        })?;
+    check_accessor(&accessor, buffers, &path)?;
+
+    let formats: &[_] = match (semantic, quantized) {
+        ("ROTATION", _) => &[(F32, Vec4), (I8, Vec4), (I16, Vec4)],
+        (_, false) => &[(F32, Vec3)],
+        (_, true) => &[
+            (F32, Vec3),
+            (I8, Vec3),
+            (U8, Vec3),
+            (I16, Vec3),
+            (U16, Vec3),
+        ],
+    };
+    check_format(&accessor, formats, &path)?;
+    Ok(Some(read_floats(&accessor, buffers, &path)?))
+}
+
+/// Reads the per instance TRS of a node, the instance transforms are applied
+/// before the node's own world transform.
+fn load_mesh_instances(
+    gltf: &gltf::Gltf,
+    node: &gltf::Node,
+    world: cgmath::Matrix4<f32>,
+    attributes: &Value,
+    quantized: bool,
+    buffers: &GltfBuffers,
+) -> GltfResult<Option<MeshInstances>> {
+    use cgmath::{Matrix4, Quaternion, Vector3};
+
+    let mesh = match node.mesh() {
+        Some(mesh) => mesh.index(),
+        None => return Ok(None),
+    };
+    let path = GltfPath::default().join(Segment::Node(node.index(), node.name().map(String::from)));
+    let attribute =
+        |semantic| load_instancing_attribute(gltf, attributes, semantic, quantized, buffers, &path);
+    let translations = attribute("TRANSLATION")?.map(to_vec3);
+    let rotations = attribute("ROTATION")?.map(to_vec4);
+    let scales = attribute("SCALE")?.map(to_vec3);
+
+    let counts = [
+        translations.as_ref().map(Vec::len),
+        rotations.as_ref().map(Vec::len),
+        scales.as_ref().map(Vec::len),
+    ];
+    let count = match counts.iter().flatten().next() {
+        Some(&count) => count,
+        None => return Ok(None),
+    };
+    for (semantic, attribute_count) in ["TRANSLATION", "ROTATION", "SCALE"].iter().zip(&counts) {
+        match attribute_count {
+            Some(attribute_count) if *attribute_count != count => {
+                return Err(GltfError::CountMismatch {
+                    path: path.join(Segment::Attribute(semantic.to_string())),
+                    count: *attribute_count,
+                    expected: count,
+                })
             }
+            _ => {}
         }
     }
-    for sampler in animation.samplers() {}
+
+    let transforms = (0..count)
+        .map(|i| {
+            let [tx, ty, tz] = translations.as_ref().map_or([0.0; 3], |t| t[i]);
+            let [x, y, z, w] = rotations.as_ref().map_or([0.0, 0.0, 0.0, 1.0], |r| r[i]);
+            let [sx, sy, sz] = scales.as_ref().map_or([1.0; 3], |s| s[i]);
+            let instance = Matrix4::from_translation(Vector3::new(tx, ty, tz))
+                * Matrix4::from(Quaternion::new(w, x, y, z))
+                * Matrix4::from_nonuniform_scale(sx, sy, sz);
+            (world * instance).into()
+        })
+        .collect();
+    Ok(Some(MeshInstances {
+        node: node.index(),
+        mesh,
+        transforms,
+        translations,
+        rotations,
+        scales,
+    }))
 }
 
-fn load_skins(skin: &gltf::Skin, buffers: &GltfBuffers) {
-    let reader = skin.reader(|id| buffers.buffer(&id));
-    if let Some(inv_bind_matrices) = reader.read_inverse_bind_matrices() {
-        for mat in inv_bind_matrices {
-            println!("inv_bind {:#?}", mat);
+fn gather_mesh_instances(
+    gltf: &gltf::Gltf,
+    node: gltf::Node,
+    parent: cgmath::Matrix4<f32>,
+    json: &Value,
+    quantized: bool,
+    buffers: &GltfBuffers,
+    instances: &mut Vec<MeshInstances>,
+) -> GltfResult<()> {
+    let world = parent * cgmath::Matrix4::from(node.transform().matrix());
+    let pointer = format!(
+        "/nodes/{}/extensions/EXT_mesh_gpu_instancing/attributes",
+        node.index()
+    );
+    if let Some(attributes) = json.pointer(&pointer) {
+        instances.extend(load_mesh_instances(
+            gltf, &node, world, attributes, quantized, buffers,
+        )?);
+    }
+    for child in node.children() {
+        gather_mesh_instances(gltf, child, world, json, quantized, buffers, instances)?;
+    }
+    Ok(())
+}
+
+fn load_node(node: &gltf::Node) -> Node {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    Node {
+        name: node.name().map(String::from),
+        mesh: node.mesh().map(|mesh| mesh.index()),
+        skin: node.skin().map(|skin| skin.index()),
+        children: node.children().map(|child| child.index()).collect(),
+        translation,
+        rotation,
+        scale,
+    }
+}
+
+/// Images stay encoded, URIs (including data URIs) are kept as they are.
+fn load_image(image: &gltf::Image, buffers: &GltfBuffers) -> GltfResult<Image> {
+    let (mime_type, source) = match image.source() {
+        image::Source::View { view, mime_type } => {
+            let path = GltfPath::default().join(Segment::Image(image.index()));
+            check_view_range(&view, buffers, 0, 0, 1, view.length(), &path)?;
+            (
+                Some(mime_type),
+                ImageSource::Bytes(view_data(&view, buffers).to_vec()),
+            )
         }
+        image::Source::Uri { uri, mime_type } => (mime_type, ImageSource::Uri(uri.to_string())),
+    };
+    Ok(Image {
+        name: image.name().map(String::from),
+        mime_type: mime_type.map(String::from),
+        source,
+    })
+}
+
+fn json_f32(value: Option<&Value>, default: f32) -> f32 {
+    value.and_then(Value::as_f64).map_or(default, |v| v as f32)
+}
+
+fn json_vec2(value: Option<&Value>, default: [f32; 2]) -> [f32; 2] {
+    match value.and_then(Value::as_array).map(Vec::as_slice) {
+        Some([x, y]) => [json_f32(Some(x), default[0]), json_f32(Some(y), default[1])],
+        _ => default,
     }
 }
 
-pub fn load_gltf<P: AsRef<Path>>(path: P) -> Result<()> {
-    let gltf = gltf::Gltf::open(path)?;
+/// Reads a textureInfo object from the raw JSON, `gltf` drops the extensions
+/// we're interested in so everything texture related goes through here.
+fn load_texture_ref(
+    info: Option<&Value>,
+    texture_count: usize,
+    path: &GltfPath,
+) -> GltfResult<Option<TextureRef>> {
+    let info = match info {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+    let texture = match info.get("index").and_then(Value::as_u64) {
+        Some(texture) => texture as usize,
+        None => return Err(GltfError::MissingTextureIndex { path: path.clone() }),
+    };
+    if texture >= texture_count {
+        return Err(GltfError::TextureOutOfRange {
+            path: path.clone(),
+            texture,
+            texture_count,
+        });
+    }
+    let mut tex_coord = info.get("texCoord").and_then(Value::as_u64).unwrap_or(0) as u32;
+    let transform = match info.pointer("/extensions/KHR_texture_transform") {
+        Some(ext) => {
+            if let Some(set) = ext.get("texCoord").and_then(Value::as_u64) {
+                tex_coord = set as u32;
+            }
+            TextureTransform {
+                offset: json_vec2(ext.get("offset"), [0.0, 0.0]),
+                rotation: json_f32(ext.get("rotation"), 0.0),
+                scale: json_vec2(ext.get("scale"), [1.0, 1.0]),
+            }
+        }
+        None => TextureTransform::default(),
+    };
+    Ok(Some(TextureRef {
+        texture,
+        tex_coord,
+        transform,
+    }))
+}
+
+fn load_material(
+    material: &gltf::Material,
+    json: &Value,
+    texture_count: usize,
+) -> GltfResult<Material> {
+    let index = material.index().unwrap_or(0);
+    let path = GltfPath::default().join(Segment::Material(index));
+    let texture = |pointer: &str| {
+        let path = path.join(Segment::Texture(
+            pointer.trim_start_matches('/').to_string(),
+        ));
+        load_texture_ref(json.pointer(pointer), texture_count, &path)
+    };
+
+    let pbr = material.pbr_metallic_roughness();
+    let clearcoat = match json.pointer("/extensions/KHR_materials_clearcoat") {
+        Some(ext) => Some(Clearcoat {
+            factor: json_f32(ext.get("clearcoatFactor"), 0.0),
+            texture: texture("/extensions/KHR_materials_clearcoat/clearcoatTexture")?,
+            roughness_factor: json_f32(ext.get("clearcoatRoughnessFactor"), 0.0),
+            roughness_texture: texture(
+                "/extensions/KHR_materials_clearcoat/clearcoatRoughnessTexture",
+            )?,
+            normal_texture: texture("/extensions/KHR_materials_clearcoat/clearcoatNormalTexture")?,
+        }),
+        None => None,
+    };
+
+    Ok(Material {
+        name: material.name().map(String::from),
+        base_color_factor: pbr.base_color_factor(),
+        base_color_texture: texture("/pbrMetallicRoughness/baseColorTexture")?,
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: texture("/pbrMetallicRoughness/metallicRoughnessTexture")?,
+        normal_texture: texture("/normalTexture")?,
+        occlusion_texture: texture("/occlusionTexture")?,
+        emissive_factor: material.emissive_factor(),
+        emissive_strength: json_f32(
+            json.pointer("/extensions/KHR_materials_emissive_strength/emissiveStrength"),
+            1.0,
+        ),
+        emissive_texture: texture("/emissiveTexture")?,
+        clearcoat,
+        unlit: json.pointer("/extensions/KHR_materials_unlit").is_some(),
+    })
+}
+
+pub fn load_gltf<P: AsRef<Path>>(path: P) -> Result<Model> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    load_gltf_slice(&bytes, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Loads a `.gltf` or `.glb` document, external buffers are relative to
+/// `base_dir`.
+fn load_gltf_slice(bytes: &[u8], base_dir: &Path) -> Result<Model> {
+    let gltf = gltf::Gltf::from_slice(bytes)?;
+    // the raw document, for the extensions `gltf` doesn't know about
+    let json: Value = if bytes.starts_with(b"glTF") {
+        serde_json::from_slice(&gltf::binary::Glb::from_slice(bytes)?.json)?
+    } else {
+        serde_json::from_slice(bytes)?
+    };
+    let buffers = collect_buffers(&gltf, base_dir)?;
+
+    let nodes = gltf.nodes().map(|node| load_node(&node)).collect();
+    let images = gltf
+        .images()
+        .map(|image| load_image(&image, &buffers))
+        .collect::<GltfResult<_>>()?;
+    let samplers = gltf
+        .samplers()
+        .map(|sampler| Sampler {
+            name: sampler.name().map(String::from),
+            mag_filter: sampler.mag_filter(),
+            min_filter: sampler.min_filter(),
+            wrap_s: sampler.wrap_s(),
+            wrap_t: sampler.wrap_t(),
+        })
+        .collect();
+    let textures = gltf
+        .textures()
+        .map(|texture| Texture {
+            name: texture.name().map(String::from),
+            image: texture.source().index(),
+            sampler: texture.sampler().index(),
+        })
+        .collect();
+
+    let texture_count = gltf.textures().count();
+    let materials = gltf
+        .materials()
+        .map(|material| {
+            let index = material.index().unwrap_or(0);
+            let material_json = json.pointer(&format!("/materials/{}", index));
+            load_material(
+                &material,
+                material_json.unwrap_or(&Value::Null),
+                texture_count,
+            )
+        })
+        .collect::<GltfResult<_>>()?;
+    let quantized = gltf
+        .extensions_used()
+        .any(|extension| extension == "KHR_mesh_quantization");
+
+    let meshes = gltf
+        .meshes()
+        .map(|mesh| load_mesh(&mesh, &buffers, quantized))
+        .collect::<GltfResult<Vec<_>>>()?;
+    check_skinned_nodes(&gltf, &meshes)?;
+
+    let mut skins: Vec<Skin> = gltf
+        .skins()
+        .map(|skin| load_skin(&skin, &buffers))
+        .collect::<GltfResult<_>>()?;
+    compute_joint_bounds(&gltf, &meshes, &mut skins);
+    let animations = gltf
+        .animations()
+        .map(|animation| load_animation(&animation, &buffers))
+        .collect::<GltfResult<_>>()?;
+    let scenes = gltf.scenes().map(transform_forest).collect();
+    let scene = gltf.default_scene().map(|scene| scene.index());
 
+    let mut instances = Vec::new();
     for scene in gltf.scenes() {
-        let name = scene.name().unwrap_or("--");
-        println!("Scene {} with index {}", name, scene.index());
         for node in scene.nodes() {
-            visit_node(&node);
+            gather_mesh_instances(
+                &gltf,
+                node,
+                cgmath::SquareMatrix::identity(),
+                &json,
+                quantized,
+                &buffers,
+                &mut instances,
+            )?;
         }
     }
 
-    let gltf_buffers = collect_buffers(&gltf);
-    println!("Buffer total {}", gltf_buffers.0.len());
-    for (index, (start, end)) in gltf_buffers.1.iter().cloned().enumerate() {
-        println!("Buffer {} {:?}", index, &gltf_buffers.0[start..end]);
+    Ok(Model {
+        nodes,
+        images,
+        samplers,
+        textures,
+        materials,
+        meshes,
+        skins,
+        animations,
+        scenes,
+        scene,
+        instances,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn triangle(normals: Option<Vec<[f32; 3]>>) -> Primitive {
+        let positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        Primitive {
+            bounds: Bounds::from_points(&positions),
+            positions,
+            normals,
+            tex_coords: None,
+            tangents: None,
+            joints: None,
+            weights: None,
+            indices: None,
+            material: None,
+        }
     }
 
-    for mesh in gltf.meshes() {
-        let _ = load_geometry(&mesh, &gltf_buffers);
+    #[test]
+    fn missing_normals_are_flat() {
+        let (vertices, indices) = triangle(None).vertices();
+        assert_eq!(indices, vec![0, 1, 2]);
+        for vertex in &vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+            assert_eq!(vertex.tex_coord, [0.0, 0.0]);
+        }
     }
-    for skin in gltf.skins() {
-        load_skins(&skin, &gltf_buffers);
+
+    #[test]
+    fn given_normals_are_kept() {
+        let normals = vec![[0.0, 0.0, 1.0], [0.0, 0.6, 0.8], [0.6, 0.0, 0.8]];
+        let (vertices, _) = triangle(Some(normals.clone())).vertices();
+        let kept: Vec<_> = vertices.iter().map(|vertex| vertex.normal).collect();
+        assert_eq!(kept, normals);
+        assert!(vertices.iter().all(|vertex| vertex.tangent[3].abs() == 1.0));
     }
-    for scene in gltf.scenes() {
-        let v = transform_forest(scene);
-        println!("{:#?}", v);
+
+    /// A triangle with float positions in bytes 0..36 of the buffer and u16
+    /// indices in 36..42.
+    fn triangle_document() -> (Value, Vec<u8>) {
+        let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let mut buffer = bytemuck::cast_slice(&positions).to_vec();
+        buffer.extend_from_slice(bytemuck::cast_slice(&[0u16, 1, 2, 0]));
+        let document = json!({
+            "asset": { "version": "2.0" },
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+            "accessors": [
+                {
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0],
+                },
+                { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" },
+            ],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 6 },
+            ],
+        });
+        (document, buffer)
     }
 
-    for animation in gltf.animations() {
-        load_animation(&animation, &gltf_buffers);
+    /// The triangle skinned to `joints` with `weights` per vertex, both
+    /// appended to the buffer. Node 0 instances it with skin 0, whose only
+    /// joint is node 1.
+    fn skinned_document(joints: [[u8; 4]; 3], weights: [[f32; 4]; 3]) -> (Value, Vec<u8>) {
+        let (mut document, mut buffer) = triangle_document();
+        let joints_offset = buffer.len();
+        buffer.extend(joints.iter().flatten());
+        let weights_offset = buffer.len();
+        buffer.extend_from_slice(bytemuck::cast_slice(&weights));
+        document["meshes"][0]["primitives"][0]["attributes"] =
+            json!({ "POSITION": 0, "JOINTS_0": 2, "WEIGHTS_0": 3 });
+        let accessors = document["accessors"].as_array_mut().unwrap();
+        accessors
+            .push(json!({ "bufferView": 2, "componentType": 5121, "count": 3, "type": "VEC4" }));
+        accessors
+            .push(json!({ "bufferView": 3, "componentType": 5126, "count": 3, "type": "VEC4" }));
+        let views = document["bufferViews"].as_array_mut().unwrap();
+        views.push(json!({ "buffer": 0, "byteOffset": joints_offset, "byteLength": 12 }));
+        views.push(json!({ "buffer": 0, "byteOffset": weights_offset, "byteLength": 48 }));
+        document["nodes"] = json!([{ "mesh": 0, "skin": 0 }, {}]);
+        document["skins"] = json!([{ "joints": [1] }]);
+        (document, buffer)
+    }
+
+    /// Appends `data` to the buffer behind a new view and returns the view.
+    fn push_view(document: &mut Value, buffer: &mut Vec<u8>, data: &[u8]) -> usize {
+        while !buffer.len().is_multiple_of(4) {
+            buffer.push(0);
+        }
+        let views = document["bufferViews"].as_array_mut().unwrap();
+        views.push(json!({ "buffer": 0, "byteOffset": buffer.len(), "byteLength": data.len() }));
+        buffer.extend_from_slice(data);
+        views.len() - 1
+    }
+
+    /// Points `semantic` of the triangle at a new accessor over `data`, with
+    /// `KHR_mesh_quantization` enabled.
+    fn quantized_attribute(
+        semantic: &str,
+        component_type: u32,
+        ty: &str,
+        data: &[u8],
+    ) -> Primitive {
+        let (mut document, mut buffer) = triangle_document();
+        let view = push_view(&mut document, &mut buffer, data);
+        let accessors = document["accessors"].as_array_mut().unwrap();
+        accessors.push(json!({
+            "bufferView": view, "componentType": component_type, "normalized": true,
+            "count": 3, "type": ty,
+        }));
+        if semantic == "POSITION" {
+            // required on positions, the values aren't checked
+            let accessor = accessors.last_mut().unwrap();
+            accessor["min"] = json!([-1.0, -1.0, -1.0]);
+            accessor["max"] = json!([1.0, 1.0, 1.0]);
+        }
+        let accessor = accessors.len() - 1;
+        document["meshes"][0]["primitives"][0]["attributes"][semantic] = json!(accessor);
+        document["extensionsUsed"] = json!(["KHR_mesh_quantization"]);
+        document["extensionsRequired"] = json!(["KHR_mesh_quantization"]);
+        let mut model = load(&document, &buffer).unwrap();
+        model.meshes.remove(0).primitives.remove(0)
+    }
+
+    fn with_buffer(mut document: Value, uri: String, byte_length: usize) -> Vec<u8> {
+        document["buffers"] = json!([{ "byteLength": byte_length, "uri": uri }]);
+        serde_json::to_vec(&document).unwrap()
+    }
+
+    fn load(document: &Value, buffer: &[u8]) -> Result<Model> {
+        let uri = format!("{}{}", DATA_BASE64, base64::encode(buffer));
+        let bytes = with_buffer(document.clone(), uri, buffer.len());
+        load_gltf_slice(&bytes, Path::new(""))
+    }
+
+    fn load_error(document: &Value, buffer: &[u8]) -> GltfError {
+        match load(document, buffer) {
+            Ok(_) => panic!("the document loaded"),
+            Err(err) => err.downcast().unwrap(),
+        }
+    }
+
+    fn assert_path(err: &GltfError, path: &str) {
+        let message = err.to_string();
+        assert!(message.starts_with(&format!("{}: ", path)), "{}", message);
+    }
+
+    #[test]
+    fn valid_documents_load() {
+        let (document, buffer) = triangle_document();
+        let model = load(&document, &buffer).unwrap();
+        assert_eq!(model.meshes[0].primitives[0].indices, Some(vec![0, 1, 2]));
+        let (document, buffer) = skinned_document([[0; 4]; 3], [[1.0, 0.0, 0.0, 0.0]; 3]);
+        load(&document, &buffer).unwrap();
+    }
+
+    #[test]
+    fn view_past_the_buffer() {
+        let (mut document, buffer) = triangle_document();
+        document["bufferViews"][0]["byteLength"] = json!(48);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::AccessorOutOfBounds {
+                end: 48,
+                len: 44,
+                ..
+            }
+        ));
+        assert_path(
+            &err,
+            "meshes[0]/primitives[0]/attributes[POSITION]/accessors[0]/buffers[0]",
+        );
+    }
+
+    #[test]
+    fn accessor_past_the_view() {
+        let (mut document, buffer) = triangle_document();
+        document["accessors"][0]["count"] = json!(4);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::AccessorOutOfBounds {
+                end: 48,
+                len: 36,
+                ..
+            }
+        ));
+        assert_path(
+            &err,
+            "meshes[0]/primitives[0]/attributes[POSITION]/accessors[0]",
+        );
+    }
+
+    #[test]
+    fn wrong_format() {
+        let (mut document, buffer) = triangle_document();
+        document["accessors"][0]["type"] = json!("VEC2");
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::UnsupportedFormat {
+                data_type: DataType::F32,
+                dimensions: Dimensions::Vec2,
+                ..
+            }
+        ));
+        assert_path(
+            &err,
+            "meshes[0]/primitives[0]/attributes[POSITION]/accessors[0]",
+        );
+    }
+
+    #[test]
+    fn empty_accessor() {
+        let (mut document, buffer) = triangle_document();
+        document["accessors"][1]["count"] = json!(0);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(err, GltfError::EmptyAccessor { .. }));
+        assert_path(&err, "meshes[0]/primitives[0]/indices/accessors[1]");
+    }
+
+    #[test]
+    fn attribute_count_mismatch() {
+        let (mut document, buffer) = triangle_document();
+        document["meshes"][0]["primitives"][0]["attributes"]["NORMAL"] = json!(2);
+        document["accessors"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3" }));
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::CountMismatch {
+                count: 2,
+                expected: 3,
+                ..
+            }
+        ));
+        assert_path(
+            &err,
+            "meshes[0]/primitives[0]/attributes[NORMAL]/accessors[2]",
+        );
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let (document, mut buffer) = triangle_document();
+        buffer[40..42].copy_from_slice(&5u16.to_le_bytes());
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::IndexOutOfRange {
+                index: 5,
+                vertex_count: 3,
+                ..
+            }
+        ));
+        assert_path(&err, "meshes[0]/primitives[0]/indices");
+    }
+
+    #[test]
+    fn weights_not_summing_to_one() {
+        let mut weights = [[1.0, 0.0, 0.0, 0.0]; 3];
+        weights[1] = [0.5, 0.0, 0.0, 0.0];
+        let (document, buffer) = skinned_document([[0; 4]; 3], weights);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::WeightsNotNormalized { vertex: 1, .. }
+        ));
+        assert_path(&err, "meshes[0]/primitives[0]/attributes[WEIGHTS_0]");
+    }
+
+    #[test]
+    fn joints_without_weights() {
+        let (mut document, buffer) = skinned_document([[0; 4]; 3], [[1.0, 0.0, 0.0, 0.0]; 3]);
+        document["meshes"][0]["primitives"][0]["attributes"]
+            .as_object_mut()
+            .unwrap()
+            .remove("WEIGHTS_0");
+        let err = load_error(&document, &buffer);
+        assert!(
+            matches!(err, GltfError::MissingAttribute { ref semantic, .. } if semantic == "WEIGHTS_0")
+        );
+        assert_path(&err, "meshes[0]/primitives[0]");
+    }
+
+    #[test]
+    fn joint_outside_the_skin() {
+        let mut joints = [[0; 4]; 3];
+        joints[2] = [1, 0, 0, 0];
+        let (document, buffer) = skinned_document(joints, [[1.0, 0.0, 0.0, 0.0]; 3]);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::JointOutOfRange {
+                vertex: 2,
+                joint: 1,
+                joint_count: 1,
+                ..
+            }
+        ));
+        assert_path(
+            &err,
+            "nodes[0]/meshes[0]/primitives[0]/attributes[JOINTS_0]",
+        );
+    }
+
+    #[test]
+    fn texture_reference_without_index() {
+        let (mut document, buffer) = triangle_document();
+        document["materials"] =
+            json!([{ "extensions": { "KHR_materials_clearcoat": { "clearcoatTexture": {} } } }]);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(err, GltfError::MissingTextureIndex { .. }));
+        assert_path(
+            &err,
+            "materials[0]/extensions/KHR_materials_clearcoat/clearcoatTexture",
+        );
+    }
+
+    #[test]
+    fn texture_out_of_range() {
+        let (mut document, buffer) = triangle_document();
+        document["materials"] = json!([{
+            "extensions": { "KHR_materials_clearcoat": { "clearcoatTexture": { "index": 3 } } }
+        }]);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::TextureOutOfRange {
+                texture: 3,
+                texture_count: 0,
+                ..
+            }
+        ));
+        assert_path(
+            &err,
+            "materials[0]/extensions/KHR_materials_clearcoat/clearcoatTexture",
+        );
+    }
+
+    #[test]
+    fn missing_instancing_accessor() {
+        let (mut document, buffer) = triangle_document();
+        document["scenes"] = json!([{ "nodes": [0] }]);
+        document["nodes"] = json!([{
+            "mesh": 0,
+            "extensions": { "EXT_mesh_gpu_instancing": { "attributes": { "TRANSLATION": 9 } } }
+        }]);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::MissingAccessor { accessor: 9, .. }
+        ));
+        assert_path(&err, "nodes[0]/attributes[TRANSLATION]");
+    }
+
+    #[test]
+    fn broken_buffers() {
+        let (document, buffer) = triangle_document();
+        let load_buffer = |uri: &str, byte_length| {
+            let bytes = with_buffer(document.clone(), uri.to_string(), byte_length);
+            let err: GltfError = load_gltf_slice(&bytes, Path::new("/nonexistent"))
+                .err()
+                .unwrap()
+                .downcast()
+                .unwrap();
+            assert_path(&err, "buffers[0]");
+            err
+        };
+        let valid = format!("{}{}", DATA_BASE64, base64::encode(&buffer));
+        assert!(matches!(
+            load_buffer(&valid, 64),
+            GltfError::BufferTooShort {
+                expected: 64,
+                actual: 44,
+                ..
+            }
+        ));
+        let invalid = format!("{}not*base64", DATA_BASE64);
+        assert!(matches!(
+            load_buffer(&invalid, 44),
+            GltfError::Base64 { .. }
+        ));
+        assert!(matches!(
+            load_buffer("triangle.bin", 44),
+            GltfError::Io { .. }
+        ));
+        assert!(matches!(
+            load_buffer("https://example.com/triangle.bin", 44),
+            GltfError::UnsupportedBufferSource { .. }
+        ));
+    }
+
+    #[test]
+    fn sparse_substitution_on_a_view() {
+        let (mut document, mut buffer) = triangle_document();
+        let indices = push_view(&mut document, &mut buffer, bytemuck::cast_slice(&[2u16, 0]));
+        let values = push_view(
+            &mut document,
+            &mut buffer,
+            bytemuck::cast_slice(&[5.0f32, 6.0, 7.0]),
+        );
+        document["accessors"][0]["sparse"] = json!({
+            "count": 1,
+            "indices": { "bufferView": indices, "componentType": 5123 },
+            "values": { "bufferView": values },
+        });
+        let model = load(&document, &buffer).unwrap();
+        let positions = &model.meshes[0].primitives[0].positions;
+        assert_eq!(
+            positions,
+            &vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [5.0, 6.0, 7.0]]
+        );
+    }
+
+    #[test]
+    fn sparse_without_a_view_starts_from_zeros() {
+        let (mut document, mut buffer) = triangle_document();
+        let indices = push_view(&mut document, &mut buffer, &[1, 2, 0, 0]);
+        let values = push_view(
+            &mut document,
+            &mut buffer,
+            bytemuck::cast_slice(&[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        );
+        let accessor = document["accessors"][0].as_object_mut().unwrap();
+        accessor.remove("bufferView");
+        accessor.insert(
+            "sparse".into(),
+            json!({
+                "count": 2,
+                "indices": { "bufferView": indices, "componentType": 5121 },
+                "values": { "bufferView": values },
+            }),
+        );
+        let model = load(&document, &buffer).unwrap();
+        let positions = &model.meshes[0].primitives[0].positions;
+        assert_eq!(
+            positions,
+            &vec![[0.0, 0.0, 0.0], [1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]
+        );
+    }
+
+    #[test]
+    fn sparse_index_out_of_range() {
+        let (mut document, mut buffer) = triangle_document();
+        let indices = push_view(&mut document, &mut buffer, &[3, 0, 0, 0]);
+        let values = push_view(
+            &mut document,
+            &mut buffer,
+            bytemuck::cast_slice(&[1.0f32; 3]),
+        );
+        document["accessors"][0]["sparse"] = json!({
+            "count": 1,
+            "indices": { "bufferView": indices, "componentType": 5121 },
+            "values": { "bufferView": values },
+        });
+        let err = load_error(&document, &buffer);
+        assert!(matches!(err, GltfError::IndexOutOfRange { index: 3, .. }));
+        assert_path(
+            &err,
+            "meshes[0]/primitives[0]/attributes[POSITION]/accessors[0]",
+        );
+    }
+
+    #[test]
+    fn quantized_positions() {
+        let i8s: [i8; 9] = [127, -127, -128, 0, 0, 0, 64, 0, 0];
+        let primitive = quantized_attribute("POSITION", 5120, "VEC3", bytemuck::cast_slice(&i8s));
+        assert_eq!(
+            primitive.positions,
+            vec![[1.0, -1.0, -1.0], [0.0, 0.0, 0.0], [64.0 / 127.0, 0.0, 0.0]]
+        );
+
+        let u8s: [u8; 9] = [255, 0, 51, 0, 0, 0, 0, 0, 0];
+        let primitive = quantized_attribute("POSITION", 5121, "VEC3", &u8s);
+        assert_eq!(primitive.positions[0], [1.0, 0.0, 0.2]);
+
+        let i16s: [i16; 9] = [32767, -32767, -32768, 0, 0, 0, 0, 0, 0];
+        let primitive = quantized_attribute("POSITION", 5122, "VEC3", bytemuck::cast_slice(&i16s));
+        assert_eq!(primitive.positions[0], [1.0, -1.0, -1.0]);
+
+        let u16s: [u16; 9] = [65535, 0, 13107, 0, 0, 0, 0, 0, 0];
+        let primitive = quantized_attribute("POSITION", 5123, "VEC3", bytemuck::cast_slice(&u16s));
+        assert_eq!(primitive.positions[0], [1.0, 0.0, 0.2]);
+    }
+
+    #[test]
+    fn quantized_normals() {
+        let i8s: [i8; 9] = [127, 0, 0, 0, -128, 0, 0, 0, 127];
+        let primitive = quantized_attribute("NORMAL", 5120, "VEC3", bytemuck::cast_slice(&i8s));
+        let expected = vec![[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_eq!(primitive.normals, Some(expected.clone()));
+
+        let i16s: [i16; 9] = [32767, 0, 0, 0, -32767, 0, 0, 0, 32767];
+        let primitive = quantized_attribute("NORMAL", 5122, "VEC3", bytemuck::cast_slice(&i16s));
+        assert_eq!(primitive.normals, Some(expected));
+    }
+
+    #[test]
+    fn quantized_tex_coords() {
+        let u8s: [u8; 6] = [0, 255, 51, 0, 255, 255];
+        let primitive = quantized_attribute("TEXCOORD_0", 5121, "VEC2", &u8s);
+        let expected = vec![[0.0, 1.0], [0.2, 0.0], [1.0, 1.0]];
+        assert_eq!(primitive.tex_coords, Some(expected.clone()));
+
+        let u16s: [u16; 6] = [0, 65535, 13107, 0, 65535, 65535];
+        let primitive =
+            quantized_attribute("TEXCOORD_0", 5123, "VEC2", bytemuck::cast_slice(&u16s));
+        assert_eq!(primitive.tex_coords, Some(expected));
+
+        let i16s: [i16; 6] = [0, 32767, -32767, 0, -32768, 32767];
+        let primitive =
+            quantized_attribute("TEXCOORD_0", 5122, "VEC2", bytemuck::cast_slice(&i16s));
+        assert_eq!(
+            primitive.tex_coords,
+            Some(vec![[0.0, 1.0], [-1.0, 0.0], [-1.0, 1.0]])
+        );
+    }
+
+    #[test]
+    fn quantized_positions_need_the_extension() {
+        let (mut document, mut buffer) = triangle_document();
+        let view = push_view(&mut document, &mut buffer, &[0; 9]);
+        document["accessors"][0]["bufferView"] = json!(view);
+        document["accessors"][0]["componentType"] = json!(5121);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::UnsupportedFormat {
+                data_type: DataType::U8,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn strips_and_fans_become_lists() {
+        let corners = [0, 1, 2, 3, 4];
+        assert_eq!(
+            triangle_list(Mode::TriangleStrip, &corners),
+            vec![0, 1, 2, 2, 1, 3, 2, 3, 4]
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleFan, &corners),
+            vec![1, 2, 0, 2, 3, 0, 3, 4, 0]
+        );
+        assert!(triangle_list(Mode::TriangleStrip, &corners[..2]).is_empty());
+    }
+
+    #[test]
+    fn strip_primitives_load_as_lists() {
+        let (mut document, buffer) = triangle_document();
+        let primitive = &mut document["meshes"][0]["primitives"][0];
+        primitive["mode"] = json!(5);
+        primitive.as_object_mut().unwrap().remove("indices");
+        let model = load(&document, &buffer).unwrap();
+        assert_eq!(model.meshes[0].primitives[0].indices, Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn line_primitives_are_rejected() {
+        let (mut document, buffer) = triangle_document();
+        document["meshes"][0]["primitives"][0]["mode"] = json!(1);
+        let err = load_error(&document, &buffer);
+        assert!(matches!(
+            err,
+            GltfError::UnsupportedMode {
+                mode: Mode::Lines,
+                ..
+            }
+        ));
+        assert_path(&err, "meshes[0]/primitives[0]");
     }
-    Ok(())
 }
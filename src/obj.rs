@@ -0,0 +1,608 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    geometry,
+    render_types::{VertexTexNormal, VertexTexNormalTangent},
+    tangents,
+};
+
+/// The subset of MTL the PBR pipeline can use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    /// `Kd`
+    pub diffuse: [f32; 3],
+    /// `d`, or `1 - Tr`
+    pub dissolve: f32,
+    /// `Ke`
+    pub emissive: [f32; 3],
+    /// `Ns`, used for the roughness when there's no `Pr`
+    pub specular_exponent: Option<f32>,
+    /// `Pr`, from the PBR extension to MTL
+    pub roughness: Option<f32>,
+    /// `Pm`
+    pub metallic: Option<f32>,
+    /// `map_Kd`
+    pub diffuse_map: Option<PathBuf>,
+    /// `map_Bump`, `bump` or `norm`, expected to hold a tangent space normal map
+    pub bump_map: Option<PathBuf>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        ObjMaterial {
+            name: String::new(),
+            diffuse: [0.8, 0.8, 0.8],
+            dissolve: 1.0,
+            emissive: [0.0, 0.0, 0.0],
+            specular_exponent: None,
+            roughness: None,
+            metallic: None,
+            diffuse_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+impl ObjMaterial {
+    /// Blinn-Phong exponent to GGX roughness, `sqrt(2 / (Ns + 2))`.
+    pub fn pbr_roughness(&self) -> f32 {
+        match (self.roughness, self.specular_exponent) {
+            (Some(roughness), _) => roughness,
+            (None, Some(ns)) => (2.0 / (ns.max(0.0) + 2.0)).sqrt(),
+            (None, None) => 1.0,
+        }
+    }
+}
+
+/// One group of faces sharing a material. Indices are 16 bit to match the
+/// pipelines, so big groups are split into several meshes.
+pub struct ObjMesh {
+    pub name: Option<String>,
+    pub material: Option<usize>,
+    pub vertices: Vec<VertexTexNormalTangent>,
+    pub indices: Vec<u16>,
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+// position, uv, normal indices into the file's attribute lists
+type IndexTriple = (usize, Option<usize>, Option<usize>);
+
+const MAX_VERTICES: usize = u16::MAX as usize + 1;
+
+struct MeshBuilder {
+    name: Option<String>,
+    material: Option<usize>,
+    vertices: Vec<VertexTexNormal>,
+    indices: Vec<u16>,
+    lookup: HashMap<IndexTriple, u16>,
+    // vertices without a `vn`, their normals are generated from the faces
+    missing_normals: Vec<bool>,
+}
+
+impl MeshBuilder {
+    fn new(name: Option<String>, material: Option<usize>) -> Self {
+        MeshBuilder {
+            name,
+            material,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            lookup: HashMap::new(),
+            missing_normals: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, triple: IndexTriple, attributes: &Attributes) -> u16 {
+        let vertices = &mut self.vertices;
+        let missing_normals = &mut self.missing_normals;
+        *self.lookup.entry(triple).or_insert_with(|| {
+            let (position, tex_coord, normal) = triple;
+            // OBJ has the uv origin at the bottom left, textures start at the top
+            let tex_coord = tex_coord.map_or([0.0, 0.0], |i| {
+                let [u, v] = attributes.tex_coords[i];
+                [u, 1.0 - v]
+            });
+            vertices.push(VertexTexNormal {
+                position: attributes.positions[position],
+                tex_coord,
+                normal: normal.map_or([0.0; 3], |i| attributes.normals[i]),
+            });
+            missing_normals.push(normal.is_none());
+            (vertices.len() - 1) as u16
+        })
+    }
+
+    fn triangle(&mut self, triangle: [IndexTriple; 3], attributes: &Attributes) {
+        for &triple in triangle.iter() {
+            let index = self.vertex(triple, attributes);
+            self.indices.push(index);
+        }
+    }
+
+    /// Area weighted face normals for the vertices the file gave none.
+    fn generate_normals(&mut self) {
+        if !self.missing_normals.contains(&true) {
+            return;
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [
+                cgmath::Vector3::from(self.vertices[triangle[0] as usize].position),
+                cgmath::Vector3::from(self.vertices[triangle[1] as usize].position),
+                cgmath::Vector3::from(self.vertices[triangle[2] as usize].position),
+            ];
+            let face_normal = (b - a).cross(c - a);
+            for &index in triangle {
+                if self.missing_normals[index as usize] {
+                    let normal = &mut self.vertices[index as usize].normal;
+                    *normal = (cgmath::Vector3::from(*normal) + face_normal).into();
+                }
+            }
+        }
+        for (vertex, _) in self
+            .vertices
+            .iter_mut()
+            .zip(&self.missing_normals)
+            .filter(|(_, missing)| **missing)
+        {
+            let normal = cgmath::Vector3::from(vertex.normal);
+            if normal != cgmath::Vector3::new(0.0, 0.0, 0.0) {
+                vertex.normal = cgmath::InnerSpace::normalize(normal).into();
+            }
+        }
+    }
+
+    /// Usually one mesh, more if the vertices split for their tangents
+    /// don't fit 16 bit indices anymore.
+    fn finish(mut self) -> Vec<ObjMesh> {
+        self.generate_normals();
+        let positions: Vec<_> = self.vertices.iter().map(|v| v.position).collect();
+        let normals: Vec<_> = self.vertices.iter().map(|v| v.normal).collect();
+        let tex_coords: Vec<_> = self.vertices.iter().map(|v| v.tex_coord).collect();
+        let indices: Vec<u32> = self.indices.iter().map(|&i| i as u32).collect();
+        let generated = tangents::generate_tangents(&positions, &normals, &tex_coords, &indices);
+        let vertices: Vec<_> = generated
+            .vertices
+            .iter()
+            .zip(&generated.tangents)
+            .map(|(&source, &tangent)| {
+                let vertex = &self.vertices[source as usize];
+                VertexTexNormalTangent {
+                    position: vertex.position,
+                    tex_coord: vertex.tex_coord,
+                    normal: vertex.normal,
+                    tangent,
+                }
+            })
+            .collect();
+        geometry::split_indices(vertices.len(), &generated.indices)
+            .into_iter()
+            .map(|(sources, indices)| ObjMesh {
+                name: self.name.clone(),
+                material: self.material,
+                vertices: sources.iter().map(|&i| vertices[i as usize]).collect(),
+                indices,
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Attributes {
+    positions: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    normals: Vec<[f32; 3]>,
+}
+
+fn parse_floats<'a>(
+    tokens: impl Iterator<Item = &'a str>,
+    min: usize,
+    out: &mut [f32],
+) -> Result<()> {
+    let mut count = 0;
+    for (value, token) in out.iter_mut().zip(tokens) {
+        *value = token
+            .parse()
+            .with_context(|| format!("invalid number {:?}", token))?;
+        count += 1;
+    }
+    if count < min {
+        bail!("expected at least {} numbers, found {}", min, count);
+    }
+    Ok(())
+}
+
+/// Resolves a 1 based, possibly negative (relative to the end) OBJ index.
+fn resolve_index(token: &str, len: usize) -> Result<usize> {
+    let index: i64 = token
+        .parse()
+        .with_context(|| format!("invalid index {:?}", token))?;
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        bail!("index {} out of range for {} elements", index, len);
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_vertex_ref(token: &str, attributes: &Attributes) -> Result<IndexTriple> {
+    let mut parts = token.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), attributes.positions.len())?;
+    let tex_coord = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, attributes.tex_coords.len())?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, attributes.normals.len())?),
+        _ => None,
+    };
+    Ok((position, tex_coord, normal))
+}
+
+/// Splits off the first whitespace separated token.
+fn next_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[..end], &text[end..])
+}
+
+/// Texture statements may carry options (`-bm 0.5`, `-s 1 1 1`, ...) before
+/// the file name. Everything after the options is the file name, which may
+/// contain spaces.
+fn parse_map(rest: &str, base: &Path) -> Result<PathBuf> {
+    let mut rest = rest.trim();
+    while rest.starts_with('-') {
+        let (option, after) = next_token(rest);
+        rest = after;
+        // `-o`, `-s` and `-t` take up to three numbers, the others a fixed count
+        let (min, max) = match option {
+            "-blendu" | "-blendv" | "-bm" | "-boost" | "-cc" | "-clamp" | "-imfchan"
+            | "-texres" | "-type" => (1, 1),
+            "-mm" => (2, 2),
+            "-o" | "-s" | "-t" => (1, 3),
+            _ => bail!("unknown texture option {:?}", option),
+        };
+        for count in 0..max {
+            let (argument, after) = next_token(rest);
+            if count >= min && argument.parse::<f32>().is_err() {
+                break;
+            }
+            if argument.is_empty() {
+                bail!("missing argument for {}", option);
+            }
+            rest = after;
+        }
+        rest = rest.trim_start();
+    }
+    if rest.is_empty() {
+        bail!("missing texture file name");
+    }
+    Ok(base.join(rest))
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<Vec<ObjMaterial>> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    parse_mtl(&source, path)
+}
+
+/// Parses the contents of the MTL file at `path`, texture paths are relative
+/// to its directory.
+fn parse_mtl(source: &str, path: &Path) -> Result<Vec<ObjMaterial>> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        let (keyword, rest) = match line.find(char::is_whitespace) {
+            Some(split) => (&line[..split], line[split..].trim()),
+            None => (line, ""),
+        };
+        let error = |err: anyhow::Error| err.context(format!("{}:{}", path.display(), number + 1));
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial {
+                name: rest.to_string(),
+                ..ObjMaterial::default()
+            });
+            continue;
+        }
+        if keyword.is_empty() || keyword.starts_with('#') {
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => return Err(error(anyhow!("{} before newmtl", keyword))),
+        };
+        let tokens = rest.split_whitespace();
+        let mut value = [0.0];
+        match keyword {
+            "Kd" => parse_floats(tokens, 3, &mut material.diffuse).map_err(error)?,
+            "Ke" => parse_floats(tokens, 3, &mut material.emissive).map_err(error)?,
+            "d" => {
+                parse_floats(tokens, 1, &mut value).map_err(error)?;
+                material.dissolve = value[0];
+            }
+            "Tr" => {
+                parse_floats(tokens, 1, &mut value).map_err(error)?;
+                material.dissolve = 1.0 - value[0];
+            }
+            "Ns" => {
+                parse_floats(tokens, 1, &mut value).map_err(error)?;
+                material.specular_exponent = Some(value[0]);
+            }
+            "Pr" => {
+                parse_floats(tokens, 1, &mut value).map_err(error)?;
+                material.roughness = Some(value[0]);
+            }
+            "Pm" => {
+                parse_floats(tokens, 1, &mut value).map_err(error)?;
+                material.metallic = Some(value[0]);
+            }
+            "map_Kd" => material.diffuse_map = Some(parse_map(rest, base).map_err(error)?),
+            "map_Bump" | "map_bump" | "bump" | "norm" => {
+                material.bump_map = Some(parse_map(rest, base).map_err(error)?)
+            }
+            // Ka, Ks, Ni, illum and the other maps have no PBR counterpart
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// Loads an OBJ file and the MTL libraries it references. Polygons are fan
+/// triangulated, so they're expected to be convex.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<ObjModel> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    parse_obj(&source, path)
+}
+
+/// Parses the contents of the OBJ file at `path`, MTL libraries are loaded
+/// relative to its directory.
+fn parse_obj(source: &str, path: &Path) -> Result<ObjModel> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut attributes = Attributes::default();
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    let mut meshes = Vec::new();
+    let mut builder = MeshBuilder::new(None, None);
+
+    // a new group or material starts a new mesh, empty ones are dropped
+    let mut switch = |builder: &mut MeshBuilder, name, material| {
+        let next = MeshBuilder::new(name, material);
+        let done = std::mem::replace(builder, next);
+        if !done.indices.is_empty() {
+            meshes.extend(done.finish());
+        }
+    };
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        let (keyword, rest) = match line.find(char::is_whitespace) {
+            Some(split) => (&line[..split], line[split..].trim()),
+            None => (line, ""),
+        };
+        let error = |err: anyhow::Error| err.context(format!("{}:{}", path.display(), number + 1));
+        let tokens = rest.split_whitespace();
+        match keyword {
+            "v" => {
+                let mut position = [0.0; 3];
+                parse_floats(tokens, 3, &mut position).map_err(error)?;
+                attributes.positions.push(position);
+            }
+            "vt" => {
+                let mut tex_coord = [0.0; 2];
+                parse_floats(tokens, 1, &mut tex_coord).map_err(error)?;
+                attributes.tex_coords.push(tex_coord);
+            }
+            "vn" => {
+                let mut normal = [0.0; 3];
+                parse_floats(tokens, 3, &mut normal).map_err(error)?;
+                attributes.normals.push(normal);
+            }
+            "f" => {
+                let face = tokens
+                    .map(|token| parse_vertex_ref(token, &attributes))
+                    .collect::<Result<Vec<_>>>()
+                    .map_err(error)?;
+                if face.len() < 3 {
+                    return Err(error(anyhow!("face with {} vertices", face.len())));
+                }
+                for i in 1..face.len() - 1 {
+                    if builder.vertices.len() + 3 > MAX_VERTICES {
+                        let (name, material) = (builder.name.clone(), builder.material);
+                        switch(&mut builder, name, material);
+                    }
+                    builder.triangle([face[0], face[i], face[i + 1]], &attributes);
+                }
+            }
+            "o" | "g" => {
+                let name = Some(rest.to_string()).filter(|name| !name.is_empty());
+                let material = builder.material;
+                switch(&mut builder, name, material);
+            }
+            "usemtl" => {
+                let material = materials
+                    .iter()
+                    .position(|material| material.name == rest)
+                    .ok_or_else(|| error(anyhow!("unknown material {:?}", rest)))?;
+                let name = builder.name.clone();
+                switch(&mut builder, name, Some(material));
+            }
+            "mtllib" => {
+                for file in tokens {
+                    materials.extend(load_mtl(base.join(file)).map_err(error)?);
+                }
+            }
+            // smoothing groups, lines, points and free-form geometry
+            _ => {}
+        }
+    }
+    switch(&mut builder, None, None);
+
+    Ok(ObjModel { meshes, materials })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> ObjModel {
+        parse_obj(source, Path::new("assets/test.obj")).unwrap()
+    }
+
+    fn positions(mesh: &ObjMesh) -> Vec<[f32; 3]> {
+        mesh.vertices.iter().map(|v| v.position).collect()
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0.5 2 0\nv 0 1 0\n\
+             f 1 2 3 4 5\n",
+        );
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        assert_eq!(mesh.vertices.len(), 5);
+        // no `vn`, so the normals come from the faces
+        assert!(mesh.vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn repeated_index_triples_share_a_vertex() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+             f 1/1/1 2/2/1 3/3/1\nf 1/1/1 3/3/1 4/4/1\n\
+             f 1/1 2/2/1 3/3/1\n",
+        );
+        let mesh = &model.meshes[0];
+        // the third face uses position 1 without its normal, which is a new vertex
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 4, 1, 2]);
+        assert_eq!(mesh.vertices[4].position, mesh.vertices[0].position);
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let model = parse(
+            "v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\n\
+             f -3//-1 -2//-1 -1//-1\n",
+        );
+        let mesh = &model.meshes[0];
+        assert_eq!(
+            positions(mesh),
+            vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        );
+        assert!(parse_obj("v 0 0 0\nf -2 1 1\n", Path::new("bad.obj")).is_err());
+        assert!(parse_obj("v 0 0 0\nf 0 1 1\n", Path::new("bad.obj")).is_err());
+    }
+
+    #[test]
+    fn tex_coords_are_flipped_to_a_top_left_origin() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0.25\nvt 0 1\n\
+             f 1/1 2/2 3/3\n",
+        );
+        let tex_coords: Vec<_> = model.meshes[0]
+            .vertices
+            .iter()
+            .map(|v| v.tex_coord)
+            .collect();
+        assert_eq!(tex_coords, vec![[0.0, 1.0], [1.0, 0.75], [0.0, 0.0]]);
+    }
+
+    #[test]
+    fn groups_and_materials_start_new_meshes() {
+        let dir = std::env::temp_dir().join(format!("skinning-obj-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("colors.mtl"),
+            "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n",
+        )
+        .unwrap();
+        let parsed = parse_obj(
+            "mtllib colors.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             usemtl blue\nf 1 2 3\ng second\nf 1 2 3\nusemtl red\nf 1 2 3\n\
+             g empty\ng last\nf 1 2 3\n",
+            &dir.join("test.obj"),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let model = parsed.unwrap();
+        assert_eq!(model.materials.len(), 2);
+        let meshes: Vec<_> = model
+            .meshes
+            .iter()
+            .map(|mesh| (mesh.name.as_deref(), mesh.material))
+            .collect();
+        assert_eq!(
+            meshes,
+            vec![
+                (None, Some(1)),
+                (Some("second"), Some(1)),
+                (Some("second"), Some(0)),
+                (Some("last"), Some(0)),
+            ]
+        );
+        assert!(parse_obj("usemtl missing\n", Path::new("bad.obj")).is_err());
+    }
+
+    #[test]
+    fn mtl_values_and_maps() {
+        let materials = parse_mtl(
+            "# comment\nnewmtl metal\nKd 0.5 0.25 1\nTr 0.25\nNs 98\nPm 1\n\
+             map_Kd -s 2 2 -clamp on textures/base color.png\n\
+             map_Bump -bm 0.5 normal.png\n\
+             newmtl plain\nd 0.5\nPr 0.3\n",
+            Path::new("assets/test.mtl"),
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 2);
+        let metal = &materials[0];
+        assert_eq!(metal.name, "metal");
+        assert_eq!(metal.diffuse, [0.5, 0.25, 1.0]);
+        assert_eq!(metal.dissolve, 0.75);
+        assert_eq!(metal.metallic, Some(1.0));
+        assert_eq!(metal.pbr_roughness(), 0.02f32.sqrt());
+        assert_eq!(
+            metal.diffuse_map,
+            Some(PathBuf::from("assets/textures/base color.png"))
+        );
+        assert_eq!(metal.bump_map, Some(PathBuf::from("assets/normal.png")));
+        let plain = &materials[1];
+        assert_eq!(plain.dissolve, 0.5);
+        assert_eq!(plain.pbr_roughness(), 0.3);
+        assert_eq!(plain.diffuse_map, None);
+    }
+
+    #[test]
+    fn map_options_are_skipped() {
+        let base = Path::new("");
+        let map = |rest| parse_map(rest, base).unwrap();
+        assert_eq!(map("plain.png"), PathBuf::from("plain.png"));
+        assert_eq!(map("-o 0.5 file.png"), PathBuf::from("file.png"));
+        assert_eq!(
+            map("-o 1 2 3 -mm 0 1 my file.png"),
+            PathBuf::from("my file.png")
+        );
+        // a numeric name after the optional arguments is still a name
+        assert_eq!(map("-s 1 1 1 2.png"), PathBuf::from("2.png"));
+        assert!(parse_map("-bm 0.5", base).is_err());
+        assert!(parse_map("-xyz file.png", base).is_err());
+        assert!(parse_map("", base).is_err());
+    }
+}
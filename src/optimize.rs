@@ -0,0 +1,340 @@
+use std::{collections::HashMap, fmt};
+
+/// Size of the FIFO cache `acmr` simulates, a common post-transform cache
+/// size on desktop GPUs.
+const STATS_CACHE_SIZE: usize = 16;
+
+/// LRU cache size the vertex scores of the reordering are tuned for.
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct OptimizeStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+impl fmt::Display for OptimizeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} vertices, ACMR {:.3} -> {:.3}",
+            self.vertices_before, self.vertices_after, self.acmr_before, self.acmr_after
+        )
+    }
+}
+
+/// Average cache miss ratio, transformed vertices per triangle with a FIFO
+/// cache. 0.5 is the ideal for a regular grid, 3 means no reuse at all.
+pub fn acmr(indices: &[u16]) -> f32 {
+    if indices.len() < 3 {
+        return 0.0;
+    }
+    let mut cache = std::collections::VecDeque::with_capacity(STATS_CACHE_SIZE);
+    let mut misses = 0;
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == STATS_CACHE_SIZE {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+/// Merges bitwise identical vertices.
+pub fn weld<V: bytemuck::Pod>(vertices: &[V], indices: &[u16]) -> (Vec<V>, Vec<u16>) {
+    let mut welded = Vec::with_capacity(vertices.len());
+    let mut lookup: HashMap<&[u8], u16> = HashMap::with_capacity(vertices.len());
+    let remap: Vec<u16> = vertices
+        .iter()
+        .map(|vertex| {
+            *lookup.entry(bytemuck::bytes_of(vertex)).or_insert_with(|| {
+                welded.push(*vertex);
+                (welded.len() - 1) as u16
+            })
+        })
+        .collect();
+    let indices = indices.iter().map(|&i| remap[i as usize]).collect();
+    (welded, indices)
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        // the last triangle's vertices get a fixed score, so the next one
+        // doesn't just reuse the same edge
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+    // favour vertices with few triangles left, so they leave the working set
+    let valence_boost =
+        VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+    cache_score + valence_boost
+}
+
+/// Reorders triangles for the post-transform vertex cache, after Tom
+/// Forsyth's "Linear-Speed Vertex Cache Optimisation".
+pub fn optimize_vertex_cache(indices: &[u16], vertex_count: usize) -> Vec<u16> {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return indices.to_vec();
+    }
+
+    // triangles using each vertex, the first `remaining` are not emitted yet
+    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &vertex in corners {
+            adjacency[vertex as usize].push(triangle);
+        }
+    }
+    let mut remaining: Vec<usize> = adjacency.iter().map(Vec::len).collect();
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut scores: Vec<f32> = (0..vertex_count)
+        .map(|v| vertex_score(None, remaining[v]))
+        .collect();
+    let triangle_score = |scores: &[f32], t: usize| -> f32 {
+        (0..3).map(|c| scores[indices[t * 3 + c] as usize]).sum()
+    };
+    let mut triangle_scores: Vec<f32> = (0..triangle_count)
+        .map(|t| triangle_score(&scores, t))
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut cache: Vec<u16> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(indices.len());
+    let mut next_unemitted = 0;
+    let mut best: Option<usize> = None;
+
+    for _ in 0..triangle_count {
+        let triangle = match best {
+            Some(triangle) => triangle,
+            None => {
+                // nothing in the cache touches a live triangle, restart from
+                // the next one in the original order
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+        emitted[triangle] = true;
+
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        output.extend_from_slice(corners);
+        for &vertex in corners {
+            let live = &mut adjacency[vertex as usize];
+            let count = remaining[vertex as usize];
+            if let Some(position) = live[..count].iter().position(|&t| t == triangle) {
+                live.swap(position, count - 1);
+                remaining[vertex as usize] -= 1;
+            }
+        }
+
+        // most recently used first, the evicted tail keeps being rescored
+        let mut new_cache: Vec<u16> = Vec::with_capacity(CACHE_SIZE + 3);
+        for &vertex in corners.iter().chain(&cache) {
+            if !new_cache.contains(&vertex) {
+                new_cache.push(vertex);
+            }
+        }
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            cache_position[vertex as usize] = if position < CACHE_SIZE {
+                Some(position)
+            } else {
+                None
+            };
+            scores[vertex as usize] =
+                vertex_score(cache_position[vertex as usize], remaining[vertex as usize]);
+        }
+
+        best = None;
+        let mut best_score = -1.0;
+        for &vertex in &new_cache {
+            let vertex = vertex as usize;
+            for &t in &adjacency[vertex][..remaining[vertex]] {
+                triangle_scores[t] = triangle_score(&scores, t);
+                if triangle_scores[t] > best_score {
+                    best_score = triangle_scores[t];
+                    best = Some(t);
+                }
+            }
+        }
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+    output
+}
+
+/// Renumbers vertices in the order the index buffer first uses them, so the
+/// vertex fetch walks memory linearly. Unreferenced vertices are dropped.
+pub fn optimize_vertex_fetch<V: Copy>(vertices: &[V], indices: &[u16]) -> (Vec<V>, Vec<u16>) {
+    let mut remap: Vec<Option<u16>> = vec![None; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            *remap[index as usize].get_or_insert_with(|| {
+                reordered.push(vertices[index as usize]);
+                (reordered.len() - 1) as u16
+            })
+        })
+        .collect();
+    (reordered, indices)
+}
+
+/// Welds, reorders for the vertex cache and then for fetch locality. Expects
+/// an indexed triangle list.
+pub fn optimize_mesh<V: bytemuck::Pod>(
+    vertices: &[V],
+    indices: &[u16],
+) -> (Vec<V>, Vec<u16>, OptimizeStats) {
+    let acmr_before = acmr(indices);
+    let (welded, indices) = weld(vertices, indices);
+    let indices = optimize_vertex_cache(&indices, welded.len());
+    let (optimized, indices) = optimize_vertex_fetch(&welded, &indices);
+    let stats = OptimizeStats {
+        vertices_before: vertices.len(),
+        vertices_after: optimized.len(),
+        acmr_before,
+        acmr_after: acmr(&indices),
+    };
+    (optimized, indices, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Row by row grid of `size` x `size` quads, with every triangle's
+    /// vertices duplicated if `split`.
+    fn grid(size: u16, split: bool) -> (Vec<[f32; 3]>, Vec<u16>) {
+        let vertex = |x: u16, y: u16| y * (size + 1) + x;
+        let positions: Vec<[f32; 3]> = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| [x as f32, y as f32, 0.0]))
+            .collect();
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let (a, b) = (vertex(x, y), vertex(x + 1, y));
+                let (c, d) = (vertex(x, y + 1), vertex(x + 1, y + 1));
+                indices.extend_from_slice(&[a, b, d, a, d, c]);
+            }
+        }
+        if !split {
+            return (positions, indices);
+        }
+        let vertices = indices.iter().map(|&i| positions[i as usize]).collect();
+        (vertices, (0..indices.len() as u16).collect())
+    }
+
+    /// The triangles as corner positions, each rotated to start at its
+    /// smallest corner so the winding is kept, in a stable order.
+    fn triangles(vertices: &[[f32; 3]], indices: &[u16]) -> Vec<[[i32; 3]; 3]> {
+        let mut triangles: Vec<_> = indices
+            .chunks_exact(3)
+            .map(|corners| {
+                let mut triangle = [[0; 3]; 3];
+                for (corner, &index) in triangle.iter_mut().zip(corners) {
+                    let [x, y, z] = vertices[index as usize];
+                    *corner = [x as i32, y as i32, z as i32];
+                }
+                let first = (0..3).min_by_key(|&i| triangle[i]).unwrap();
+                triangle.rotate_left(first);
+                triangle
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn weld_merges_identical_vertices() {
+        let (vertices, indices) = grid(4, true);
+        let (welded, welded_indices) = weld(&vertices, &indices);
+        assert_eq!(vertices.len(), 4 * 4 * 6);
+        assert_eq!(welded.len(), 5 * 5);
+        assert_eq!(
+            triangles(&welded, &welded_indices),
+            triangles(&vertices, &indices)
+        );
+        // nothing left to merge
+        let (again, again_indices) = weld(&welded, &welded_indices);
+        assert_eq!(again, welded);
+        assert_eq!(again_indices, welded_indices);
+    }
+
+    #[test]
+    fn every_step_keeps_the_triangles() {
+        let (vertices, indices) = grid(12, true);
+        let expected = triangles(&vertices, &indices);
+
+        let (welded, indices) = weld(&vertices, &indices);
+        assert_eq!(triangles(&welded, &indices), expected);
+        let indices = optimize_vertex_cache(&indices, welded.len());
+        assert_eq!(triangles(&welded, &indices), expected);
+        let (fetched, indices) = optimize_vertex_fetch(&welded, &indices);
+        assert_eq!(triangles(&fetched, &indices), expected);
+
+        // vertices are numbered in the order the indices first use them
+        let mut next = 0;
+        for &index in &indices {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, fetched.len());
+    }
+
+    #[test]
+    fn unreferenced_vertices_are_dropped() {
+        let vertices = [
+            [0.0, 0.0, 0.0],
+            [9.0, 9.0, 9.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let (fetched, indices) = optimize_vertex_fetch(&vertices, &[3, 0, 2]);
+        assert_eq!(
+            fetched,
+            vec![[0.0, 1.0, 0.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]
+        );
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn acmr_improves_on_a_grid() {
+        let (vertices, indices) = grid(40, false);
+        let optimized = optimize_vertex_cache(&indices, vertices.len());
+        let (before, after) = (acmr(&indices), acmr(&optimized));
+        assert!(after <= before, "ACMR went from {} to {}", before, after);
+        // rows wider than the cache don't reuse the previous row at all
+        assert!(before > 1.0, "ACMR before {}", before);
+        assert!(after < 0.8, "ACMR after {}", after);
+
+        let (_, _, stats) = optimize_mesh(&vertices, &indices);
+        assert_eq!(stats.vertices_after, vertices.len());
+        assert!(stats.acmr_after <= stats.acmr_before);
+    }
+
+    #[test]
+    fn acmr_of_unshared_triangles_is_three() {
+        assert_eq!(acmr(&[0, 1, 2, 3, 4, 5]), 3.0);
+        assert_eq!(acmr(&[0, 1, 2, 0, 2, 3]), 2.0);
+        assert_eq!(acmr(&[]), 0.0);
+    }
+}
@@ -2,8 +2,9 @@ use crate::{
     camera::Camera,
     geometry::Geometry,
     pipelines,
-    render::Render,
+    render::{ColorTarget, Render},
     render_types::{MvpUniforms, VertexDesc, VertexPlain},
+    staging::StagingBelt,
     texture,
 };
 
@@ -32,6 +33,7 @@ impl EquirectLayout {
         &self,
         device: &wgpu::Device,
         sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
         vs_module: &wgpu::ShaderModule,
         fs_module: &wgpu::ShaderModule,
     ) -> wgpu::RenderPipeline {
@@ -64,7 +66,7 @@ impl EquirectLayout {
                 index_format: wgpu::IndexFormat::Uint16,
                 vertex_buffers: &[VertexPlain::desc()],
             },
-            sample_count: 1,
+            sample_count,
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
         })
@@ -74,6 +76,7 @@ impl EquirectLayout {
 pub struct Equirect {
     pub pipeline: wgpu::RenderPipeline,
     pub layout: EquirectLayout,
+    modules: (wgpu::ShaderModule, wgpu::ShaderModule),
 }
 
 impl Equirect {
@@ -83,9 +86,26 @@ impl Equirect {
         let (vs_module, fs_module) = pipelines::compile_modules(&device, (vs_src, fs_src), "pbr");
 
         let layout = EquirectLayout::new(&device);
-        let pipeline = layout.create_render_pipeline(&device, &sc_desc, &vs_module, &fs_module);
+        let pipeline = layout.create_render_pipeline(&device, &sc_desc, 1, &vs_module, &fs_module);
 
-        Equirect { layout, pipeline }
+        Equirect {
+            layout,
+            pipeline,
+            modules: (vs_module, fs_module),
+        }
+    }
+
+    /// Rebuilds the pipeline for a target with `sample_count` samples.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) {
+        let (vs_module, fs_module) = &self.modules;
+        self.pipeline =
+            self.layout
+                .create_render_pipeline(device, sc_desc, sample_count, vs_module, fs_module);
     }
 }
 
@@ -97,19 +117,22 @@ pub struct SimpleRenderPass<'a> {
 impl Render for SimpleRenderPass<'_> {
     fn render(
         &self,
-        _device: &wgpu::Device,
+        device: &wgpu::Device,
         pipeline: &wgpu::RenderPipeline,
-        frame: &wgpu::SwapChainOutput,
+        target: &ColorTarget,
+        staging: &mut StagingBelt,
         encoder: &mut wgpu::CommandEncoder,
     ) {
+        staging.write(
+            device,
+            encoder,
+            &self.state.uniform_buffer,
+            0,
+            &[self.state.uniforms],
+        );
+
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                attachment: &frame.view,
-                resolve_target: None,
-                load_op: wgpu::LoadOp::Clear,
-                store_op: wgpu::StoreOp::Store,
-                clear_color: self.clear_color,
-            }],
+            color_attachments: &[target.attachment(self.clear_color)],
             depth_stencil_attachment: None,
         });
 
@@ -156,33 +179,9 @@ impl EquirectState {
         }
     }
 
-    pub fn update_uniforms(
-        &mut self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        camera: &Camera,
-        model_angle: f32,
-    ) {
+    /// The uniforms are uploaded when the pass is drawn.
+    pub fn update_uniforms(&mut self, camera: &Camera, model_angle: f32) {
         self.uniforms.update_view_proj(&camera);
         self.uniforms.update_model_rotation(model_angle);
-
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("update encoder"),
-        });
-
-        let staging_buffer = device.create_buffer_with_data(
-            bytemuck::cast_slice(&[self.uniforms]),
-            wgpu::BufferUsage::COPY_SRC,
-        );
-
-        encoder.copy_buffer_to_buffer(
-            &staging_buffer,
-            0,
-            &self.uniform_buffer,
-            0,
-            std::mem::size_of::<MvpUniforms>() as wgpu::BufferAddress,
-        );
-
-        queue.submit(&[encoder.finish()]);
     }
 }
@@ -1,3 +1,4 @@
+pub mod blit;
 pub mod equirect;
 pub mod pbr;
 pub mod simple;
@@ -1,14 +1,31 @@
 use crate::{
+    bounds::Bounds,
+    bvh::Ray,
     camera::Camera,
-    geometry::Geometry,
-    pipelines,
-    render::Render,
+    geometry::{self, Geometry, MeshData, UploadOptions},
+    id_buffer::{IdBuffer, ID_INSTANCE, ID_PRIMITIVE},
+    model, obj, pipelines,
+    render_graph::{GraphPass, PassAttachments, RenderGraph, TransientDesc, TARGET},
     render_types::{
-        MaterialInfoRaw, MvpUniforms, PbrFragmentUniforms, TransformRaw, VertexDesc,
-        VertexTexNormal,
+        MaterialInfoRaw, MvpUniforms, OutlineUniforms, PbrFragmentUniforms, PbrMaterialUniforms,
+        TransformRaw, VertexDesc, VertexTexNormalTangent,
     },
+    staging::StagingBelt,
     texture::Texture,
 };
+use std::ops::Range;
+
+/// Depth and stencil transient of the render graph.
+pub const DEPTH: &str = "pbr_depth";
+/// Depth of the id pass drawn apart from a multisampled frame.
+pub const ID_DEPTH: &str = "pbr_id_depth";
+
+/// Largest on screen deviation, in pixels, a level of detail may have.
+const LOD_PIXEL_ERROR: f32 = 1.0;
+
+/// Stencil value covered by the selected instance, the outline is drawn
+/// around it where the value is missing.
+const SELECTED_STENCIL: u32 = 1;
 
 pub fn clamp(value: f32, min: f32, max: f32) -> f32 {
     let mut x = value;
@@ -27,12 +44,211 @@ pub struct Material {
     pub ambient_occlusion: Texture,
     pub normals: Texture,
     pub metallic: Texture,
+    pub emissive: Texture,
+    pub clearcoat: Texture,
+    pub clearcoat_roughness: Texture,
+    pub uniforms: PbrMaterialUniforms,
+    pub uniform_buffer: wgpu::Buffer,
+}
+
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        // albedo, roughness, ambient occlusion, normals, metallic, emissive,
+        // clearcoat, clearcoat roughness
+        textures: [Texture; 8],
+        uniforms: PbrMaterialUniforms,
+    ) -> Self {
+        let [albedo, roughness, ambient_occlusion, normals, metallic, emissive, clearcoat, clearcoat_roughness] =
+            textures;
+        Material {
+            albedo,
+            roughness,
+            ambient_occlusion,
+            normals,
+            metallic,
+            emissive,
+            clearcoat,
+            clearcoat_roughness,
+            uniforms,
+            uniform_buffer: device.create_buffer_with_data(
+                bytemuck::cast_slice(&[uniforms]),
+                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            ),
+        }
+    }
+
+    /// Maps an MTL material onto the PBR inputs, `Kd` and the scalar factors
+    /// go through the uniforms and inputs without a map get a 1x1 texture.
+    pub fn from_obj(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material: &obj::ObjMaterial,
+    ) -> anyhow::Result<Self> {
+        let load = |map: &Option<std::path::PathBuf>, fallback, is_normal| {
+            let (texture, cmd_buffer) = match map {
+                Some(path) => Texture::from_bytes(&device, &std::fs::read(path)?, is_normal)?,
+                None => Texture::from_color(&device, fallback, is_normal)?,
+            };
+            queue.submit(&[cmd_buffer]);
+            Ok::<_, anyhow::Error>(texture)
+        };
+        const WHITE: [u8; 4] = [255, 255, 255, 255];
+        const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+        Ok(Material::new(
+            &device,
+            [
+                load(&material.diffuse_map, WHITE, false)?,
+                load(&None, WHITE, false)?,
+                load(&None, WHITE, false)?,
+                load(&material.bump_map, FLAT_NORMAL, true)?,
+                load(&None, WHITE, false)?,
+                load(&None, WHITE, false)?,
+                load(&None, WHITE, false)?,
+                load(&None, WHITE, false)?,
+            ],
+            PbrMaterialUniforms::from(material),
+        ))
+    }
+
+    /// Maps a glTF material onto the PBR inputs. The metallic roughness
+    /// texture takes the roughness slot, the shader reads it packed the glTF
+    /// way. Image URIs are relative to `base_dir`. Only `TEXCOORD_0` is
+    /// loaded and the clearcoat uses the geometry normal, materials asking
+    /// for more are rejected rather than drawn wrong.
+    pub fn from_gltf(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        model: &model::Model,
+        material: &model::Material,
+        base_dir: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let load = |texture: &Option<model::TextureRef>, fallback, is_linear| {
+            let (mut texture, sampler, cmd_buffer) = match texture {
+                Some(texture) => {
+                    let texture = &model.textures[texture.texture];
+                    let image = &model.images[texture.image];
+                    let sampler = texture.sampler.map(|sampler| &model.samplers[sampler]);
+                    let (texture, cmd_buffer) =
+                        Texture::from_bytes(device, &image.bytes(base_dir)?, is_linear)?;
+                    (texture, sampler, cmd_buffer)
+                }
+                None => {
+                    let (texture, cmd_buffer) = Texture::from_color(device, fallback, is_linear)?;
+                    (texture, None, cmd_buffer)
+                }
+            };
+            queue.submit(&[cmd_buffer]);
+            texture.sampler = gltf_sampler(device, sampler);
+            Ok::<_, anyhow::Error>(texture)
+        };
+        const WHITE: [u8; 4] = [255, 255, 255, 255];
+        const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+        let name = material.name.as_deref().unwrap_or("unnamed");
+        let clearcoat = material.clearcoat.as_ref();
+        let textures = [
+            &material.base_color_texture,
+            &material.metallic_roughness_texture,
+            &material.occlusion_texture,
+            &material.normal_texture,
+            &material.emissive_texture,
+            clearcoat.map_or(&None, |c| &c.texture),
+            clearcoat.map_or(&None, |c| &c.roughness_texture),
+            clearcoat.map_or(&None, |c| &c.normal_texture),
+        ];
+        for texture in textures.iter().filter_map(|texture| texture.as_ref()) {
+            if texture.tex_coord != 0 {
+                anyhow::bail!(
+                    "Material {}: textures on TEXCOORD_{} aren't supported, only TEXCOORD_0",
+                    name,
+                    texture.tex_coord
+                );
+            }
+        }
+        if clearcoat.is_some_and(|c| c.normal_texture.is_some()) {
+            anyhow::bail!(
+                "Material {}: clearcoat normal textures aren't supported",
+                name
+            );
+        }
+
+        Ok(Material::new(
+            device,
+            [
+                load(&material.base_color_texture, WHITE, false)?,
+                load(&material.metallic_roughness_texture, WHITE, true)?,
+                load(&material.occlusion_texture, WHITE, true)?,
+                load(&material.normal_texture, FLAT_NORMAL, true)?,
+                load(&None, WHITE, true)?,
+                load(&material.emissive_texture, WHITE, false)?,
+                load(clearcoat.map_or(&None, |c| &c.texture), WHITE, true)?,
+                load(
+                    clearcoat.map_or(&None, |c| &c.roughness_texture),
+                    WHITE,
+                    true,
+                )?,
+            ],
+            PbrMaterialUniforms::from(material),
+        ))
+    }
+}
+
+/// glTF samplers repeat unless told otherwise. The images have no mip
+/// levels, so only the minification filter's base part is used.
+fn gltf_sampler(device: &wgpu::Device, sampler: Option<&model::Sampler>) -> wgpu::Sampler {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |wrap| match wrap {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let (wrap_s, wrap_t, mag_filter, min_filter) = match sampler {
+        Some(sampler) => (
+            sampler.wrap_s,
+            sampler.wrap_t,
+            sampler.mag_filter,
+            sampler.min_filter,
+        ),
+        None => (WrappingMode::Repeat, WrappingMode::Repeat, None, None),
+    };
+    let mag_filter = match mag_filter {
+        Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+        Some(MagFilter::Linear) | None => wgpu::FilterMode::Linear,
+    };
+    let min_filter = match min_filter {
+        Some(MinFilter::Linear)
+        | Some(MinFilter::LinearMipmapNearest)
+        | Some(MinFilter::LinearMipmapLinear) => wgpu::FilterMode::Linear,
+        _ => wgpu::FilterMode::Nearest,
+    };
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: address_mode(wrap_s),
+        address_mode_v: address_mode(wrap_t),
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter,
+        min_filter,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        lod_min_clamp: -100.0,
+        lod_max_clamp: 100.0,
+        compare: wgpu::CompareFunction::Always,
+    })
+}
+
+pub struct Mesh {
+    pub geometry: Geometry,
+    pub material: usize,
 }
 
 pub struct PbrLayout {
     texture_layout: wgpu::BindGroupLayout,
     uniform_layout: wgpu::BindGroupLayout,
     pipeline_layout: wgpu::PipelineLayout,
+    outline_layout: wgpu::BindGroupLayout,
+    outline_pipeline_layout: wgpu::PipelineLayout,
+    id_pipeline_layout: wgpu::PipelineLayout,
 }
 
 impl PbrLayout {
@@ -99,6 +315,57 @@ impl PbrLayout {
                     visibility: wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Sampler { comparison: false },
                 },
+                // material params
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                // emissive
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Uint,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
+                // clearcoat
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Uint,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
+                // clearcoat roughness
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Uint,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
             ],
             label: Some("texture_bind_group_layout"),
         })
@@ -150,11 +417,75 @@ impl PbrLayout {
             bind_group_layouts: &[&uniform_layout, &texture_layout],
         });
 
+        let outline_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+            }],
+            label: Some("outline_bind_group_layout"),
+        });
+        let outline_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[&uniform_layout, &outline_layout],
+            });
+        let id_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&uniform_layout],
+        });
+
         PbrLayout {
             texture_layout,
             uniform_layout,
             pipeline_layout,
+            outline_layout,
+            outline_pipeline_layout,
+            id_pipeline_layout,
+        }
+    }
+
+    fn create_outline_bind_group(
+        &self,
+        device: &wgpu::Device,
+        outline_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.outline_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: outline_buffer,
+                    range: 0..std::mem::size_of::<OutlineUniforms>() as wgpu::BufferAddress,
+                },
+            }],
+            label: Some("outline_bind_group"),
+        })
+    }
+
+    /// The colour target followed by the instance and primitive id targets.
+    /// The id targets can't be resolved, so multisampled passes only have
+    /// the colour target and `IdRenderPass` draws the ids.
+    fn color_states(
+        sc_desc: &wgpu::SwapChainDescriptor,
+        id_write_mask: wgpu::ColorWrite,
+        sample_count: u32,
+    ) -> Vec<wgpu::ColorStateDescriptor> {
+        let mut states = vec![wgpu::ColorStateDescriptor {
+            format: sc_desc.format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }];
+        if sample_count == 1 {
+            let id_state = wgpu::ColorStateDescriptor {
+                format: IdBuffer::FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: id_write_mask,
+            };
+            states.push(id_state.clone());
+            states.push(id_state);
         }
+        states
     }
 
     fn create_uniform_bind_group(
@@ -249,6 +580,43 @@ impl PbrLayout {
                     binding: 7,
                     resource: wgpu::BindingResource::Sampler(&material.normals.sampler),
                 },
+                // material params
+                wgpu::Binding {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &material.uniform_buffer,
+                        range: 0..std::mem::size_of::<PbrMaterialUniforms>() as wgpu::BufferAddress,
+                    },
+                },
+                // emissive
+                wgpu::Binding {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&material.emissive.view),
+                },
+                wgpu::Binding {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&material.emissive.sampler),
+                },
+                // clearcoat
+                wgpu::Binding {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&material.clearcoat.view),
+                },
+                wgpu::Binding {
+                    binding: 12,
+                    resource: wgpu::BindingResource::Sampler(&material.clearcoat.sampler),
+                },
+                // clearcoat roughness
+                wgpu::Binding {
+                    binding: 13,
+                    resource: wgpu::BindingResource::TextureView(
+                        &material.clearcoat_roughness.view,
+                    ),
+                },
+                wgpu::Binding {
+                    binding: 14,
+                    resource: wgpu::BindingResource::Sampler(&material.clearcoat_roughness.sampler),
+                },
             ],
             label: Some("bind_group"),
         })
@@ -258,9 +626,16 @@ impl PbrLayout {
         &self,
         device: &wgpu::Device,
         sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
         vs_module: &wgpu::ShaderModule,
         fs_module: &wgpu::ShaderModule,
     ) -> wgpu::RenderPipeline {
+        let stamp = wgpu::StencilStateFaceDescriptor {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             layout: &self.pipeline_layout,
             vertex_stage: wgpu::ProgrammableStageDescriptor {
@@ -272,32 +647,144 @@ impl PbrLayout {
                 entry_point: "main",
             }),
             rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Cw, // Ccw,
+                front_face: wgpu::FrontFace::Ccw,
                 cull_mode: wgpu::CullMode::Back,
                 depth_bias: 0,
                 depth_bias_slope_scale: 0.0,
                 depth_bias_clamp: 0.0,
             }),
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: sc_desc.format,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            // since we're rendering spheres by strips
-            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &PbrLayout::color_states(sc_desc, wgpu::ColorWrite::ALL, sample_count),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            // every draw stamps its stencil reference, see `SELECTED_STENCIL`
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: stamp.clone(),
+                stencil_back: stamp,
+                stencil_read_mask: !0,
+                stencil_write_mask: !0,
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[VertexTexNormalTangent::desc()],
+            },
+            sample_count,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        })
+    }
+
+    /// Draws the selected instance pushed out along its normals, where it
+    /// didn't stamp the stencil buffer. Depth is tested but not written so
+    /// whatever hides the instance hides its outline too.
+    fn create_outline_pipeline(
+        &self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        let stencil = wgpu::StencilStateFaceDescriptor {
+            compare: wgpu::CompareFunction::NotEqual,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &self.outline_pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::Back,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            color_states: &PbrLayout::color_states(
+                sc_desc,
+                wgpu::ColorWrite::empty(),
+                sample_count,
+            ),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: stencil.clone(),
+                stencil_back: stencil,
+                stencil_read_mask: !0,
+                stencil_write_mask: 0,
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[VertexTexNormalTangent::desc()],
+            },
+            sample_count,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        })
+    }
+
+    /// Draws only the instance and primitive ids, always with one sample,
+    /// for frames whose colour is multisampled.
+    fn create_id_pipeline(
+        &self,
+        device: &wgpu::Device,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        let id_state = wgpu::ColorStateDescriptor {
+            format: IdBuffer::FORMAT,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let keep = wgpu::StencilStateFaceDescriptor {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &self.id_pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::Back,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            color_states: &[id_state.clone(), id_state],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
                 format: Texture::DEPTH_FORMAT,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_front: keep.clone(),
+                stencil_back: keep,
                 stencil_read_mask: 0,
                 stencil_write_mask: 0,
             }),
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[VertexTexNormal::desc()],
+                vertex_buffers: &[VertexTexNormalTangent::desc()],
             },
             sample_count: 1,
             sample_mask: !0,
@@ -308,7 +795,12 @@ impl PbrLayout {
 
 pub struct Pbr {
     pub pipeline: wgpu::RenderPipeline,
+    pub outline_pipeline: wgpu::RenderPipeline,
+    /// Single sampled whatever the target, see `create_id_pipeline`.
+    pub id_pipeline: wgpu::RenderPipeline,
     pub layout: PbrLayout,
+    modules: (wgpu::ShaderModule, wgpu::ShaderModule),
+    outline_modules: (wgpu::ShaderModule, wgpu::ShaderModule),
 }
 
 impl Pbr {
@@ -319,29 +811,89 @@ impl Pbr {
         let (vs_module, fs_module) = pipelines::compile_modules(&device, (vs_src, fs_src), "pbr");
 
         let layout = PbrLayout::new(&device);
-        let pipeline = layout.create_render_pipeline(&device, &sc_desc, &vs_module, &fs_module);
+        let pipeline = layout.create_render_pipeline(&device, &sc_desc, 1, &vs_module, &fs_module);
 
-        Pbr { layout, pipeline }
+        let vs_src = include_str!("../../shaders/outline_vs.glsl");
+        let fs_src = include_str!("../../shaders/outline_fs.glsl");
+        let outline_modules = pipelines::compile_modules(device, (vs_src, fs_src), "outline");
+        let outline_pipeline = layout.create_outline_pipeline(
+            device,
+            sc_desc,
+            1,
+            &outline_modules.0,
+            &outline_modules.1,
+        );
+
+        let vs_src = include_str!("../../shaders/id_vs.glsl");
+        let fs_src = include_str!("../../shaders/id_fs.glsl");
+        let (id_vs_module, id_fs_module) =
+            pipelines::compile_modules(device, (vs_src, fs_src), "id");
+        let id_pipeline = layout.create_id_pipeline(device, &id_vs_module, &id_fs_module);
+
+        Pbr {
+            layout,
+            pipeline,
+            outline_pipeline,
+            id_pipeline,
+            modules: (vs_module, fs_module),
+            outline_modules,
+        }
+    }
+
+    /// Rebuilds the pipelines for a target with `sample_count` samples.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) {
+        let (vs_module, fs_module) = &self.modules;
+        self.pipeline =
+            self.layout
+                .create_render_pipeline(device, sc_desc, sample_count, vs_module, fs_module);
+        let (vs_module, fs_module) = &self.outline_modules;
+        self.outline_pipeline = self.layout.create_outline_pipeline(
+            device,
+            sc_desc,
+            sample_count,
+            vs_module,
+            fs_module,
+        );
     }
 }
 
 pub struct PbrRenderPass<'a> {
     pub clear_color: wgpu::Color,
+    pub pipeline: &'a wgpu::RenderPipeline,
 
     pub mvp: &'a mut MvpUniforms,
     pub mvp_buffer: &'a wgpu::Buffer,
 
     pub uniform_bind_group: &'a wgpu::BindGroup,
-    pub geometry: &'a Geometry,
+    pub meshes: &'a [Mesh],
+    pub materials: &'a [(Material, wgpu::BindGroup)],
+
+    pub sample_count: u32,
+    pub id_buffer: &'a IdBuffer,
+    pub id_pipeline: &'a wgpu::RenderPipeline,
 
-    pub depth_texture: &'a Texture,
-    pub num_instances: usize,
+    // visible instances per mesh, sorted by level of detail
+    pub instances: &'a (Vec<TransformRaw>, Vec<MaterialInfoRaw>),
+    pub transforms_buffer: &'a wgpu::Buffer,
+    pub material_info_buffer: &'a wgpu::Buffer,
+    pub lod_ranges: &'a [Vec<Range<u32>>],
 
-    pub texture_bind_group: &'a wgpu::BindGroup,
+    // (mesh, slot, level) the selected instance is drawn with
+    pub selected_slots: Vec<(usize, u32, usize)>,
+    pub outline_pipeline: &'a wgpu::RenderPipeline,
+    pub outline: &'a OutlineUniforms,
+    pub outline_buffer: &'a wgpu::Buffer,
+    pub outline_bind_group: &'a wgpu::BindGroup,
 }
 
 impl<'a> PbrRenderPass<'a> {
-    pub fn new(state: &'a mut PbrState) -> Self {
+    pub fn new(state: &'a mut PbrState, pipeline: &'a Pbr) -> Self {
+        let selected_slots = state.selected_slots();
         PbrRenderPass {
             clear_color: wgpu::Color {
                 r: 0.1,
@@ -349,57 +901,237 @@ impl<'a> PbrRenderPass<'a> {
                 b: 0.1,
                 a: 1.0,
             },
+            pipeline: &pipeline.pipeline,
             mvp: &mut state.mvp,
             mvp_buffer: &state.mvp_buffer,
             uniform_bind_group: &state.uniform_bind_group,
-            geometry: &state.sphere,
-            depth_texture: &state.depth_texture,
-            num_instances: state.instances.0.len(),
-            texture_bind_group: &state.material_bind_group,
+            meshes: &state.meshes,
+            materials: &state.materials,
+            sample_count: state.sample_count,
+            id_buffer: &state.id_buffer,
+            id_pipeline: &pipeline.id_pipeline,
+            instances: &state.visible_instances,
+            transforms_buffer: &state.transforms_buffer,
+            material_info_buffer: &state.material_info_buffer,
+            lod_ranges: &state.lod_ranges,
+            selected_slots,
+            outline_pipeline: &pipeline.outline_pipeline,
+            outline: &state.outline,
+            outline_buffer: &state.outline_buffer,
+            outline_bind_group: &state.outline_bind_group,
+        }
+    }
+
+    /// Adds the pass into the target to `graph`, with its depth and the id
+    /// buffer. Without multisampling the ids are drawn by the same pass,
+    /// otherwise by an `IdRenderPass` of their own.
+    pub fn add_to(self, graph: &mut RenderGraph<'a>) {
+        graph.add_transient(
+            DEPTH,
+            TransientDesc {
+                format: Texture::DEPTH_FORMAT,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+                multisampled: true,
+            },
+        );
+        self.id_buffer.add_to(graph);
+        if self.sample_count == 1 {
+            graph.add_pass(
+                "pbr",
+                &[],
+                &[TARGET, DEPTH, ID_INSTANCE, ID_PRIMITIVE],
+                self,
+            );
+        } else {
+            graph.add_transient(
+                ID_DEPTH,
+                TransientDesc {
+                    format: Texture::DEPTH_FORMAT,
+                    usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+                    multisampled: false,
+                },
+            );
+            let id_pass = IdRenderPass {
+                pipeline: self.id_pipeline,
+                uniform_bind_group: self.uniform_bind_group,
+                meshes: self.meshes,
+                lod_ranges: self.lod_ranges,
+            };
+            graph.add_pass("pbr", &[], &[TARGET, DEPTH], self);
+            // added after the pass uploading the instances it draws, so it
+            // runs after it
+            graph.add_pass(
+                "pbr_ids",
+                &[],
+                &[ID_INSTANCE, ID_PRIMITIVE, ID_DEPTH],
+                id_pass,
+            );
         }
     }
 }
 
-impl Render for PbrRenderPass<'_> {
-    fn render(
+impl GraphPass for PbrRenderPass<'_> {
+    fn execute(
         &self,
         device: &wgpu::Device,
-        pipeline: &wgpu::RenderPipeline,
-        frame: &wgpu::SwapChainOutput,
+        attachments: &PassAttachments,
+        staging: &mut StagingBelt,
         encoder: &mut wgpu::CommandEncoder,
     ) {
-        PbrState::stage_uniforms(device, encoder, &self.mvp, &self.mvp_buffer);
+        staging.write(device, encoder, self.mvp_buffer, 0, &[*self.mvp]);
+        staging.write(
+            device,
+            encoder,
+            self.transforms_buffer,
+            0,
+            &self.instances.0,
+        );
+        staging.write(
+            device,
+            encoder,
+            self.material_info_buffer,
+            0,
+            &self.instances.1,
+        );
+        if !self.selected_slots.is_empty() {
+            staging.write(device, encoder, self.outline_buffer, 0, &[*self.outline]);
+        }
 
+        let mut targets = vec![(attachments.color(TARGET), self.clear_color)];
+        if self.sample_count == 1 {
+            for &name in [ID_INSTANCE, ID_PRIMITIVE].iter() {
+                targets.push((attachments.color(name), wgpu::Color::TRANSPARENT));
+            }
+        }
+        let color_attachments: Vec<_> = targets
+            .iter()
+            .map(|(target, clear_color)| target.attachment(*clear_color))
+            .collect();
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                attachment: &frame.view,
-                resolve_target: None,
-                load_op: wgpu::LoadOp::Clear,
-                store_op: wgpu::StoreOp::Store,
-                clear_color: self.clear_color,
-            }],
+            color_attachments: &color_attachments,
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                attachment: &self.depth_texture.view,
-                depth_load_op: wgpu::LoadOp::Clear,
+                attachment: attachments.view(DEPTH),
+                depth_load_op: attachments.load_op(DEPTH),
                 depth_store_op: wgpu::StoreOp::Store,
                 clear_depth: 1.0,
-                stencil_load_op: wgpu::LoadOp::Clear,
+                stencil_load_op: attachments.load_op(DEPTH),
                 stencil_store_op: wgpu::StoreOp::Store,
                 clear_stencil: 0,
             }),
         });
 
-        render_pass.set_pipeline(&pipeline);
+        render_pass.set_pipeline(self.pipeline);
         render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.texture_bind_group, &[]);
 
-        render_pass.set_vertex_buffer(0, &self.geometry.vertex_buffer, 0, 0);
-        render_pass.set_index_buffer(&self.geometry.index_buffer, 0, 0);
-        render_pass.draw_indexed(
-            0..self.geometry.num_indices,
-            0,
-            0..self.num_instances as u32,
-        );
+        for (mesh_index, (mesh, lod_ranges)) in self.meshes.iter().zip(self.lod_ranges).enumerate()
+        {
+            if lod_ranges
+                .iter()
+                .all(|instances| instances.start == instances.end)
+            {
+                continue;
+            }
+            render_pass.set_bind_group(1, &self.materials[mesh.material].1, &[]);
+            render_pass.set_vertex_buffer(0, &mesh.geometry.vertex_buffer, 0, 0);
+            render_pass.set_index_buffer(&mesh.geometry.index_buffer, 0, 0);
+            for (level, instances) in lod_ranges.iter().enumerate() {
+                if instances.start == instances.end {
+                    continue;
+                }
+                let lod = mesh.geometry.lod(level);
+                let indices = lod.first_index..lod.first_index + lod.num_indices;
+                let selected = self
+                    .selected_slots
+                    .iter()
+                    .find(|&&(m, slot, _)| m == mesh_index && instances.contains(&slot));
+                match selected {
+                    Some(&(_, slot, _)) => {
+                        render_pass.draw_indexed(indices.clone(), 0, instances.start..slot);
+                        render_pass.set_stencil_reference(SELECTED_STENCIL);
+                        render_pass.draw_indexed(indices.clone(), 0, slot..slot + 1);
+                        render_pass.set_stencil_reference(0);
+                        render_pass.draw_indexed(indices, 0, slot + 1..instances.end);
+                    }
+                    None => render_pass.draw_indexed(indices, 0, instances.clone()),
+                }
+            }
+        }
+
+        if !self.selected_slots.is_empty() {
+            render_pass.set_pipeline(self.outline_pipeline);
+            render_pass.set_bind_group(0, self.uniform_bind_group, &[]);
+            render_pass.set_bind_group(1, self.outline_bind_group, &[]);
+            render_pass.set_stencil_reference(SELECTED_STENCIL);
+            for &(mesh_index, slot, level) in &self.selected_slots {
+                let geometry = &self.meshes[mesh_index].geometry;
+                let lod = geometry.lod(level);
+                render_pass.set_vertex_buffer(0, &geometry.vertex_buffer, 0, 0);
+                render_pass.set_index_buffer(&geometry.index_buffer, 0, 0);
+                render_pass.draw_indexed(
+                    lod.first_index..lod.first_index + lod.num_indices,
+                    0,
+                    slot..slot + 1,
+                );
+            }
+        }
+    }
+}
+
+/// Draws the visible instances into the id buffer with a depth of its own,
+/// for frames whose colour is multisampled and can't carry the ids along.
+pub struct IdRenderPass<'a> {
+    pub pipeline: &'a wgpu::RenderPipeline,
+    pub uniform_bind_group: &'a wgpu::BindGroup,
+    pub meshes: &'a [Mesh],
+    pub lod_ranges: &'a [Vec<Range<u32>>],
+}
+
+impl GraphPass for IdRenderPass<'_> {
+    fn execute(
+        &self,
+        _device: &wgpu::Device,
+        attachments: &PassAttachments,
+        _staging: &mut StagingBelt,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let targets = [
+            attachments.color(ID_INSTANCE),
+            attachments.color(ID_PRIMITIVE),
+        ];
+        let color_attachments: Vec<_> = targets
+            .iter()
+            .map(|target| target.attachment(wgpu::Color::TRANSPARENT))
+            .collect();
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: attachments.view(ID_DEPTH),
+                depth_load_op: attachments.load_op(ID_DEPTH),
+                depth_store_op: wgpu::StoreOp::Store,
+                clear_depth: 1.0,
+                stencil_load_op: attachments.load_op(ID_DEPTH),
+                stencil_store_op: wgpu::StoreOp::Store,
+                clear_stencil: 0,
+            }),
+        });
+
+        render_pass.set_pipeline(self.pipeline);
+        render_pass.set_bind_group(0, self.uniform_bind_group, &[]);
+        for (mesh, lod_ranges) in self.meshes.iter().zip(self.lod_ranges) {
+            render_pass.set_vertex_buffer(0, &mesh.geometry.vertex_buffer, 0, 0);
+            render_pass.set_index_buffer(&mesh.geometry.index_buffer, 0, 0);
+            for (level, instances) in lod_ranges.iter().enumerate() {
+                if instances.start == instances.end {
+                    continue;
+                }
+                let lod = mesh.geometry.lod(level);
+                render_pass.draw_indexed(
+                    lod.first_index..lod.first_index + lod.num_indices,
+                    0,
+                    instances.clone(),
+                );
+            }
+        }
     }
 }
 
@@ -435,24 +1167,433 @@ fn make_instances() -> (Vec<TransformRaw>, Vec<MaterialInfoRaw>) {
         .unzip()
 }
 
+/// Every node reachable from a scene, once each.
+fn scene_nodes(model: &model::Model) -> Vec<usize> {
+    fn walk(roots: &[model::JointNode], nodes: &mut Vec<usize>) {
+        for root in roots {
+            if !nodes.contains(&root.index) {
+                nodes.push(root.index);
+                walk(&root.children, nodes);
+            }
+        }
+    }
+    let mut nodes = Vec::new();
+    for scene in &model.scenes {
+        walk(scene, &mut nodes);
+    }
+    nodes
+}
+
+/// The factors of `uniforms` written back into `material`.
+fn material_with_uniforms(
+    material: &model::Material,
+    uniforms: &PbrMaterialUniforms,
+) -> model::Material {
+    let [r, g, b, strength] = uniforms.emissive;
+    let [metallic, roughness, clearcoat, clearcoat_roughness] = uniforms.factors;
+    let clearcoat = match &material.clearcoat {
+        Some(coat) => Some(model::Clearcoat {
+            factor: clearcoat,
+            roughness_factor: clearcoat_roughness,
+            ..coat.clone()
+        }),
+        None if clearcoat > 0.0 => Some(model::Clearcoat {
+            factor: clearcoat,
+            texture: None,
+            roughness_factor: clearcoat_roughness,
+            roughness_texture: None,
+            normal_texture: None,
+        }),
+        None => None,
+    };
+    model::Material {
+        base_color_factor: uniforms.base_color,
+        metallic_factor: metallic,
+        roughness_factor: roughness,
+        emissive_factor: [r, g, b],
+        emissive_strength: strength,
+        clearcoat,
+        unlit: uniforms.flags[0] != 0,
+        ..material.clone()
+    }
+}
+
+/// Sets the node's TRS properties to `matrix`, which mustn't shear.
+fn set_node_matrix(node: &mut model::Node, matrix: cgmath::Matrix4<f32>) {
+    let (translation, rotation, scale) = gltf::scene::Transform::Matrix {
+        matrix: matrix.into(),
+    }
+    .decomposed();
+    node.translation = translation;
+    node.rotation = rotation;
+    node.scale = scale;
+}
+
+/// `model` as the viewer shows it, for exporting: the instances it was
+/// loaded with where they are now and without the removed ones, spawned
+/// instances as new nodes of the default scene, and the factors of
+/// `materials`, the uniforms of `model.materials` in order. Moving a skinned
+/// instance moves the roots of its skeleton. Per-instance material
+/// multipliers have no glTF counterpart and are left out.
+pub fn live_model(
+    model: &model::Model,
+    instances: &Instances,
+    materials: &[PbrMaterialUniforms],
+) -> model::Model {
+    use cgmath::SquareMatrix;
+    use std::collections::HashMap;
+
+    let mut live = model.clone();
+    for (material, uniforms) in live.materials.iter_mut().zip(materials) {
+        *material = material_with_uniforms(material, uniforms);
+    }
+
+    let worlds = model.world_transforms();
+    let mut parents = vec![None; model.nodes.len()];
+    for (index, node) in model.nodes.iter().enumerate() {
+        for &child in &node.children {
+            parents[child] = Some(index);
+        }
+    }
+    let parent_world =
+        |index: usize| parents[index].map_or(cgmath::Matrix4::identity(), |parent| worlds[parent]);
+    // moves `index` by `delta` in world space, keeping its parent
+    let move_node = |live: &mut model::Model, index: usize, delta: cgmath::Matrix4<f32>| {
+        let inverse_parent = parent_world(index)
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        set_node_matrix(
+            &mut live.nodes[index],
+            inverse_parent * delta * worlds[index],
+        );
+    };
+
+    let loaded = Instances::from_model(model);
+    let loaded: HashMap<_, _> = loaded
+        .origins
+        .iter()
+        .zip(&loaded.transforms)
+        .map(|(&origin, transform)| (origin, transform.model))
+        .collect();
+    let mut kept_nodes = vec![false; model.nodes.len()];
+    let mut kept_transforms: Vec<Vec<usize>> = vec![Vec::new(); model.instances.len()];
+    let scene = model.scene.unwrap_or(0);
+    for (i, &origin) in instances.origins.iter().enumerate() {
+        let transform = instances.transforms[i].model;
+        match origin {
+            InstanceOrigin::Node(index) => {
+                kept_nodes[index] = true;
+                let before = loaded[&origin];
+                if transform == before {
+                    continue;
+                }
+                let delta = transform * before.invert().unwrap_or_else(cgmath::Matrix4::identity);
+                match model.nodes[index].skin {
+                    // a skinned mesh is placed by its joints alone
+                    Some(skin) => {
+                        let joints = &model.skins[skin].joints;
+                        for &joint in joints {
+                            if !parents[joint].is_some_and(|parent| joints.contains(&parent)) {
+                                move_node(&mut live, joint, delta);
+                            }
+                        }
+                    }
+                    None => move_node(&mut live, index, delta),
+                }
+            }
+            InstanceOrigin::Instancing(entry, k) => {
+                kept_transforms[entry].push(k);
+                let mesh_instances = &mut live.instances[entry];
+                mesh_instances.transforms[k] = transform.into();
+                if transform == loaded[&origin] {
+                    continue;
+                }
+                let (translation, rotation, scale) = gltf::scene::Transform::Matrix {
+                    matrix: (worlds[mesh_instances.node]
+                        .invert()
+                        .unwrap_or_else(cgmath::Matrix4::identity)
+                        * transform)
+                        .into(),
+                }
+                .decomposed();
+                let count = mesh_instances.transforms.len();
+                mesh_instances
+                    .translations
+                    .get_or_insert_with(|| vec![[0.0; 3]; count])[k] = translation;
+                mesh_instances
+                    .rotations
+                    .get_or_insert_with(|| vec![[0.0, 0.0, 0.0, 1.0]; count])[k] = rotation;
+                mesh_instances
+                    .scales
+                    .get_or_insert_with(|| vec![[1.0; 3]; count])[k] = scale;
+            }
+            InstanceOrigin::Spawned => {
+                let mut node = model::Node {
+                    name: None,
+                    mesh: Some(instances.groups[i]),
+                    skin: None,
+                    children: Vec::new(),
+                    translation: [0.0; 3],
+                    rotation: [0.0, 0.0, 0.0, 1.0],
+                    scale: [1.0; 3],
+                };
+                set_node_matrix(&mut node, transform);
+                if live.scenes.is_empty() {
+                    live.scenes.push(Vec::new());
+                }
+                let scene = scene.min(live.scenes.len() - 1);
+                live.scenes[scene].push(model::JointNode {
+                    index: live.nodes.len(),
+                    children: Vec::new(),
+                });
+                live.nodes.push(node);
+            }
+        }
+    }
+
+    // removed instances
+    for (index, kept) in kept_nodes.into_iter().enumerate() {
+        if !kept && loaded.contains_key(&InstanceOrigin::Node(index)) {
+            live.nodes[index].mesh = None;
+        }
+    }
+    fn keep<T: Copy>(values: &mut Vec<T>, kept: &[usize]) {
+        *values = kept.iter().map(|&k| values[k]).collect();
+    }
+    for (mesh_instances, mut kept) in live.instances.iter_mut().zip(kept_transforms) {
+        kept.sort_unstable();
+        keep(&mut mesh_instances.transforms, &kept);
+        if let Some(translations) = &mut mesh_instances.translations {
+            keep(translations, &kept);
+        }
+        if let Some(rotations) = &mut mesh_instances.rotations {
+            keep(rotations, &kept);
+        }
+        if let Some(scales) = &mut mesh_instances.scales {
+            keep(scales, &kept);
+        }
+    }
+    for mesh_instances in live.instances.iter().filter(|i| i.transforms.is_empty()) {
+        live.nodes[mesh_instances.node].mesh = None;
+    }
+    live.instances
+        .retain(|mesh_instances| !mesh_instances.transforms.is_empty());
+    live
+}
+
+/// Closest surface under a ray, see `PbrState::pick`.
+#[derive(Debug, Clone, Copy)]
+pub struct Pick {
+    pub instance: usize,
+    pub mesh: usize,
+    /// Index into the mesh's triangle list.
+    pub triangle: u32,
+    pub barycentrics: [f32; 3],
+    /// Along the ray, in world units for a unit length direction.
+    pub distance: f32,
+}
+
+/// Surface under a pixel of the id buffer, see `PbrState::poll_id_pick`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdPick {
+    pub instance: usize,
+    pub mesh: usize,
+    /// Index into the triangle list of `level`.
+    pub triangle: u32,
+    pub level: usize,
+}
+
+/// Where an instance of a glTF model comes from, see `live_model`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstanceOrigin {
+    /// A node drawing its mesh.
+    Node(usize),
+    /// A transform of `model::Model::instances`, by entry and index.
+    Instancing(usize, usize),
+    /// Added after loading, or not loaded from glTF at all.
+    Spawned,
+}
+
+/// Every instance drawn, with the group of meshes it draws.
+#[derive(Default)]
+pub struct Instances {
+    pub transforms: Vec<TransformRaw>,
+    pub material_infos: Vec<MaterialInfoRaw>,
+    groups: Vec<usize>,
+    // bounds in the instance's space replacing its meshes' own, for skinned
+    // instances whose vertices their joints place
+    bounds: Vec<Option<Bounds>>,
+    origins: Vec<InstanceOrigin>,
+}
+
+impl Instances {
+    fn new(
+        (transforms, material_infos): (Vec<TransformRaw>, Vec<MaterialInfoRaw>),
+        groups: Vec<usize>,
+        bounds: Vec<Option<Bounds>>,
+    ) -> Self {
+        let origins = vec![InstanceOrigin::Spawned; transforms.len()];
+        Instances {
+            transforms,
+            material_infos,
+            groups,
+            bounds,
+            origins,
+        }
+    }
+
+    /// The instances of `model`'s scenes as `PbrState::set_model` draws
+    /// them, every glTF mesh is the group of the same index.
+    pub fn from_model(model: &model::Model) -> Self {
+        let material_info = MaterialInfoRaw {
+            info: cgmath::Vector4::new(1.0, 1.0, 1.0, 0.0),
+        };
+        let mut instances = Instances::default();
+        for (entry, mesh_instances) in model.instances.iter().enumerate() {
+            for (i, &transform) in mesh_instances.transforms.iter().enumerate() {
+                let transform = TransformRaw {
+                    model: cgmath::Matrix4::from(transform),
+                };
+                let index = instances.push(mesh_instances.mesh, transform, material_info);
+                instances.origins[index] = InstanceOrigin::Instancing(entry, i);
+            }
+        }
+        let worlds = model.world_transforms();
+        for index in scene_nodes(model) {
+            let node = &model.nodes[index];
+            let mesh = match node.mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            if model
+                .instances
+                .iter()
+                .any(|instances| instances.node == index)
+            {
+                continue;
+            }
+            // skinned vertices are placed by their joints, not the node, so
+            // they are culled against the boxes of the joints, in the space
+            // of an identity transform
+            let (transform, bounds) = match node.skin.map(|skin| &model.skins[skin]) {
+                Some(skin) => {
+                    let joints: Vec<_> = skin.joints.iter().map(|&joint| worlds[joint]).collect();
+                    (cgmath::SquareMatrix::identity(), skin.bounds(&joints))
+                }
+                None => (worlds[index], None),
+            };
+            let instance = instances.push(mesh, TransformRaw { model: transform }, material_info);
+            instances.bounds[instance] = bounds;
+            instances.origins[instance] = InstanceOrigin::Node(index);
+        }
+        instances
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    fn push(
+        &mut self,
+        group: usize,
+        transform: TransformRaw,
+        material_info: MaterialInfoRaw,
+    ) -> usize {
+        self.transforms.push(transform);
+        self.material_infos.push(material_info);
+        self.groups.push(group);
+        self.bounds.push(None);
+        self.origins.push(InstanceOrigin::Spawned);
+        self.len() - 1
+    }
+
+    fn remove(&mut self, index: usize) -> Option<(TransformRaw, MaterialInfoRaw)> {
+        if index >= self.len() {
+            return None;
+        }
+        self.groups.remove(index);
+        self.bounds.remove(index);
+        self.origins.remove(index);
+        Some((
+            self.transforms.remove(index),
+            self.material_infos.remove(index),
+        ))
+    }
+
+    /// Bounds replacing the meshes' own move along with the transform, as
+    /// they're kept in the instance's space.
+    fn update(
+        &mut self,
+        index: usize,
+        transform: TransformRaw,
+        material_info: MaterialInfoRaw,
+    ) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        self.transforms[index] = transform;
+        self.material_infos[index] = material_info;
+        true
+    }
+
+    /// World space bounds of a mesh with `mesh_bounds` drawn by instance
+    /// `index`.
+    fn world_bounds(&self, index: usize, mesh_bounds: &Bounds) -> Bounds {
+        self.bounds[index]
+            .as_ref()
+            .unwrap_or(mesh_bounds)
+            .transform(&self.transforms[index].model)
+    }
+}
+
 pub struct PbrState {
     pub mvp: MvpUniforms,
     pub pbr_fs: PbrFragmentUniforms,
 
-    pub depth_texture: Texture,
+    /// Samples per pixel of the target, with more than one the id buffer
+    /// is drawn by a pass of its own.
+    pub sample_count: u32,
+    pub id_buffer: IdBuffer,
+
+    /// Instance drawn with an outline.
+    pub selected: Option<usize>,
+    pub outline: OutlineUniforms,
+    pub outline_buffer: wgpu::Buffer,
+    pub outline_bind_group: wgpu::BindGroup,
 
     pub mvp_buffer: wgpu::Buffer,
     pub pbr_fs_buffer: wgpu::Buffer,
 
     pub uniform_bind_group: wgpu::BindGroup,
 
-    pub sphere: Geometry,
+    pub meshes: Vec<Mesh>,
+    // consecutive meshes drawn together, e.g. the primitives of a glTF mesh,
+    groups: Vec<Range<usize>>,
+
+    pub instances: Instances,
+    pub transforms_buffer: wgpu::Buffer,
+    pub material_info_buffer: wgpu::Buffer,
+    // instances the buffers have room for, counted once per mesh of their
+    // group
+    instance_capacity: usize,
 
-    pub instances: (Vec<TransformRaw>, Vec<MaterialInfoRaw>),
+    // the instances each mesh is visible in, grouped by mesh and then by
+    // level of detail, with the range of every level of every mesh
+    pub visible_instances: (Vec<TransformRaw>, Vec<MaterialInfoRaw>),
+    pub lod_ranges: Vec<Vec<Range<u32>>>,
+    // (mesh, instance, level) drawn by every visible slot, and a copy taken
+    // when an id readback starts
+    visible_slots: Vec<(usize, usize, usize)>,
+    id_slots: Vec<(usize, usize, usize)>,
+    pub lod_enabled: bool,
+    pub culling_enabled: bool,
 
     // pub albedo_tex: (Texture, wgpu::BindGroup),
-    pub material: Material,
-    pub material_bind_group: wgpu::BindGroup,
+    pub materials: Vec<(Material, wgpu::BindGroup)>,
 }
 
 impl PbrState {
@@ -483,19 +1624,43 @@ impl PbrState {
         let metallic = load_texture("metallic", false);
         let normals = load_texture("normal-dx", true);
         let ambient_occlusion = load_texture("ao", false);
-        let material = Material {
-            albedo,
-            roughness,
-            metallic,
-            normals,
-            ambient_occlusion,
+        let white = || {
+            let (texture, cmd_buffer) =
+                Texture::from_color(device, [255; 4], false).expect("Failed to create texture");
+            queue.submit(&[cmd_buffer]);
+            texture
         };
+        let mut material_uniforms = PbrMaterialUniforms::default();
+        material_uniforms.flags[1] = 1; // normal-dx
+        let material = Material::new(
+            &device,
+            [
+                albedo,
+                roughness,
+                ambient_occlusion,
+                normals,
+                metallic,
+                white(),
+                white(),
+                white(),
+            ],
+            material_uniforms,
+        );
 
         let material_bind_group = pipeline
             .layout
             .create_texture_bind_group(&device, &material);
 
-        let depth_texture = Texture::create_depth_texture(&device, &sc_desc, "pbr_depth_texture");
+        let id_buffer = IdBuffer::new(&device);
+
+        let outline = OutlineUniforms::new([1.0, 0.6, 0.0, 1.0], 3.0, &sc_desc);
+        let outline_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[outline]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let outline_bind_group = pipeline
+            .layout
+            .create_outline_bind_group(&device, &outline_buffer);
 
         // instances
         let instances = make_instances();
@@ -530,69 +1695,752 @@ impl PbrState {
             wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         );
 
-        let (transforms_buffer, transforms_size) = (
-            device.create_buffer_with_data(
-                bytemuck::cast_slice(&instances.0),
-                wgpu::BufferUsage::STORAGE_READ,
-            ),
-            instances.0.len() * std::mem::size_of::<TransformRaw>(),
-        );
-
-        let (material_info_buffer, material_info_size) = (
-            device.create_buffer_with_data(
-                bytemuck::cast_slice(&instances.1),
-                wgpu::BufferUsage::STORAGE_READ,
-            ),
-            instances.0.len() * std::mem::size_of::<MaterialInfoRaw>(),
-        );
+        let meshes = vec![Mesh {
+            geometry: Geometry::create_sphere_pbr(&device),
+            material: 0,
+        }];
 
-        let uniform_bind_group = pipeline.layout.create_uniform_bind_group(
+        let groups = std::iter::once(0..meshes.len()).collect();
+        let count = instances.0.len();
+        let instances = Instances::new(instances, vec![0; count], vec![None; count]);
+        let capacity = instances.len() * meshes.len();
+        let (transforms_buffer, material_info_buffer) =
+            PbrState::create_instance_buffers(&device, capacity);
+        let uniform_bind_group = PbrState::create_uniform_bind_group(
             &device,
+            &pipeline,
             &mvp_buffer,
-            std::mem::size_of_val(&mvp),
             &pbr_fs_buffer,
-            std::mem::size_of_val(&pbr_fs),
-            &transforms_buffer,
-            transforms_size,
-            &material_info_buffer,
-            material_info_size,
+            (&transforms_buffer, &material_info_buffer),
+            capacity,
         );
 
-        let sphere = Geometry::create_sphere_pbr(&device);
-
         PbrState {
             mvp,
             pbr_fs,
             mvp_buffer,
             pbr_fs_buffer,
             uniform_bind_group,
-            sphere,
-            depth_texture,
+            meshes,
+            groups,
+            sample_count: 1,
+            id_buffer,
+            selected: None,
+            outline,
+            outline_buffer,
+            outline_bind_group,
+            lod_ranges: Vec::new(),
+            visible_slots: Vec::new(),
+            id_slots: Vec::new(),
+            visible_instances: (Vec::new(), Vec::new()),
             instances,
-            material,
-            material_bind_group,
+            transforms_buffer,
+            material_info_buffer,
+            instance_capacity: capacity,
+            lod_enabled: true,
+            culling_enabled: true,
+            materials: vec![(material, material_bind_group)],
         }
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
-        self.depth_texture = Texture::create_depth_texture(&device, &sc_desc, "pbr_depth_texture");
+    /// Storage buffers for the visible instances, rewritten every frame.
+    /// Every mesh of a group can see every instance of it, so `capacity` is
+    /// the sum of their products.
+    fn create_instance_buffers(
+        device: &wgpu::Device,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer) {
+        let create = |label, size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (capacity * size) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_DST,
+            })
+        };
+        (
+            create("pbr_transforms", std::mem::size_of::<TransformRaw>()),
+            create("pbr_material_info", std::mem::size_of::<MaterialInfoRaw>()),
+        )
     }
 
-    pub fn stage_uniforms(
+    fn create_uniform_bind_group(
         device: &wgpu::Device,
-        encoder: &mut wgpu::CommandEncoder,
-        mvp: &MvpUniforms,
+        pipeline: &Pbr,
         mvp_buffer: &wgpu::Buffer,
+        pbr_fs_buffer: &wgpu::Buffer,
+        (transforms_buffer, material_info_buffer): (&wgpu::Buffer, &wgpu::Buffer),
+        num_instances: usize,
+    ) -> wgpu::BindGroup {
+        pipeline.layout.create_uniform_bind_group(
+            &device,
+            &mvp_buffer,
+            std::mem::size_of::<MvpUniforms>(),
+            &pbr_fs_buffer,
+            std::mem::size_of::<PbrFragmentUniforms>(),
+            &transforms_buffer,
+            num_instances * std::mem::size_of::<TransformRaw>(),
+            &material_info_buffer,
+            num_instances * std::mem::size_of::<MaterialInfoRaw>(),
+        )
+    }
+
+    /// Recreates the instance buffers with room for `capacity`, and the
+    /// bind group pointing at them.
+    fn resize_instance_buffers(&mut self, device: &wgpu::Device, pipeline: &Pbr, capacity: usize) {
+        let (transforms_buffer, material_info_buffer) =
+            PbrState::create_instance_buffers(device, capacity);
+        self.uniform_bind_group = PbrState::create_uniform_bind_group(
+            device,
+            pipeline,
+            &self.mvp_buffer,
+            &self.pbr_fs_buffer,
+            (&transforms_buffer, &material_info_buffer),
+            capacity,
+        );
+        self.transforms_buffer = transforms_buffer;
+        self.material_info_buffer = material_info_buffer;
+        self.instance_capacity = capacity;
+    }
+
+    /// Replaces the instance grid, every instance draws the first group of
+    /// meshes. An empty set keeps the current one, storage bindings can't be
+    /// empty.
+    pub fn set_instances(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: &Pbr,
+        instances: (Vec<TransformRaw>, Vec<MaterialInfoRaw>),
     ) {
-        let staging_buffer = device
-            .create_buffer_with_data(bytemuck::cast_slice(&[*mvp]), wgpu::BufferUsage::COPY_SRC);
+        let groups = vec![0; instances.0.len()];
+        let bounds = vec![None; instances.0.len()];
+        self.set_grouped_instances(device, pipeline, Instances::new(instances, groups, bounds));
+    }
 
-        encoder.copy_buffer_to_buffer(
-            &staging_buffer,
-            0,
-            &mvp_buffer,
-            0,
-            std::mem::size_of::<MvpUniforms>() as wgpu::BufferAddress,
+    fn set_grouped_instances(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: &Pbr,
+        instances: Instances,
+    ) {
+        if instances.is_empty() {
+            return;
+        }
+        let capacity = self.slot_count(&instances.groups).max(1);
+        self.resize_instance_buffers(device, pipeline, capacity);
+        self.lod_ranges.clear();
+        self.visible_slots.clear();
+        self.selected = None;
+        self.visible_instances = (Vec::new(), Vec::new());
+        self.instances = instances;
+    }
+
+    /// Visible slots needed if every instance passes culling.
+    fn slot_count(&self, instance_groups: &[usize]) -> usize {
+        instance_groups
+            .iter()
+            .map(|&group| self.groups[group].len())
+            .sum()
+    }
+
+    /// The group of meshes an instance draws, see `add_instance`.
+    pub fn instance_group(&self, index: usize) -> Option<usize> {
+        self.instances.groups.get(index).copied()
+    }
+
+    /// Adds an instance drawing the meshes of `group` and returns its index.
+    /// The buffers at least double when they run out of room, so spawning
+    /// one at a time stays cheap.
+    pub fn add_instance(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: &Pbr,
+        group: usize,
+        transform: TransformRaw,
+        material_info: MaterialInfoRaw,
+    ) -> usize {
+        let needed = self.slot_count(&self.instances.groups) + self.groups[group].len();
+        if needed > self.instance_capacity {
+            let capacity = needed.max(2 * self.instance_capacity);
+            self.resize_instance_buffers(device, pipeline, capacity);
+        }
+        self.instances.push(group, transform, material_info)
+    }
+
+    /// Removes an instance, the ones after it move down an index. The
+    /// buffers keep their size.
+    pub fn remove_instance(&mut self, index: usize) -> Option<(TransformRaw, MaterialInfoRaw)> {
+        if index >= self.instances.len() {
+            return None;
+        }
+        self.selected = match self.selected {
+            Some(selected) if selected == index => None,
+            Some(selected) if selected > index => Some(selected - 1),
+            selected => selected,
+        };
+        // slots of a readback in flight may point past the end now
+        self.id_slots.clear();
+        self.instances.remove(index)
+    }
+
+    /// Moves an instance or changes its material info, uploaded with the
+    /// next frame. Returns false if there's no such instance.
+    pub fn update_instance(
+        &mut self,
+        index: usize,
+        transform: TransformRaw,
+        material_info: MaterialInfoRaw,
+    ) -> bool {
+        self.instances.update(index, transform, material_info)
+    }
+
+    /// Culls every mesh against the camera frustum once per instance of its
+    /// group, then
+    /// picks the coarsest level of detail whose error stays under
+    /// `LOD_PIXEL_ERROR` on screen. The survivors are grouped by mesh and
+    /// level so each level is a single instanced draw.
+    pub fn update_visible_instances(&mut self, camera: &Camera, viewport_height: u32) {
+        use cgmath::InnerSpace;
+
+        let frustum = camera.frustum();
+        let mut visible = (Vec::new(), Vec::new());
+        let mut slots = Vec::new();
+        self.lod_ranges = self
+            .meshes
+            .iter()
+            .enumerate()
+            .map(|(mesh_index, mesh)| {
+                let geometry = &mesh.geometry;
+                let levels = if self.lod_enabled {
+                    geometry.lods.len()
+                } else {
+                    1
+                };
+                let instances = &self.instances;
+                let mut order: Vec<(usize, usize)> = instances
+                    .transforms
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| self.groups[instances.groups[i]].contains(&mesh_index))
+                    .filter_map(|(i, transform)| {
+                        let model = transform.model;
+                        let bounds = instances.world_bounds(i, &geometry.bounds);
+                        if self.culling_enabled && !frustum.intersects(&bounds) {
+                            return None;
+                        }
+                        let scale = model
+                            .x
+                            .truncate()
+                            .magnitude()
+                            .max(model.y.truncate().magnitude())
+                            .max(model.z.truncate().magnitude());
+                        let center = bounds.sphere.center;
+                        let level = (0..levels)
+                            .rev()
+                            .find(|&level| {
+                                let error = geometry.lods[level].error * scale;
+                                camera.screen_size(error, center) * viewport_height as f32
+                                    <= LOD_PIXEL_ERROR
+                            })
+                            .unwrap_or(0);
+                        Some((level, i))
+                    })
+                    .collect();
+                order.sort_by_key(|&(level, _)| level);
+
+                let base = visible.0.len() as u32;
+                let ranges = (0..levels)
+                    .map(|level| {
+                        let start = order.iter().take_while(|&&(l, _)| l < level).count();
+                        let end = order.iter().take_while(|&&(l, _)| l <= level).count();
+                        base + start as u32..base + end as u32
+                    })
+                    .collect();
+                for &(level, i) in &order {
+                    visible.0.push(self.instances.transforms[i]);
+                    visible.1.push(self.instances.material_infos[i]);
+                    slots.push((mesh_index, i, level));
+                }
+                ranges
+            })
+            .collect();
+        self.visible_instances = visible;
+        self.visible_slots = slots;
+    }
+
+    /// Where the selected instance sits among the visible ones, as
+    /// `(mesh, slot, level)` for every mesh it is visible in.
+    fn selected_slots(&self) -> Vec<(usize, u32, usize)> {
+        let selected = match self.selected {
+            Some(selected) => selected,
+            None => return Vec::new(),
+        };
+        self.visible_slots
+            .iter()
+            .enumerate()
+            .filter(|(_, &(_, instance, _))| instance == selected)
+            .map(|(slot, &(mesh, _, level))| (mesh, slot as u32, level))
+            .collect()
+    }
+
+    /// Reads back the id buffer under `cursor` after the next frame, unless
+    /// an earlier readback is still in flight.
+    pub fn request_id_pick(
+        &mut self,
+        cursor: winit::dpi::PhysicalPosition<f64>,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) {
+        if cursor.x < 0.0 || cursor.y < 0.0 {
+            return;
+        }
+        let (x, y) = (cursor.x as u32, cursor.y as u32);
+        if x < sc_desc.width && y < sc_desc.height {
+            self.id_buffer.request([x, y]);
+        }
+    }
+
+    /// Call once the frame is submitted. The slots are remembered since the
+    /// visible instances can change before the readback completes.
+    pub fn begin_id_readback(&mut self) {
+        if self.id_buffer.begin_readback() {
+            self.id_slots.clone_from(&self.visible_slots);
+        }
+    }
+
+    /// `Some` once a readback completes, holding what was under the cursor.
+    pub fn poll_id_pick(&mut self, device: &wgpu::Device) -> Option<Option<IdPick>> {
+        let sample = self.id_buffer.poll(device)?;
+        Some(sample.instance.and_then(|slot| {
+            let &(mesh, instance, level) = self.id_slots.get(slot as usize)?;
+            Some(IdPick {
+                instance,
+                mesh,
+                triangle: sample.primitive,
+                level,
+            })
+        }))
+    }
+
+    /// Casts `ray` against the full resolution triangles of every instance's
+    /// meshes.
+    pub fn pick(&self, ray: &Ray) -> Option<Pick> {
+        use cgmath::SquareMatrix;
+
+        let mut closest: Option<Pick> = None;
+        for (instance, transform) in self.instances.transforms.iter().enumerate() {
+            let local_ray = match transform.model.invert() {
+                Some(inverse) => ray.transform(&inverse),
+                None => continue,
+            };
+            for mesh_index in self.groups[self.instances.groups[instance]].clone() {
+                let mesh = &self.meshes[mesh_index];
+                let hit = match mesh.geometry.bvh.intersect(&local_ray) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                if hit.distance < closest.map_or(f32::INFINITY, |pick| pick.distance) {
+                    closest = Some(Pick {
+                        instance,
+                        mesh: mesh_index,
+                        triangle: hit.triangle,
+                        barycentrics: hit.barycentrics,
+                        distance: hit.distance,
+                    });
+                }
+            }
+        }
+        closest
+    }
+
+    /// Swaps the meshes for a single group of them, the instance buffers
+    /// are resized to match.
+    fn set_meshes(&mut self, device: &wgpu::Device, pipeline: &Pbr, meshes: Vec<Mesh>) {
+        self.groups = std::iter::once(0..meshes.len()).collect();
+        self.meshes = meshes;
+        let instances = std::mem::take(&mut self.instances);
+        self.set_instances(
+            device,
+            pipeline,
+            (instances.transforms, instances.material_infos),
+        );
+    }
+
+    /// Draws `geometry` with the default material in place of the sphere.
+    pub fn set_geometry(&mut self, device: &wgpu::Device, pipeline: &Pbr, geometry: Geometry) {
+        self.set_meshes(
+            device,
+            pipeline,
+            vec![Mesh {
+                geometry,
+                material: 0,
+            }],
+        );
+    }
+
+    /// Draws terrain chunks with the default material, tiled instead of
+    /// clamped since the chunk UVs run across the whole terrain.
+    pub fn set_terrain(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline: &Pbr,
+        chunks: &[MeshData],
+        options: UploadOptions,
+    ) -> anyhow::Result<()> {
+        if let Some((material, bind_group)) = self.materials.first_mut() {
+            for texture in [
+                &mut material.albedo,
+                &mut material.roughness,
+                &mut material.ambient_occlusion,
+                &mut material.normals,
+                &mut material.metallic,
+                &mut material.emissive,
+                &mut material.clearcoat,
+                &mut material.clearcoat_roughness,
+            ]
+            .iter_mut()
+            {
+                texture.set_address_mode(device, wgpu::AddressMode::Repeat);
+            }
+            *bind_group = pipeline.layout.create_texture_bind_group(device, material);
+        }
+        let meshes = chunks
+            .iter()
+            .map(|chunk| {
+                Ok(Mesh {
+                    geometry: chunk.upload_with_lods(device, options)?,
+                    material: 0,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        self.set_meshes(device, pipeline, meshes);
+        self.set_instances(
+            device,
+            pipeline,
+            (
+                vec![TransformRaw {
+                    model: cgmath::SquareMatrix::identity(),
+                }],
+                vec![MaterialInfoRaw {
+                    info: cgmath::Vector4::new(1.0, 1.0, 1.0, 0.0),
+                }],
+            ),
+        );
+        Ok(())
+    }
+
+    /// Replaces the meshes and materials with the contents of an OBJ file,
+    /// drawn once at the origin.
+    pub fn set_obj(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &Pbr,
+        model: &obj::ObjModel,
+        options: UploadOptions,
+    ) -> anyhow::Result<()> {
+        let default_material = model.materials.len();
+        let materials = model
+            .materials
+            .iter()
+            .chain(std::iter::once(&obj::ObjMaterial::default()))
+            .map(|material| {
+                let material = Material::from_obj(device, queue, material)?;
+                let bind_group = pipeline
+                    .layout
+                    .create_texture_bind_group(&device, &material);
+                Ok((material, bind_group))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| Mesh {
+                geometry: Geometry::with_lods(&device, &mesh.vertices, &mesh.indices, options),
+                material: mesh.material.unwrap_or(default_material),
+            })
+            .collect();
+
+        self.materials = materials;
+        self.set_meshes(device, pipeline, meshes);
+        self.set_instances(
+            device,
+            pipeline,
+            (
+                vec![TransformRaw {
+                    model: cgmath::SquareMatrix::identity(),
+                }],
+                vec![MaterialInfoRaw {
+                    info: cgmath::Vector4::new(1.0, 1.0, 1.0, 0.0),
+                }],
+            ),
+        );
+        Ok(())
+    }
+
+    /// Replaces the meshes and materials with the ones of a glTF model. Every
+    /// primitive is a mesh, drawn by one instance per node using its glTF
+    /// mesh and one per `EXT_mesh_gpu_instancing` transform. Skinned meshes
+    /// are drawn in their bind pose and culled with `Skin::bounds`.
+    pub fn set_model(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &Pbr,
+        model: &model::Model,
+        base_dir: &std::path::Path,
+        options: UploadOptions,
+    ) -> anyhow::Result<()> {
+        let default_material = model.materials.len();
+        let materials = model
+            .materials
+            .iter()
+            .chain(std::iter::once(&model::Material::default()))
+            .map(|material| {
+                let material = Material::from_gltf(device, queue, model, material, base_dir)?;
+                let bind_group = pipeline.layout.create_texture_bind_group(device, &material);
+                Ok((material, bind_group))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let mut meshes = Vec::new();
+        let mut groups = Vec::with_capacity(model.meshes.len());
+        for mesh in &model.meshes {
+            let start = meshes.len();
+            for primitive in &mesh.primitives {
+                let (vertices, indices) = primitive.vertices();
+                for (sources, indices) in geometry::split_indices(vertices.len(), &indices) {
+                    let vertices: Vec<_> = sources.iter().map(|&i| vertices[i as usize]).collect();
+                    meshes.push(Mesh {
+                        geometry: Geometry::with_lods(device, &vertices, &indices, options),
+                        material: primitive.material.unwrap_or(default_material),
+                    });
+                }
+            }
+            groups.push(start..meshes.len());
+        }
+
+        let instances = Instances::from_model(model);
+        anyhow::ensure!(!instances.is_empty(), "The model has no mesh in its scenes");
+
+        self.materials = materials;
+        self.meshes = meshes;
+        self.groups = groups;
+        self.set_grouped_instances(device, pipeline, instances);
+        Ok(())
+    }
+
+    /// `model`, which was passed to `set_model`, as it's drawn now, see
+    /// `live_model`.
+    pub fn live_model(&self, model: &model::Model) -> model::Model {
+        let uniforms: Vec<_> = self
+            .materials
+            .iter()
+            .map(|(material, _)| material.uniforms)
+            .collect();
+        live_model(model, &self.instances, &uniforms)
+    }
+
+    pub fn resize(&mut self, sc_desc: &wgpu::SwapChainDescriptor) {
+        self.id_buffer.resize();
+        self.outline.resize(sc_desc);
+    }
+
+    /// Has to match the sample count of the target, the depth follows it.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{export::export_gltf, model::load_gltf};
+    use cgmath::{InnerSpace, Matrix4, Point3, Vector3, Vector4};
+    use std::path::{Path, PathBuf};
+
+    fn translation(x: f32) -> TransformRaw {
+        TransformRaw {
+            model: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+        }
+    }
+
+    fn material_info(metallic: f32) -> MaterialInfoRaw {
+        MaterialInfoRaw {
+            info: Vector4::new(metallic, 1.0, 1.0, 0.0),
+        }
+    }
+
+    fn center(bounds: Bounds) -> Point3<f32> {
+        bounds.aabb.center()
+    }
+
+    #[test]
+    fn added_instances_use_their_meshes_bounds() {
+        let mesh_bounds = Bounds::from_points(&[[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]]);
+        let mut instances = Instances::default();
+        assert_eq!(instances.push(0, translation(2.0), material_info(0.0)), 0);
+        assert_eq!(instances.push(1, translation(5.0), material_info(0.0)), 1);
+        assert_eq!(instances.groups, vec![0, 1]);
+        assert_eq!(
+            center(instances.world_bounds(1, &mesh_bounds)),
+            Point3::new(5.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn skinned_bounds_follow_updates() {
+        let mesh_bounds = Bounds::from_points(&[[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]]);
+        // the joints hold the skinned mesh further along than its bind pose
+        let skinned = Bounds::from_points(&[[9.0, -1.0, -1.0], [11.0, 1.0, 1.0]]);
+        let mut instances = Instances::new(
+            (vec![translation(0.0)], vec![material_info(0.0)]),
+            vec![0],
+            vec![Some(skinned)],
         );
+        assert_eq!(
+            center(instances.world_bounds(0, &mesh_bounds)),
+            Point3::new(10.0, 0.0, 0.0)
+        );
+
+        assert!(instances.update(0, translation(3.0), material_info(0.5)));
+        assert_eq!(
+            center(instances.world_bounds(0, &mesh_bounds)),
+            Point3::new(13.0, 0.0, 0.0)
+        );
+        assert_eq!(instances.material_infos[0].info.x, 0.5);
+        assert!(!instances.update(1, translation(3.0), material_info(0.5)));
+    }
+
+    #[test]
+    fn removing_moves_the_later_instances_down() {
+        let mesh_bounds = Bounds::from_points(&[[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]]);
+        let skinned = Bounds::from_points(&[[9.0, -1.0, -1.0], [11.0, 1.0, 1.0]]);
+        let mut instances = Instances::new(
+            (
+                vec![translation(0.0), translation(0.0)],
+                vec![material_info(0.0), material_info(1.0)],
+            ),
+            vec![0, 1],
+            vec![None, Some(skinned)],
+        );
+        let (_, removed) = instances.remove(0).unwrap();
+        assert_eq!(removed.info.x, 0.0);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances.groups, vec![1]);
+        assert_eq!(
+            center(instances.world_bounds(0, &mesh_bounds)),
+            Point3::new(10.0, 0.0, 0.0)
+        );
+        assert!(instances.remove(1).is_none());
+    }
+
+    fn assets() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/assets")
+    }
+
+    /// Exports `live` as a `.glb` and loads it back.
+    fn reload(name: &str, live: &model::Model) -> model::Model {
+        let out_dir =
+            std::env::temp_dir().join(format!("skinning-live-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let path = out_dir.join(Path::new(name).with_extension("glb"));
+        export_gltf(live, &assets(), &path).unwrap();
+        let reloaded = load_gltf(&path).unwrap();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+        reloaded
+    }
+
+    fn assert_transform(actual: &TransformRaw, expected: Matrix4<f32>) {
+        let (actual, expected): ([[f32; 4]; 4], [[f32; 4]; 4]) =
+            (actual.model.into(), expected.into());
+        for (a, e) in actual.iter().flatten().zip(expected.iter().flatten()) {
+            assert!((a - e).abs() < 1e-5, "{:?} isn't {:?}", actual, expected);
+        }
+    }
+
+    fn uniforms(model: &model::Model) -> Vec<PbrMaterialUniforms> {
+        model
+            .materials
+            .iter()
+            .map(PbrMaterialUniforms::from)
+            .collect()
+    }
+
+    #[test]
+    fn moved_and_spawned_instances_are_exported() {
+        let model = load_gltf(assets().join("textured.gltf")).unwrap();
+        let mut instances = Instances::from_model(&model);
+        let moved =
+            Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0)) * instances.transforms[0].model;
+        assert!(instances.update(0, TransformRaw { model: moved }, material_info(1.0)));
+        let spawned = Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0));
+        instances.push(0, TransformRaw { model: spawned }, material_info(1.0));
+        let mut materials = uniforms(&model);
+        materials[0].factors[0] = 0.75;
+        materials[0].base_color = [0.5, 0.5, 0.5, 1.0];
+
+        let reloaded = reload("moved", &live_model(&model, &instances, &materials));
+        let reloaded_instances = Instances::from_model(&reloaded);
+        assert_eq!(reloaded_instances.len(), 2);
+        assert_transform(&reloaded_instances.transforms[0], moved);
+        assert_transform(&reloaded_instances.transforms[1], spawned);
+        assert_eq!(reloaded.scene, Some(0));
+        assert_eq!(reloaded.materials[0].metallic_factor, 0.75);
+        assert_eq!(
+            reloaded.materials[0].base_color_factor,
+            [0.5, 0.5, 0.5, 1.0]
+        );
+        assert_eq!(reloaded.materials[0].roughness_factor, 0.6);
+        assert!(reloaded.materials[0].base_color_texture.is_some());
+    }
+
+    #[test]
+    fn removed_instancing_transforms_are_dropped() {
+        let model = load_gltf(assets().join("instanced.gltf")).unwrap();
+        let mut instances = Instances::from_model(&model);
+        assert_eq!(instances.len(), 3);
+        let last = instances.transforms[2].model;
+        let moved =
+            Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0)) * instances.transforms[0].model;
+        assert!(instances.update(0, TransformRaw { model: moved }, material_info(1.0)));
+        instances.remove(1);
+
+        let reloaded = reload(
+            "instanced",
+            &live_model(&model, &instances, &uniforms(&model)),
+        );
+        assert_eq!(reloaded.instances.len(), 1);
+        let reloaded_instances = Instances::from_model(&reloaded);
+        assert_eq!(reloaded_instances.len(), 2);
+        assert_transform(&reloaded_instances.transforms[0], moved);
+        assert_transform(&reloaded_instances.transforms[1], last);
+
+        // without any transform left the node doesn't draw at all
+        instances.remove(0);
+        instances.remove(0);
+        let live = live_model(&model, &instances, &uniforms(&model));
+        assert!(live.instances.is_empty());
+        assert_eq!(live.nodes[0].mesh, None);
+    }
+
+    #[test]
+    fn moving_a_skinned_instance_moves_its_skeleton() {
+        let model = load_gltf(assets().join("skinned.gltf")).unwrap();
+        let mut instances = Instances::from_model(&model);
+        let mesh_bounds = model.meshes[0].primitives[0].bounds;
+        let before = center(instances.world_bounds(0, &mesh_bounds));
+        let offset = Vector3::new(3.0, 0.0, 0.0);
+        let moved = TransformRaw {
+            model: Matrix4::from_translation(offset),
+        };
+        assert!(instances.update(0, moved, material_info(1.0)));
+
+        let live = live_model(&model, &instances, &uniforms(&model));
+        // the root joint moved, the tip keeps its place relative to it
+        assert_eq!(live.nodes[1].translation, [3.0, 0.0, 0.0]);
+        assert_eq!(live.nodes[2], model.nodes[2]);
+        let reloaded = reload("skinned", &live);
+        let reloaded_instances = Instances::from_model(&reloaded);
+        let after = center(reloaded_instances.world_bounds(0, &mesh_bounds));
+        assert!((after - (before + offset)).magnitude() < 1e-5);
     }
 }
@@ -1,12 +1,120 @@
+use crate::{
+    capture::TextureReadback,
+    pipelines::blit::Blit,
+    render_graph::{RenderGraph, TexturePool, TARGET},
+    staging::StagingBelt,
+};
+use anyhow::{anyhow, bail, Context, Result};
 use winit::window::Window;
 
+/// Size of the staging chunks per-frame uploads are packed into.
+const STAGING_CHUNK_SIZE: wgpu::BufferAddress = 64 * 1024;
+
+/// Frames in a row without a swap-chain texture before giving up.
+const MAX_FAILED_FRAMES: u32 = 10;
+
+/// Copies out of textures need rows aligned to this many bytes.
+pub const ROW_ALIGNMENT: wgpu::BufferAddress = 256;
+
+/// Sample counts `--msaa` accepts, whether the adapter supports them is
+/// checked once there is one, see `sample_counts`.
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Sample counts `backend` supports for the 8-bit RGBA formats targets can
+/// have and `Depth24PlusStencil8`. wgpu 0.5 can't ask the adapter, so these
+/// are the counts the APIs require every device to support: 4 on Vulkan,
+/// Metal and OpenGL, and 4 and 8 on Direct3D feature level 11.
+pub fn sample_counts(backend: wgpu::Backend) -> &'static [u32] {
+    match backend {
+        wgpu::Backend::Dx12 | wgpu::Backend::Dx11 => &[1, 4, 8],
+        _ => &[1, 4],
+    }
+}
+
+/// No adapter matches the configuration, e.g. on a machine without a GPU or
+/// a software rasterizer.
+#[derive(Debug)]
+pub struct NoAdapter;
+
+impl std::fmt::Display for NoAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "no adapter available")
+    }
+}
+
+impl std::error::Error for NoAdapter {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    Presented,
+    /// Nothing was drawn, the window is minimized or the swap chain had to
+    /// be recreated.
+    Skipped,
+}
+
+/// Where frames end up, the window's swap chain or a texture of our own.
+pub enum RenderTarget {
+    Window {
+        surface: wgpu::Surface,
+        swap_chain: wgpu::SwapChain,
+    },
+    Offscreen {
+        texture: wgpu::Texture,
+        view: wgpu::TextureView,
+    },
+}
+
 pub struct Graphics {
-    pub surface: wgpu::Surface,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    /// Size and format of the target, offscreen too.
     pub sc_desc: wgpu::SwapChainDescriptor,
-    pub swap_chain: wgpu::SwapChain,
+    pub target: RenderTarget,
+    pub transients: TexturePool,
+    /// Where passes upload their per-frame data from.
+    pub staging: StagingBelt,
+    /// Samples per pixel of the target, 1 draws straight into it.
+    pub sample_count: u32,
+    // what passes draw into when multisampling, resolved into the target
+    multisampled: Option<(wgpu::Texture, wgpu::TextureView)>,
+    failed_frames: u32,
+    capture_requested: bool,
+    capture_target: Option<CaptureTarget>,
+    // built with the first capture of a window frame
+    blit: Option<Blit>,
+    pending_capture: Option<TextureReadback>,
+}
+
+/// What a window frame is drawn into when it's captured, since swap-chain
+/// textures can't be copied from. It's then blitted into the swap chain.
+struct CaptureTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+/// A colour attachment handed to a pass, cleared unless an earlier pass of
+/// the frame drew into it.
+pub struct ColorTarget<'a> {
+    pub view: &'a wgpu::TextureView,
+    pub resolve_target: Option<&'a wgpu::TextureView>,
+    pub load_op: wgpu::LoadOp,
+}
+
+impl ColorTarget<'_> {
+    pub fn attachment(
+        &self,
+        clear_color: wgpu::Color,
+    ) -> wgpu::RenderPassColorAttachmentDescriptor<'_> {
+        wgpu::RenderPassColorAttachmentDescriptor {
+            attachment: self.view,
+            resolve_target: self.resolve_target,
+            load_op: self.load_op,
+            store_op: wgpu::StoreOp::Store,
+            clear_color,
+        }
+    }
 }
 
 pub trait Render {
@@ -14,23 +122,199 @@ pub trait Render {
         &self,
         device: &wgpu::Device,
         pipeline: &wgpu::RenderPipeline,
-        frame: &wgpu::SwapChainOutput,
+        target: &ColorTarget,
+        staging: &mut StagingBelt,
         encoder: &mut wgpu::CommandEncoder,
     );
 }
 
+/// How the adapter and the target are picked. Each setting comes from a
+/// command line flag, or else an environment variable, or else the default.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphicsConfig {
+    pub backends: wgpu::BackendBit,
+    pub power_preference: wgpu::PowerPreference,
+    /// `Fifo` waits for vsync, `Immediate` doesn't.
+    pub present_mode: wgpu::PresentMode,
+    /// `None` picks `Bgra8UnormSrgb` for windows and `Rgba8UnormSrgb`
+    /// offscreen.
+    pub format: Option<wgpu::TextureFormat>,
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        GraphicsConfig {
+            backends: wgpu::BackendBit::PRIMARY,
+            power_preference: wgpu::PowerPreference::Default,
+            present_mode: wgpu::PresentMode::Fifo,
+            format: None,
+        }
+    }
+}
+
+impl GraphicsConfig {
+    /// Flags taking a value, with the variable used when they're missing.
+    pub const OPTIONS: [(&'static str, &'static str); 4] = [
+        ("--backend", "SKINNING_BACKEND"),
+        ("--power", "SKINNING_POWER"),
+        ("--present-mode", "SKINNING_PRESENT_MODE"),
+        ("--format", "SKINNING_FORMAT"),
+    ];
+
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut config = GraphicsConfig::default();
+        for &(flag, var) in GraphicsConfig::OPTIONS.iter() {
+            let value = match args.iter().position(|arg| arg == flag) {
+                Some(i) => args
+                    .get(i + 1)
+                    .cloned()
+                    .with_context(|| format!("{} needs a value", flag))?,
+                None => match std::env::var(var) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                },
+            };
+            let value = value.to_lowercase();
+            let invalid = || anyhow!("Invalid {} '{}' (from {} or {})", flag, value, flag, var);
+            match flag {
+                "--backend" => {
+                    config.backends = match value.as_str() {
+                        "primary" => wgpu::BackendBit::PRIMARY,
+                        "secondary" => wgpu::BackendBit::SECONDARY,
+                        "vulkan" => wgpu::BackendBit::VULKAN,
+                        "metal" => wgpu::BackendBit::METAL,
+                        "dx12" => wgpu::BackendBit::DX12,
+                        "dx11" => wgpu::BackendBit::DX11,
+                        "gl" => wgpu::BackendBit::GL,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--power" => {
+                    config.power_preference = match value.as_str() {
+                        "default" => wgpu::PowerPreference::Default,
+                        "low" => wgpu::PowerPreference::LowPower,
+                        "high" => wgpu::PowerPreference::HighPerformance,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--present-mode" => {
+                    config.present_mode = match value.as_str() {
+                        "fifo" | "vsync" => wgpu::PresentMode::Fifo,
+                        "mailbox" => wgpu::PresentMode::Mailbox,
+                        "immediate" | "no-vsync" => wgpu::PresentMode::Immediate,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => {
+                    config.format = Some(match value.as_str() {
+                        "bgra8-srgb" => wgpu::TextureFormat::Bgra8UnormSrgb,
+                        "bgra8" => wgpu::TextureFormat::Bgra8Unorm,
+                        "rgba8-srgb" => wgpu::TextureFormat::Rgba8UnormSrgb,
+                        "rgba8" => wgpu::TextureFormat::Rgba8Unorm,
+                        _ => return Err(invalid()),
+                    })
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
 impl Graphics {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window, config: &GraphicsConfig) -> Result<Self> {
         let surface = wgpu::Surface::create(window);
+        let (adapter, device, queue) = Graphics::request_device(config, Some(&surface))
+            .await
+            .with_context(|| format!("No adapter matches {:?}", config))?;
+
+        let size = window.inner_size();
+
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            format: config.format.unwrap_or(wgpu::TextureFormat::Bgra8UnormSrgb),
+            width: size.width,
+            height: size.height,
+            present_mode: config.present_mode,
+        };
+        println!("Surface {:?}, {:?}", sc_desc.format, sc_desc.present_mode);
+
+        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+
+        Ok(Graphics {
+            adapter,
+            device,
+            queue,
+            sc_desc,
+            target: RenderTarget::Window {
+                surface,
+                swap_chain,
+            },
+            transients: TexturePool::default(),
+            staging: StagingBelt::new(STAGING_CHUNK_SIZE),
+            sample_count: 1,
+            multisampled: None,
+            failed_frames: 0,
+            capture_requested: false,
+            capture_target: None,
+            blit: None,
+            pending_capture: None,
+        })
+    }
+
+    /// Renders into a texture instead of a window, for tools and CI where
+    /// there is no display. The frame is read back with `read_image`.
+    pub async fn headless(width: u32, height: u32, config: &GraphicsConfig) -> Result<Self> {
+        let (adapter, device, queue) = Graphics::request_device(config, None)
+            .await
+            .ok_or(NoAdapter)
+            .with_context(|| format!("No adapter for headless rendering matches {:?}", config))?;
+
+        // not a real swap chain, it describes the offscreen texture so the
+        // pipelines can be built the same way
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            format: config.format.unwrap_or(wgpu::TextureFormat::Rgba8UnormSrgb),
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        let target = Graphics::create_offscreen_target(&device, &sc_desc);
+
+        Ok(Graphics {
+            adapter,
+            device,
+            queue,
+            sc_desc,
+            target,
+            transients: TexturePool::default(),
+            staging: StagingBelt::new(STAGING_CHUNK_SIZE),
+            sample_count: 1,
+            multisampled: None,
+            failed_frames: 0,
+            capture_requested: false,
+            capture_target: None,
+            blit: None,
+            pending_capture: None,
+        })
+    }
+
+    async fn request_device(
+        config: &GraphicsConfig,
+        compatible_surface: Option<&wgpu::Surface>,
+    ) -> Option<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
         let adapter = wgpu::Adapter::request(
             &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::Default,
-                compatible_surface: Some(&surface),
+                power_preference: config.power_preference,
+                compatible_surface,
             },
-            wgpu::BackendBit::PRIMARY,
+            config.backends,
         )
-        .await
-        .expect("Failed to get adapter");
+        .await?;
+        let info = adapter.get_info();
+        println!(
+            "Adapter {} ({:?} on {:?}, vendor {:#x}, device {:#x})",
+            info.name, info.device_type, info.backend, info.vendor, info.device
+        );
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
@@ -41,48 +325,304 @@ impl Graphics {
             })
             .await;
 
-        let size = window.inner_size();
+        Some((adapter, device, queue))
+    }
 
-        let sc_desc = wgpu::SwapChainDescriptor {
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
+    fn create_offscreen_target(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> RenderTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_texture"),
+            size: wgpu::Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: sc_desc.format,
+            usage: sc_desc.usage,
+        });
+        let view = texture.create_default_view();
+        RenderTarget::Offscreen { texture, view }
+    }
 
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+    /// Sample counts the adapter supports, see `sample_counts`.
+    pub fn sample_counts(&self) -> &'static [u32] {
+        sample_counts(self.adapter.get_info().backend)
+    }
 
-        Graphics {
-            surface,
-            adapter,
-            device,
-            queue,
-            sc_desc,
-            swap_chain,
+    /// Fails for counts the adapter doesn't support.
+    pub fn check_sample_count(&self, sample_count: u32) -> Result<()> {
+        let supported = self.sample_counts();
+        if !supported.contains(&sample_count) {
+            bail!(
+                "MSAA {}x isn't supported on {:?}, pick one of {:?}",
+                sample_count,
+                self.adapter.get_info().backend,
+                supported
+            );
         }
+        Ok(())
+    }
+
+    /// Pipelines drawing into the target have to be built with the same
+    /// count.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.multisampled = self.create_multisampled_target();
+    }
+
+    fn create_multisampled_target(&self) -> Option<(wgpu::Texture, wgpu::TextureView)> {
+        if self.sample_count == 1 || self.is_minimized() {
+            return None;
+        }
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("multisampled_texture"),
+            size: wgpu::Extent3d {
+                width: self.sc_desc.width,
+                height: self.sc_desc.height,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: self.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.sc_desc.format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        });
+        let view = texture.create_default_view();
+        Some((texture, view))
+    }
+
+    /// A zero-sized window, frames are skipped until it's resized again.
+    pub fn is_minimized(&self) -> bool {
+        self.sc_desc.width == 0 || self.sc_desc.height == 0
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        self.transients.clear();
+        self.capture_target = None;
+        self.multisampled = self.create_multisampled_target();
+        // the swap chain can't be zero-sized, keep the old one around
+        if self.is_minimized() {
+            return;
+        }
+        match &mut self.target {
+            RenderTarget::Window {
+                surface,
+                swap_chain,
+            } => *swap_chain = self.device.create_swap_chain(surface, &self.sc_desc),
+            target @ RenderTarget::Offscreen { .. } => {
+                *target = Graphics::create_offscreen_target(&self.device, &self.sc_desc)
+            }
+        }
     }
 
-    pub fn render(&mut self, pipeline: &wgpu::RenderPipeline, pass: &'_ dyn Render) {
-        let frame = self
-            .swap_chain
-            .get_next_texture()
-            .expect("Timeout getting texture");
+    pub fn render(
+        &mut self,
+        pipeline: &wgpu::RenderPipeline,
+        pass: &'_ dyn Render,
+    ) -> Result<Frame> {
+        let mut graph = RenderGraph::new();
+        graph.add_render("main", pipeline, pass, TARGET);
+        self.execute(&graph)
+    }
 
+    /// Records every pass of the frame, uploads included, into one encoder
+    /// and submits it.
+    /// Missing swap-chain textures skip the frame, and only fail once they
+    /// keep missing.
+    pub fn execute(&mut self, graph: &RenderGraph) -> Result<Frame> {
+        if self.is_minimized() {
+            return Ok(Frame::Skipped);
+        }
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        if self.capture_requested {
+            if let RenderTarget::Window { .. } = self.target {
+                self.create_capture_target();
+            }
+        }
+        let size = (self.sc_desc.width, self.sc_desc.height);
+        let multisampled = self.multisampled.as_ref().map(|(_, view)| view);
+        // passes draw into the multisampled texture and resolve into the
+        // target, or straight into the target
+        let targets = |view| match multisampled {
+            Some(multisampled) => (multisampled, Some(view)),
+            None => (view, None),
+        };
 
-        pass.render(&self.device, &pipeline, &frame, &mut encoder);
+        let mut capture = None;
+        match &mut self.target {
+            RenderTarget::Window {
+                surface,
+                swap_chain,
+            } => {
+                let frame = match swap_chain.get_next_texture() {
+                    Ok(frame) => frame,
+                    Err(wgpu::TimeOut) => {
+                        self.failed_frames += 1;
+                        if self.failed_frames >= MAX_FAILED_FRAMES {
+                            return Err(anyhow!(
+                                "No swap-chain texture for {} frames in a row",
+                                self.failed_frames
+                            ));
+                        }
+                        // an outdated or lost swap chain shows up as a
+                        // timeout too, a new one fixes those
+                        *swap_chain = self.device.create_swap_chain(surface, &self.sc_desc);
+                        return Ok(Frame::Skipped);
+                    }
+                };
+                self.failed_frames = 0;
+                match (self.capture_requested, &self.capture_target, &self.blit) {
+                    (true, Some(capture_target), Some(blit)) => {
+                        // draw once into the capture texture and show that
+                        let (target, resolve_target) = targets(&capture_target.view);
+                        graph.execute(
+                            &self.device,
+                            target,
+                            resolve_target,
+                            size,
+                            self.sample_count,
+                            &mut self.transients,
+                            &mut self.staging,
+                            &mut encoder,
+                        )?;
+                        capture = Some(TextureReadback::record(
+                            &self.device,
+                            &mut encoder,
+                            &capture_target.texture,
+                            self.sc_desc.format,
+                            size,
+                        )?);
+                        blit.draw(&mut encoder, &capture_target.bind_group, &frame.view);
+                    }
+                    _ => {
+                        let (target, resolve_target) = targets(&frame.view);
+                        graph.execute(
+                            &self.device,
+                            target,
+                            resolve_target,
+                            size,
+                            self.sample_count,
+                            &mut self.transients,
+                            &mut self.staging,
+                            &mut encoder,
+                        )?;
+                    }
+                }
+                self.staging.finish();
+                self.queue.submit(&[encoder.finish()]);
+                self.staging.recall(&self.device);
+            }
+            RenderTarget::Offscreen { texture, view } => {
+                let (target, resolve_target) = targets(view);
+                graph.execute(
+                    &self.device,
+                    target,
+                    resolve_target,
+                    size,
+                    self.sample_count,
+                    &mut self.transients,
+                    &mut self.staging,
+                    &mut encoder,
+                )?;
+                if self.capture_requested {
+                    capture = Some(TextureReadback::record(
+                        &self.device,
+                        &mut encoder,
+                        texture,
+                        self.sc_desc.format,
+                        size,
+                    )?);
+                }
+                self.staging.finish();
+                self.queue.submit(&[encoder.finish()]);
+                self.staging.recall(&self.device);
+            }
+        }
+        self.capture_requested = false;
+        self.pending_capture = capture;
+        Ok(Frame::Presented)
+    }
 
+    /// Copies the offscreen texture back, blocking until the GPU is done.
+    pub fn read_image(&self) -> Result<image::RgbaImage> {
+        let texture = match &self.target {
+            RenderTarget::Offscreen { texture, .. } => texture,
+            RenderTarget::Window { .. } => {
+                return Err(anyhow!("Only offscreen targets can be read back"))
+            }
+        };
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("readback_encoder"),
+            });
+        let readback = TextureReadback::record(
+            &self.device,
+            &mut encoder,
+            texture,
+            self.sc_desc.format,
+            (self.sc_desc.width, self.sc_desc.height),
+        )?;
         self.queue.submit(&[encoder.finish()]);
+        readback.finish(&self.device)
+    }
+
+    /// Keeps a copy of the next frame, see `take_capture`.
+    pub fn request_capture(&mut self) {
+        self.capture_requested = true;
+    }
+
+    /// The frame asked for with `request_capture`, once it was drawn.
+    /// Blocks until the GPU is done with it.
+    pub fn take_capture(&mut self) -> Option<Result<image::RgbaImage>> {
+        let readback = self.pending_capture.take()?;
+        Some(readback.finish(&self.device))
+    }
+
+    /// Creates the texture a window frame is drawn into for a capture, and
+    /// the blit showing it.
+    fn create_capture_target(&mut self) {
+        let (device, sc_desc) = (&self.device, &self.sc_desc);
+        let blit = self
+            .blit
+            .get_or_insert_with(|| Blit::new(device, sc_desc.format));
+        self.capture_target.get_or_insert_with(|| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("capture_texture"),
+                size: wgpu::Extent3d {
+                    width: sc_desc.width,
+                    height: sc_desc.height,
+                    depth: 1,
+                },
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: sc_desc.format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
+                    | wgpu::TextureUsage::SAMPLED
+                    | wgpu::TextureUsage::COPY_SRC,
+            });
+            let view = texture.create_default_view();
+            let bind_group = blit.bind_group(device, &view);
+            CaptureTarget {
+                texture,
+                view,
+                bind_group,
+            }
+        });
     }
 }
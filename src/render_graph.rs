@@ -0,0 +1,449 @@
+use crate::{
+    render::{ColorTarget, Render},
+    staging::StagingBelt,
+};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// The swap-chain frame, or the offscreen texture of a headless `Graphics`.
+pub const TARGET: &str = "target";
+
+/// A texture that only lives for one frame, sized like the target. Drawing
+/// alongside the target, e.g. depth, needs as many samples as it has, which
+/// `multisampled` asks for. Textures that are copied or sampled from leave it
+/// unset, they always have one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransientDesc {
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsage,
+    pub multisampled: bool,
+}
+
+/// The attachments a pass declared, resolved for one frame.
+pub struct PassAttachments<'a> {
+    views: HashMap<&'static str, &'a wgpu::TextureView>,
+    textures: HashMap<&'static str, &'a wgpu::Texture>,
+    resolve_target: Option<&'a wgpu::TextureView>,
+    load_ops: HashMap<&'static str, wgpu::LoadOp>,
+}
+
+impl<'a> PassAttachments<'a> {
+    /// Panics if the pass didn't declare `name`.
+    pub fn view(&self, name: &str) -> &'a wgpu::TextureView {
+        self.views
+            .get(name)
+            .unwrap_or_else(|| panic!("Attachment '{}' wasn't declared by the pass", name))
+    }
+
+    /// The texture behind a transient, e.g. to copy out of it. Panics for the
+    /// target and attachments the pass didn't declare.
+    pub fn texture(&self, name: &str) -> &'a wgpu::Texture {
+        self.textures
+            .get(name)
+            .unwrap_or_else(|| panic!("Attachment '{}' isn't a transient of the pass", name))
+    }
+
+    /// Cleared by the first pass of the frame that writes it, loaded after.
+    pub fn load_op(&self, name: &str) -> wgpu::LoadOp {
+        self.load_ops
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| panic!("Attachment '{}' isn't written by the pass", name))
+    }
+
+    pub fn color(&self, name: &str) -> ColorTarget<'a> {
+        ColorTarget {
+            view: self.view(name),
+            resolve_target: if name == TARGET {
+                self.resolve_target
+            } else {
+                None
+            },
+            load_op: self.load_op(name),
+        }
+    }
+}
+
+pub trait GraphPass {
+    fn execute(
+        &self,
+        device: &wgpu::Device,
+        attachments: &PassAttachments,
+        staging: &mut StagingBelt,
+        encoder: &mut wgpu::CommandEncoder,
+    );
+}
+
+/// Runs a `Render` with its pipeline into a single colour attachment.
+struct RenderNode<'a> {
+    pipeline: &'a wgpu::RenderPipeline,
+    pass: &'a dyn Render,
+    target: &'static str,
+}
+
+impl GraphPass for RenderNode<'_> {
+    fn execute(
+        &self,
+        device: &wgpu::Device,
+        attachments: &PassAttachments,
+        staging: &mut StagingBelt,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.pass.render(
+            device,
+            self.pipeline,
+            &attachments.color(self.target),
+            staging,
+            encoder,
+        );
+    }
+}
+
+struct Node<'a> {
+    name: &'static str,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+    pass: Box<dyn GraphPass + 'a>,
+}
+
+/// The passes of one frame. Passes run after every pass writing what they
+/// read, passes writing the same attachment run in the order they were
+/// added, and passes nothing depends on are skipped. Passes that write
+/// nothing, e.g. copies out of an attachment, always run.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    transients: Vec<(&'static str, TransientDesc)>,
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_transient(&mut self, name: &'static str, desc: TransientDesc) {
+        self.transients.push((name, desc));
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[&'static str],
+        writes: &[&'static str],
+        pass: impl GraphPass + 'a,
+    ) {
+        self.nodes.push(Node {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            pass: Box::new(pass),
+        });
+    }
+
+    /// Adds a `Render` drawing into `target`.
+    pub fn add_render(
+        &mut self,
+        name: &'static str,
+        pipeline: &'a wgpu::RenderPipeline,
+        pass: &'a dyn Render,
+        target: &'static str,
+    ) {
+        self.add_pass(
+            name,
+            &[],
+            &[target],
+            RenderNode {
+                pipeline,
+                pass,
+                target,
+            },
+        );
+    }
+
+    fn transient(&self, name: &str) -> Option<&TransientDesc> {
+        self.transients
+            .iter()
+            .find(|(transient, _)| *transient == name)
+            .map(|(_, desc)| desc)
+    }
+
+    /// Indices of the passes that contribute to the target, in the order
+    /// they have to run.
+    fn schedule(&self) -> Result<Vec<usize>> {
+        for node in &self.nodes {
+            for &name in node.reads.iter().chain(&node.writes) {
+                if name != TARGET && self.transient(name).is_none() {
+                    bail!("Pass '{}' uses unknown attachment '{}'", node.name, name);
+                }
+            }
+        }
+
+        let writes = |i: usize, name: &str| self.nodes[i].writes.contains(&name);
+        let mut dependencies = vec![Vec::new(); self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &name in &node.reads {
+                if writes(i, name) {
+                    continue;
+                }
+                let writers: Vec<_> = (0..self.nodes.len()).filter(|&j| writes(j, name)).collect();
+                if writers.is_empty() {
+                    bail!("Pass '{}' reads '{}' which nothing writes", node.name, name);
+                }
+                dependencies[i].extend(writers);
+            }
+            for &name in &node.writes {
+                dependencies[i].extend((0..i).filter(|&j| writes(j, name)));
+            }
+        }
+
+        // a pass is needed if it writes the target or nothing at all, or
+        // something a needed pass reads or draws over
+        let mut live = vec![false; self.nodes.len()];
+        let mut stack: Vec<_> = (0..self.nodes.len())
+            .filter(|&i| writes(i, TARGET) || self.nodes[i].writes.is_empty())
+            .collect();
+        while let Some(i) = stack.pop() {
+            if !live[i] {
+                live[i] = true;
+                stack.extend(&dependencies[i]);
+            }
+        }
+
+        // topological order, earliest added first among the ready passes
+        let mut order = Vec::new();
+        let mut done = vec![false; self.nodes.len()];
+        while let Some(i) = (0..self.nodes.len())
+            .find(|&i| live[i] && !done[i] && dependencies[i].iter().all(|&j| done[j]))
+        {
+            done[i] = true;
+            order.push(i);
+        }
+        if order.len() < live.iter().filter(|&&live| live).count() {
+            let stuck: Vec<_> = (0..self.nodes.len())
+                .filter(|&i| live[i] && !done[i])
+                .map(|i| self.nodes[i].name)
+                .collect();
+            bail!("Render graph has a cycle through {}", stuck.join(", "));
+        }
+        Ok(order)
+    }
+
+    /// Records the passes into `encoder`, with transients from `pool` and
+    /// uploads through `staging`. The target has `sample_count` samples and
+    /// is resolved into `resolve_target` when it has more than one.
+    pub fn execute(
+        &self,
+        device: &wgpu::Device,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        size: (u32, u32),
+        sample_count: u32,
+        pool: &mut TexturePool,
+        staging: &mut StagingBelt,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<()> {
+        let order = self.schedule()?;
+
+        // acquire every transient before its first use and hand it back
+        // after its last, so transients of the same kind can share a texture
+        let mut last_use = HashMap::new();
+        for (step, &i) in order.iter().enumerate() {
+            for &name in self.nodes[i].reads.iter().chain(&self.nodes[i].writes) {
+                last_use.insert(name, step);
+            }
+        }
+        let mut textures: HashMap<&str, usize> = HashMap::new();
+        let mut step_textures = Vec::with_capacity(order.len());
+        for (step, &i) in order.iter().enumerate() {
+            let node = &self.nodes[i];
+            for &name in node.reads.iter().chain(&node.writes) {
+                if let Some(desc) = self.transient(name) {
+                    if !textures.contains_key(name) {
+                        let slot = pool.acquire(device, desc, size, sample_count);
+                        textures.insert(name, slot);
+                    }
+                }
+            }
+            step_textures.push(textures.clone());
+            for (&name, &slot) in &textures {
+                if last_use[name] == step {
+                    pool.release(slot);
+                }
+            }
+            textures.retain(|name, _| last_use[name] > step);
+        }
+
+        let mut written = Vec::new();
+        for (&i, slots) in order.iter().zip(&step_textures) {
+            let node = &self.nodes[i];
+            let view = |name: &str| match slots.get(name) {
+                Some(&slot) => pool.view(slot),
+                None => target,
+            };
+            let attachments = PassAttachments {
+                views: node
+                    .reads
+                    .iter()
+                    .chain(&node.writes)
+                    .map(|&name| (name, view(name)))
+                    .collect(),
+                textures: slots
+                    .iter()
+                    .map(|(&name, &slot)| (name, pool.texture(slot)))
+                    .collect(),
+                resolve_target,
+                load_ops: node
+                    .writes
+                    .iter()
+                    .map(|&name| {
+                        let load_op = if written.contains(&name) {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear
+                        };
+                        (name, load_op)
+                    })
+                    .collect(),
+            };
+            node.pass.execute(device, &attachments, staging, encoder);
+            written.extend(&node.writes);
+        }
+        Ok(())
+    }
+}
+
+struct PooledTexture {
+    desc: TransientDesc,
+    size: (u32, u32),
+    sample_count: u32,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    in_use: bool,
+}
+
+/// Transient textures kept across frames, so a graph only allocates the
+/// first time it runs at a given size.
+#[derive(Default)]
+pub struct TexturePool {
+    textures: Vec<PooledTexture>,
+}
+
+impl TexturePool {
+    fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &TransientDesc,
+        size: (u32, u32),
+        target_sample_count: u32,
+    ) -> usize {
+        let sample_count = if desc.multisampled {
+            target_sample_count
+        } else {
+            1
+        };
+        if let Some(slot) = self.textures.iter().position(|t| {
+            !t.in_use && t.desc == *desc && t.size == size && t.sample_count == sample_count
+        }) {
+            self.textures[slot].in_use = true;
+            return slot;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("transient_texture"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format,
+            usage: desc.usage,
+        });
+        let view = texture.create_default_view();
+        self.textures.push(PooledTexture {
+            desc: *desc,
+            size,
+            sample_count,
+            texture,
+            view,
+            in_use: true,
+        });
+        self.textures.len() - 1
+    }
+
+    fn release(&mut self, slot: usize) {
+        self.textures[slot].in_use = false;
+    }
+
+    fn view(&self, slot: usize) -> &wgpu::TextureView {
+        &self.textures[slot].view
+    }
+
+    fn texture(&self, slot: usize) -> &wgpu::Texture {
+        &self.textures[slot].texture
+    }
+
+    /// Drops the textures, e.g. when the target is resized.
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop;
+
+    impl GraphPass for Noop {
+        fn execute(
+            &self,
+            _device: &wgpu::Device,
+            _attachments: &PassAttachments,
+            _staging: &mut StagingBelt,
+            _encoder: &mut wgpu::CommandEncoder,
+        ) {
+        }
+    }
+
+    const IDS: &str = "ids";
+    const UNUSED: &str = "unused";
+
+    fn graph() -> RenderGraph<'static> {
+        let desc = TransientDesc {
+            format: wgpu::TextureFormat::R32Uint,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            multisampled: false,
+        };
+        let mut graph = RenderGraph::new();
+        graph.add_transient(IDS, desc);
+        graph.add_transient(UNUSED, desc);
+        graph
+    }
+
+    #[test]
+    fn passes_writing_nothing_run_after_their_inputs() {
+        let mut graph = graph();
+        graph.add_pass("readback", &[IDS], &[], Noop);
+        graph.add_pass("draw", &[], &[TARGET, IDS], Noop);
+        graph.add_pass("overlay", &[], &[TARGET], Noop);
+        assert_eq!(graph.schedule().unwrap(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn unused_passes_are_skipped() {
+        let mut graph = graph();
+        graph.add_pass("draw", &[], &[TARGET], Noop);
+        graph.add_pass("unused", &[], &[UNUSED], Noop);
+        assert_eq!(graph.schedule().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn unwritten_reads_are_rejected() {
+        let mut graph = graph();
+        graph.add_pass("readback", &[IDS], &[], Noop);
+        assert!(graph.schedule().is_err());
+    }
+}
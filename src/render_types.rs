@@ -1,4 +1,4 @@
-use crate::camera::Camera;
+use crate::{camera::Camera, model, obj};
 use std::mem;
 use wgpu::vertex_attr_array;
 
@@ -6,6 +6,10 @@ pub trait VertexDesc {
     fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a>;
 }
 
+pub trait VertexPosition {
+    fn position(&self) -> [f32; 3];
+}
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -33,6 +37,12 @@ impl VertexDesc for VertexPlain {
 unsafe impl bytemuck::Pod for VertexPlain {}
 unsafe impl bytemuck::Zeroable for VertexPlain {}
 
+impl VertexPosition for VertexPlain {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct VertexTex {
@@ -53,6 +63,12 @@ impl VertexDesc for VertexTex {
 unsafe impl bytemuck::Pod for VertexTex {}
 unsafe impl bytemuck::Zeroable for VertexTex {}
 
+impl VertexPosition for VertexTex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct VertexTexNormal {
@@ -76,6 +92,42 @@ impl VertexDesc for VertexTexNormal {
 unsafe impl bytemuck::Pod for VertexTexNormal {}
 unsafe impl bytemuck::Zeroable for VertexTexNormal {}
 
+impl VertexPosition for VertexTexNormal {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VertexTexNormalTangent {
+    pub position: [f32; 3],
+    pub tex_coord: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4], // xyz, handedness
+}
+
+impl VertexDesc for VertexTexNormalTangent {
+    fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<VertexTexNormalTangent>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &vertex_attr_array![
+                0 => Float3, 1 => Float2, 2 => Float3, 3 => Float4
+            ],
+        }
+    }
+}
+
+unsafe impl bytemuck::Pod for VertexTexNormalTangent {}
+unsafe impl bytemuck::Zeroable for VertexTexNormalTangent {}
+
+impl VertexPosition for VertexTexNormalTangent {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct MvpUniforms {
@@ -129,10 +181,143 @@ pub struct PbrFragmentUniforms {
 unsafe impl bytemuck::Pod for PbrFragmentUniforms {}
 unsafe impl bytemuck::Zeroable for PbrFragmentUniforms {}
 
+/// Colour and pixel width of the selection outline.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct OutlineUniforms {
+    pub color: [f32; 4],
+    pub viewport: [f32; 2],
+    pub width: f32,
+    _padding: f32,
+}
+
+unsafe impl bytemuck::Pod for OutlineUniforms {}
+unsafe impl bytemuck::Zeroable for OutlineUniforms {}
+
+impl OutlineUniforms {
+    pub fn new(color: [f32; 4], width: f32, sc_desc: &wgpu::SwapChainDescriptor) -> Self {
+        OutlineUniforms {
+            color,
+            viewport: [sc_desc.width as f32, sc_desc.height as f32],
+            width,
+            _padding: 0.0,
+        }
+    }
+
+    pub fn resize(&mut self, sc_desc: &wgpu::SwapChainDescriptor) {
+        self.viewport = [sc_desc.width as f32, sc_desc.height as f32];
+    }
+}
+
+pub const UV_ALBEDO: usize = 0;
+pub const UV_ROUGHNESS: usize = 1;
+pub const UV_AMBIENT_OCCLUSION: usize = 2;
+pub const UV_NORMALS: usize = 3;
+pub const UV_EMISSIVE: usize = 4;
+pub const UV_CLEARCOAT: usize = 5;
+pub const UV_CLEARCOAT_ROUGHNESS: usize = 6;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PbrMaterialUniforms {
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 4],                // color, strength
+    pub factors: [f32; 4],                 // metallic, roughness, clearcoat, clearcoat roughness
+    pub flags: [u32; 4], // unlit, DirectX normal map, glTF metallic roughness, padding
+    pub uv_transforms: [[[f32; 4]; 2]; 7], // two rows per texture, indexed by UV_*
+}
+
+unsafe impl bytemuck::Pod for PbrMaterialUniforms {}
+unsafe impl bytemuck::Zeroable for PbrMaterialUniforms {}
+
+impl Default for PbrMaterialUniforms {
+    fn default() -> Self {
+        let identity = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]];
+        PbrMaterialUniforms {
+            base_color: [1.0; 4],
+            emissive: [0.0, 0.0, 0.0, 1.0],
+            factors: [1.0, 1.0, 0.0, 0.0],
+            flags: [0; 4],
+            uv_transforms: [identity; 7],
+        }
+    }
+}
+
+impl PbrMaterialUniforms {
+    pub fn set_uv_transform(&mut self, slot: usize, transform: &model::TextureTransform) {
+        let [row0, row1] = transform.rows();
+        self.uv_transforms[slot] = [
+            [row0[0], row0[1], row0[2], 0.0],
+            [row1[0], row1[1], row1[2], 0.0],
+        ];
+    }
+}
+
+impl From<&model::Material> for PbrMaterialUniforms {
+    fn from(material: &model::Material) -> Self {
+        let [r, g, b] = material.emissive_factor;
+        let (clearcoat, clearcoat_roughness) = material
+            .clearcoat
+            .as_ref()
+            .map_or((0.0, 0.0), |c| (c.factor, c.roughness_factor));
+
+        let mut uniforms = PbrMaterialUniforms {
+            base_color: material.base_color_factor,
+            emissive: [r, g, b, material.emissive_strength],
+            factors: [
+                material.metallic_factor,
+                material.roughness_factor,
+                clearcoat,
+                clearcoat_roughness,
+            ],
+            // metallic in blue and roughness in green of the same texture
+            flags: [material.unlit as u32, 0, 1, 0],
+            ..PbrMaterialUniforms::default()
+        };
+        let coat = material.clearcoat.as_ref();
+        let slots = [
+            (UV_ALBEDO, &material.base_color_texture),
+            (UV_ROUGHNESS, &material.metallic_roughness_texture),
+            (UV_AMBIENT_OCCLUSION, &material.occlusion_texture),
+            (UV_NORMALS, &material.normal_texture),
+            (UV_EMISSIVE, &material.emissive_texture),
+            (UV_CLEARCOAT, coat.map_or(&None, |c| &c.texture)),
+            (
+                UV_CLEARCOAT_ROUGHNESS,
+                coat.map_or(&None, |c| &c.roughness_texture),
+            ),
+        ];
+        for (slot, texture) in slots.iter() {
+            if let Some(texture) = texture {
+                uniforms.set_uv_transform(*slot, &texture.transform);
+            }
+        }
+        uniforms
+    }
+}
+
+impl From<&obj::ObjMaterial> for PbrMaterialUniforms {
+    fn from(material: &obj::ObjMaterial) -> Self {
+        let [r, g, b] = material.diffuse;
+        let [er, eg, eb] = material.emissive;
+        PbrMaterialUniforms {
+            base_color: [r, g, b, material.dissolve],
+            emissive: [er, eg, eb, 1.0],
+            factors: [
+                material.metallic.unwrap_or(0.0),
+                material.pbr_roughness(),
+                0.0,
+                0.0,
+            ],
+            ..PbrMaterialUniforms::default()
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct MaterialInfoRaw {
-    pub info: cgmath::Vector4<f32>, // metallic, roughness, ao, padding
+    pub info: cgmath::Vector4<f32>, // metallic, roughness, ao multipliers, padding
 }
 
 unsafe impl bytemuck::Pod for MaterialInfoRaw {}
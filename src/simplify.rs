@@ -0,0 +1,590 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+/// Each level targets half the triangles of the previous one.
+const LOD_REDUCTION: f32 = 0.5;
+/// A level that can't get below this fraction of the previous one ends the
+/// chain, the mesh is as simple as it gets without breaking its topology.
+const MIN_LOD_REDUCTION: f32 = 0.9;
+/// Positions closer than this fraction of the mesh extent are welded, a
+/// generated sphere's seam and poles differ in the last bits.
+const WELD_TOLERANCE: f32 = 1e-5;
+/// Collapses may not leave triangles thinner than this, 1 is equilateral.
+const MIN_TRIANGLE_QUALITY: f64 = 0.1;
+
+pub struct LodIndices {
+    pub indices: Vec<u16>,
+    /// Largest deviation from the original surface, in mesh units.
+    pub error: f32,
+}
+
+/// Symmetric 4x4 error quadric, upper triangle.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: [f64; 3], d: f64, weight: f64) -> Self {
+        let [a, b, c] = normal;
+        let mut quadric = Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ]);
+        for value in quadric.0.iter_mut() {
+            *value *= weight;
+        }
+        quadric
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (value, other) in sum.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+        sum
+    }
+
+    /// Sum of squared distances of `p` to the accumulated planes.
+    fn error(&self, p: [f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = p;
+        let error = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        error.max(0.0)
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Closest point to `p` on the triangle, edges and corners included
+/// (Ericson, Real-Time Collision Detection 5.1.5).
+fn closest_point_on_triangle(p: [f64; 3], [a, b, c]: [[f64; 3]; 3]) -> [f64; 3] {
+    let along = |from: [f64; 3], edge: [f64; 3], t: f64| {
+        [
+            from[0] + edge[0] * t,
+            from[1] + edge[1] * t,
+            from[2] + edge[2] * t,
+        ]
+    };
+    let (ab, ac, ap) = (sub(b, a), sub(c, a), sub(p, a));
+    let (d1, d2) = (dot(ab, ap), dot(ac, ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = sub(p, b);
+    let (d3, d4) = (dot(ab, bp), dot(ac, bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return along(a, ab, d1 / (d1 - d3));
+    }
+    let cp = sub(p, c);
+    let (d5, d6) = (dot(ab, cp), dot(ac, cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return along(a, ac, d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return along(b, sub(c, b), (d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denominator = va + vb + vc;
+    if denominator == 0.0 {
+        // a degenerate triangle, its corner is as good as any point
+        return a;
+    }
+    let (v, w) = (vb / denominator, vc / denominator);
+    let on_ab = along(a, ab, v);
+    along(on_ab, ac, w)
+}
+
+/// Area relative to the squared edge lengths, 1 for an equilateral triangle
+/// and 0 for a degenerate one.
+fn quality([a, b, c]: [[f64; 3]; 3]) -> f64 {
+    let (ab, bc, ca) = (sub(b, a), sub(c, b), sub(a, c));
+    let normal = cross(ab, sub(c, a));
+    let edges = dot(ab, ab) + dot(bc, bc) + dot(ca, ca);
+    if edges == 0.0 {
+        return 0.0;
+    }
+    2.0 * 3f64.sqrt() * dot(normal, normal).sqrt() / edges
+}
+
+/// Collapse of wedge `from` onto the position of wedge `to`.
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    // versions of both wedges when the cost was computed
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // cheapest first out of the max-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Edge collapse simplification driven by quadric error metrics (Garland and
+/// Heckbert), keeping the original vertices so every level can share one
+/// vertex buffer.
+///
+/// Vertices are welded by position first, so uv seams collapse together and
+/// each original vertex is snapped to the vertex of its new position with the
+/// closest uv, seam wedges only collapse into other seam wedges so no
+/// triangle ends up spanning the seam. Open borders are locked, which keeps
+/// meshes that share a border watertight.
+struct Simplifier<'a> {
+    tex_coords: &'a [[f32; 2]],
+    indices: &'a [u16],
+    // vertex -> wedge, wedge -> vertices
+    wedge_of: Vec<usize>,
+    wedges: Vec<Vec<u16>>,
+    positions: Vec<[f64; 3]>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,
+    // wedges split by a uv seam, they only slide along the seam
+    seam: Vec<bool>,
+    versions: Vec<u32>,
+    // wedge collapsed into, itself while alive
+    parent: Vec<usize>,
+    // live triangles around each wedge, may hold stale entries
+    wedge_triangles: Vec<Vec<usize>>,
+    alive: Vec<bool>,
+    alive_count: usize,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(positions: &[[f32; 3]], tex_coords: &'a [[f32; 2]], indices: &'a [u16]) -> Self {
+        let extent = positions
+            .iter()
+            .flat_map(|p| p.iter())
+            .fold(0.0f32, |extent, x| extent.max(x.abs()));
+        let cell = (extent * WELD_TOLERANCE).max(f32::MIN_POSITIVE);
+        let mut lookup: HashMap<[i64; 3], usize> = HashMap::new();
+        let mut wedges: Vec<Vec<u16>> = Vec::new();
+        let mut wedge_positions = Vec::new();
+        let wedge_of: Vec<usize> = positions
+            .iter()
+            .enumerate()
+            .map(|(vertex, p)| {
+                let key = [
+                    (p[0] / cell).round() as i64,
+                    (p[1] / cell).round() as i64,
+                    (p[2] / cell).round() as i64,
+                ];
+                let wedge = *lookup.entry(key).or_insert_with(|| {
+                    wedges.push(Vec::new());
+                    wedge_positions.push([p[0] as f64, p[1] as f64, p[2] as f64]);
+                    wedges.len() - 1
+                });
+                wedges[wedge].push(vertex as u16);
+                wedge
+            })
+            .collect();
+
+        let wedge_count = wedges.len();
+        let triangle_count = indices.len() / 3;
+        let mut simplifier = Simplifier {
+            tex_coords,
+            indices,
+            wedge_of,
+            wedges,
+            positions: wedge_positions,
+            quadrics: vec![Quadric::default(); wedge_count],
+            locked: vec![false; wedge_count],
+            seam: vec![false; wedge_count],
+            versions: vec![0; wedge_count],
+            parent: (0..wedge_count).collect(),
+            wedge_triangles: vec![Vec::new(); wedge_count],
+            alive: vec![false; triangle_count],
+            alive_count: 0,
+            heap: BinaryHeap::new(),
+        };
+
+        for (wedge, vertices) in simplifier.wedges.iter().enumerate() {
+            let first = tex_coords[vertices[0] as usize];
+            simplifier.seam[wedge] = vertices.iter().any(|&v| tex_coords[v as usize] != first);
+        }
+
+        let mut edge_counts: HashMap<(usize, usize), u32> = HashMap::new();
+        for triangle in 0..triangle_count {
+            let corners = simplifier.corners(triangle);
+            if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+                continue;
+            }
+            simplifier.alive[triangle] = true;
+            simplifier.alive_count += 1;
+
+            let [a, b, c] = corners.map_positions(&simplifier.positions);
+            let normal = cross(sub(b, a), sub(c, a));
+            let length = dot(normal, normal).sqrt();
+            if length > 0.0 {
+                let normal = [normal[0] / length, normal[1] / length, normal[2] / length];
+                // area weighted, so a fan of tiny triangles doesn't outvote
+                // the large ones
+                let quadric = Quadric::from_plane(normal, -dot(normal, a), length * 0.5);
+                for &wedge in corners.iter() {
+                    simplifier.quadrics[wedge] = simplifier.quadrics[wedge].add(&quadric);
+                }
+            }
+            for (i, &wedge) in corners.iter().enumerate() {
+                simplifier.wedge_triangles[wedge].push(triangle);
+                let next = corners[(i + 1) % 3];
+                *edge_counts
+                    .entry((wedge.min(next), wedge.max(next)))
+                    .or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edge_counts {
+            if count == 1 {
+                simplifier.locked[a] = true;
+                simplifier.locked[b] = true;
+            }
+        }
+        for &(a, b) in edge_counts.keys() {
+            simplifier.push_edge(a, b);
+        }
+        simplifier
+    }
+
+    fn find(&mut self, wedge: usize) -> usize {
+        let mut root = wedge;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut node = wedge;
+        while self.parent[node] != root {
+            let next = self.parent[node];
+            self.parent[node] = root;
+            node = next;
+        }
+        root
+    }
+
+    fn corners(&mut self, triangle: usize) -> Corners {
+        let mut corners = [0; 3];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let wedge = self.wedge_of[self.indices[triangle * 3 + i] as usize];
+            *corner = self.find(wedge);
+        }
+        Corners(corners)
+    }
+
+    fn push_edge(&mut self, a: usize, b: usize) {
+        let quadric = self.quadrics[a].add(&self.quadrics[b]);
+        for &(from, to) in [(a, b), (b, a)].iter() {
+            if self.locked[from] || (self.seam[from] && !self.seam[to]) {
+                continue;
+            }
+            self.heap.push(Collapse {
+                cost: quadric.error(self.positions[to]),
+                from,
+                to,
+                versions: (self.versions[from], self.versions[to]),
+            });
+        }
+    }
+
+    fn live_triangles(&mut self, wedge: usize) -> Vec<usize> {
+        let mut triangles = std::mem::take(&mut self.wedge_triangles[wedge]);
+        triangles.retain(|&t| self.alive[t]);
+        triangles.sort_unstable();
+        triangles.dedup();
+        self.wedge_triangles[wedge] = triangles.clone();
+        triangles
+    }
+
+    /// Rejects collapses that flip a triangle or pinch the surface into a
+    /// non-manifold edge.
+    fn is_valid(&mut self, from: usize, to: usize) -> bool {
+        let from_triangles = self.live_triangles(from);
+        let to_triangles = self.live_triangles(to);
+
+        let mut from_neighbours = HashSet::new();
+        let mut shared = 0;
+        for &t in &from_triangles {
+            let corners = self.corners(t);
+            if corners.contains(to) {
+                shared += 1;
+                continue;
+            }
+            from_neighbours.extend(corners.iter().copied().filter(|&w| w != from));
+
+            let [a, b, c] = corners.map_positions(&self.positions);
+            let before = cross(sub(b, a), sub(c, a));
+            let moved = corners.replace(from, to).map_positions(&self.positions);
+            let after = cross(sub(moved[1], moved[0]), sub(moved[2], moved[0]));
+            if dot(before, after) <= 0.0 {
+                return false;
+            }
+            let moved_quality = quality(moved);
+            if moved_quality < MIN_TRIANGLE_QUALITY && moved_quality < quality([a, b, c]) {
+                return false;
+            }
+        }
+        let mut common = 0;
+        let mut seen = HashSet::new();
+        for &t in &to_triangles {
+            for wedge in self.corners(t).iter().copied() {
+                if wedge != to && from_neighbours.contains(&wedge) && seen.insert(wedge) {
+                    common += 1;
+                }
+            }
+        }
+        // the link condition, only the edge's own triangles share neighbours
+        common <= shared
+    }
+
+    fn collapse(&mut self, from: usize, to: usize) {
+        for t in self.live_triangles(from) {
+            if self.corners(t).contains(to) {
+                self.alive[t] = false;
+                self.alive_count -= 1;
+            } else {
+                self.wedge_triangles[to].push(t);
+            }
+        }
+        self.parent[from] = to;
+        self.quadrics[to] = self.quadrics[to].add(&self.quadrics[from]);
+        self.versions[to] += 1;
+
+        let mut neighbours = HashSet::new();
+        for t in self.live_triangles(to) {
+            neighbours.extend(self.corners(t).iter().copied().filter(|&w| w != to));
+        }
+        for neighbour in neighbours {
+            self.push_edge(to, neighbour);
+        }
+    }
+
+    fn simplify(&mut self, target_triangles: usize) {
+        while self.alive_count > target_triangles {
+            let candidate = match self.heap.pop() {
+                Some(candidate) => candidate,
+                None => return,
+            };
+            let (from, to) = (candidate.from, candidate.to);
+            if self.parent[from] != from
+                || self.parent[to] != to
+                || candidate.versions != (self.versions[from], self.versions[to])
+            {
+                continue;
+            }
+            if !self.is_valid(from, to) {
+                continue;
+            }
+            self.collapse(from, to);
+        }
+    }
+
+    fn uv_distance(&self, a: u16, b: u16) -> f32 {
+        let [au, av] = self.tex_coords[a as usize];
+        let [bu, bv] = self.tex_coords[b as usize];
+        (au - bu) * (au - bu) + (av - bv) * (av - bv)
+    }
+
+    fn snapshot(&mut self) -> LodIndices {
+        let mut remap: HashMap<u16, u16> = HashMap::new();
+        let mut indices = Vec::with_capacity(self.alive_count * 3);
+        for triangle in 0..self.alive.len() {
+            if !self.alive[triangle] {
+                continue;
+            }
+            for i in 0..3 {
+                let vertex = self.indices[triangle * 3 + i];
+                let wedge = self.find(self.wedge_of[vertex as usize]);
+                let target = match remap.get(&vertex) {
+                    Some(&target) => target,
+                    None => {
+                        let target = *self.wedges[wedge]
+                            .iter()
+                            .min_by(|&&a, &&b| {
+                                self.uv_distance(vertex, a)
+                                    .partial_cmp(&self.uv_distance(vertex, b))
+                                    .unwrap_or(Ordering::Equal)
+                            })
+                            .unwrap_or(&vertex);
+                        remap.insert(vertex, target);
+                        target
+                    }
+                };
+                indices.push(target);
+            }
+        }
+        LodIndices {
+            indices,
+            error: self.error() as f32,
+        }
+    }
+
+    /// Largest distance of an original position to the triangles now around
+    /// the position it collapsed into.
+    fn error(&mut self) -> f64 {
+        let mut error: f64 = 0.0;
+        for wedge in 0..self.wedges.len() {
+            let root = self.find(wedge);
+            if root == wedge {
+                continue;
+            }
+            let p = self.positions[wedge];
+            let distance = self
+                .live_triangles(root)
+                .into_iter()
+                .map(|t| {
+                    let triangle = self.corners(t).map_positions(&self.positions);
+                    let offset = sub(p, closest_point_on_triangle(p, triangle));
+                    dot(offset, offset).sqrt()
+                })
+                .fold(f64::INFINITY, f64::min);
+            if distance.is_finite() {
+                error = error.max(distance);
+            }
+        }
+        error
+    }
+}
+
+struct Corners([usize; 3]);
+
+impl Corners {
+    fn contains(&self, wedge: usize) -> bool {
+        self.0.contains(&wedge)
+    }
+
+    fn iter(&self) -> std::slice::Iter<'_, usize> {
+        self.0.iter()
+    }
+
+    fn replace(&self, from: usize, to: usize) -> Corners {
+        let mut corners = self.0;
+        for corner in corners.iter_mut() {
+            if *corner == from {
+                *corner = to;
+            }
+        }
+        Corners(corners)
+    }
+
+    fn map_positions(&self, positions: &[[f64; 3]]) -> [[f64; 3]; 3] {
+        [
+            positions[self.0[0]],
+            positions[self.0[1]],
+            positions[self.0[2]],
+        ]
+    }
+}
+
+impl std::ops::Index<usize> for Corners {
+    type Output = usize;
+
+    fn index(&self, i: usize) -> &usize {
+        &self.0[i]
+    }
+}
+
+/// LOD 0 is the mesh itself, every further level halves the triangle count
+/// until `max_levels` or until the simplifier gets stuck.
+pub fn build_lod_chain(
+    positions: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+    indices: &[u16],
+    max_levels: usize,
+) -> Vec<LodIndices> {
+    let mut chain = vec![LodIndices {
+        indices: indices.to_vec(),
+        error: 0.0,
+    }];
+    let mut simplifier = Simplifier::new(positions, tex_coords, indices);
+    let mut triangles = simplifier.alive_count;
+    while chain.len() < max_levels {
+        let target = (triangles as f32 * LOD_REDUCTION) as usize;
+        simplifier.simplify(target);
+        if simplifier.alive_count as f32 > triangles as f32 * MIN_LOD_REDUCTION {
+            break;
+        }
+        triangles = simplifier.alive_count;
+        chain.push(simplifier.snapshot());
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_point_regions() {
+        let triangle = [[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]];
+        let cases = [
+            // face, both edges along the axes, the hypotenuse, the corners
+            ([0.5, 0.5, 3.0], [0.5, 0.5, 0.0]),
+            ([1.0, -1.0, 0.0], [1.0, 0.0, 0.0]),
+            ([-1.0, 1.0, 1.0], [0.0, 1.0, 0.0]),
+            ([2.0, 2.0, 0.0], [1.0, 1.0, 0.0]),
+            ([-1.0, -1.0, 0.0], [0.0, 0.0, 0.0]),
+            ([3.0, -1.0, 0.0], [2.0, 0.0, 0.0]),
+            ([-1.0, 3.0, 0.0], [0.0, 2.0, 0.0]),
+        ];
+        for (p, expected) in cases.iter() {
+            let closest = closest_point_on_triangle(*p, triangle);
+            let offset = sub(closest, *expected);
+            assert!(dot(offset, offset) < 1e-12, "{:?} -> {:?}", p, closest);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use futures::FutureExt;
+use std::{future::Future, pin::Pin};
+
+/// Buffer copies need offsets that are a multiple of this.
+const COPY_BUFFER_ALIGNMENT: wgpu::BufferAddress = 4;
+
+type PendingMap =
+    Pin<Box<dyn Future<Output = Result<wgpu::BufferWriteMapping, wgpu::BufferAsyncErr>>>>;
+
+/// Bytes of a chunk and how many of them this frame's writes have taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Space {
+    size: wgpu::BufferAddress,
+    offset: wgpu::BufferAddress,
+}
+
+impl Space {
+    fn new(size: wgpu::BufferAddress) -> Self {
+        Space { size, offset: 0 }
+    }
+
+    fn fits(&self, size: wgpu::BufferAddress) -> bool {
+        self.size - self.offset >= size
+    }
+
+    /// Takes `size` bytes and returns where they start, the next write starts
+    /// aligned for copying.
+    fn take(&mut self, size: wgpu::BufferAddress) -> wgpu::BufferAddress {
+        let start = self.offset;
+        let end = start + size;
+        let padding = (COPY_BUFFER_ALIGNMENT - end % COPY_BUFFER_ALIGNMENT) % COPY_BUFFER_ALIGNMENT;
+        self.offset = (end + padding).min(self.size);
+        start
+    }
+}
+
+impl AsRef<Space> for Space {
+    fn as_ref(&self) -> &Space {
+        self
+    }
+}
+
+impl AsMut<Space> for Space {
+    fn as_mut(&mut self) -> &mut Space {
+        self
+    }
+}
+
+/// Which chunks are written, copied from and free, kept apart from the
+/// buffers so it works without a device.
+struct Chunks<C> {
+    // mapped, written to this frame
+    active: Vec<C>,
+    // unmapped, copied from by the frame being submitted
+    closed: Vec<C>,
+    // mapped and empty
+    free: Vec<C>,
+}
+
+impl<C: AsRef<Space> + AsMut<Space>> Chunks<C> {
+    fn new() -> Self {
+        Chunks {
+            active: Vec::new(),
+            closed: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Finds room for `size` bytes in a chunk written this frame, or in a
+    /// free one that is written from now on. Returns the chunk's index in
+    /// `active` and the offset of the write, `None` when a new chunk is
+    /// needed.
+    fn place(&mut self, size: wgpu::BufferAddress) -> Option<(usize, wgpu::BufferAddress)> {
+        let index = match self.active.iter().position(|c| c.as_ref().fits(size)) {
+            Some(index) => index,
+            None => {
+                let free = self.free.iter().position(|c| c.as_ref().fits(size))?;
+                self.active.push(self.free.swap_remove(free));
+                self.active.len() - 1
+            }
+        };
+        Some((index, self.active[index].as_mut().take(size)))
+    }
+
+    /// Moves this frame's chunks to `closed`, `close` unmaps them.
+    fn finish(&mut self, mut close: impl FnMut(&mut C)) {
+        for mut chunk in self.active.drain(..) {
+            close(&mut chunk);
+            self.closed.push(chunk);
+        }
+    }
+
+    /// Empties a chunk the GPU is done with for reuse.
+    fn release(&mut self, mut chunk: C) {
+        chunk.as_mut().offset = 0;
+        self.free.push(chunk);
+    }
+}
+
+struct Chunk {
+    buffer: wgpu::Buffer,
+    space: Space,
+    // `None` once the chunk is unmapped for the GPU to copy from
+    mapping: Option<wgpu::BufferWriteMapping>,
+}
+
+impl AsRef<Space> for Chunk {
+    fn as_ref(&self) -> &Space {
+        &self.space
+    }
+}
+
+impl AsMut<Space> for Chunk {
+    fn as_mut(&mut self) -> &mut Space {
+        &mut self.space
+    }
+}
+
+/// Upload buffers reused across frames. Writes go into a mapped chunk and
+/// record a copy into the frame's encoder, so a frame only allocates when
+/// the chunks from earlier frames are still in flight or too small.
+pub struct StagingBelt {
+    chunk_size: wgpu::BufferAddress,
+    chunks: Chunks<Chunk>,
+    // waiting for the GPU to finish copying before they can be mapped again
+    pending: Vec<(Chunk, PendingMap)>,
+}
+
+impl StagingBelt {
+    /// Writes bigger than `chunk_size` get a chunk of their own.
+    pub fn new(chunk_size: wgpu::BufferAddress) -> Self {
+        StagingBelt {
+            chunk_size,
+            chunks: Chunks::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Records a copy of `data` to `target` at byte `offset`, which has to be
+    /// a multiple of 4.
+    pub fn write<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[T],
+    ) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        if bytes.is_empty() {
+            return;
+        }
+        let size = bytes.len() as wgpu::BufferAddress;
+
+        let (index, start) = match self.chunks.place(size) {
+            Some(placed) => placed,
+            None => {
+                // a fresh buffer comes mapped only until it's finished, so it
+                // holds just this write for now
+                let mut space = Space::new(size.max(self.chunk_size));
+                space.take(size);
+                let mapped = device.create_buffer_mapped(&wgpu::BufferDescriptor {
+                    label: Some("staging_chunk"),
+                    size: space.size,
+                    usage: wgpu::BufferUsage::MAP_WRITE | wgpu::BufferUsage::COPY_SRC,
+                });
+                mapped.data[..bytes.len()].copy_from_slice(bytes);
+                let buffer = mapped.finish();
+                encoder.copy_buffer_to_buffer(&buffer, 0, target, offset, size);
+                self.chunks.closed.push(Chunk {
+                    buffer,
+                    space,
+                    mapping: None,
+                });
+                return;
+            }
+        };
+
+        let chunk = &mut self.chunks.active[index];
+        if let Some(mapping) = &mut chunk.mapping {
+            mapping.as_slice()[start as usize..(start + size) as usize].copy_from_slice(bytes);
+        }
+        encoder.copy_buffer_to_buffer(&chunk.buffer, start, target, offset, size);
+    }
+
+    /// Unmaps the chunks written this frame. Has to happen before the
+    /// encoder holding the copies is submitted.
+    pub fn finish(&mut self) {
+        self.chunks.finish(|chunk| chunk.mapping = None);
+    }
+
+    /// Maps the chunks of submitted frames again, and takes back the ones
+    /// the GPU is done with. Call after submitting.
+    pub fn recall(&mut self, device: &wgpu::Device) {
+        for chunk in self.chunks.closed.drain(..) {
+            let mapping = Box::pin(chunk.buffer.map_write(0, chunk.space.size));
+            self.pending.push((chunk, mapping));
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.pending[i].1.as_mut().now_or_never() {
+                Some(result) => {
+                    let (mut chunk, _) = self.pending.swap_remove(i);
+                    // a chunk that failed to map is dropped
+                    if let Ok(mapping) = result {
+                        chunk.mapping = Some(mapping);
+                        self.chunks.release(chunk);
+                    }
+                }
+                None => i += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_share_a_chunk_at_aligned_offsets() {
+        let mut chunks = Chunks::new();
+        assert_eq!(chunks.place(8), None);
+        chunks.free.push(Space::new(64));
+        assert_eq!(chunks.place(6), Some((0, 0)));
+        assert_eq!(chunks.place(8), Some((0, 8)));
+        assert_eq!(chunks.active[0].offset, 16);
+        assert!(chunks.free.is_empty());
+    }
+
+    #[test]
+    fn chunks_are_reused_after_release() {
+        let mut chunks = Chunks::new();
+        chunks.free.push(Space::new(64));
+        chunks.place(40);
+
+        let mut closed = 0;
+        chunks.finish(|_| closed += 1);
+        assert_eq!(closed, 1);
+        assert!(chunks.active.is_empty());
+        // still copied from, so the next frame needs another chunk
+        assert_eq!(chunks.place(40), None);
+
+        for chunk in std::mem::take(&mut chunks.closed) {
+            chunks.release(chunk);
+        }
+        assert_eq!(chunks.place(40), Some((0, 0)));
+        assert_eq!(chunks.place(40), None);
+    }
+
+    #[test]
+    fn oversized_writes_need_their_own_chunk() {
+        let mut chunks = Chunks::new();
+        chunks.free.push(Space::new(64));
+        assert_eq!(chunks.place(100), None);
+        // the small chunk is left for writes that fit
+        assert_eq!(chunks.free.len(), 1);
+        chunks.free.push(Space::new(100));
+        assert_eq!(chunks.place(100), Some((0, 0)));
+        assert_eq!(chunks.active[0].offset, 100);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+/// Indexed triangle list fed to MikkTSpace, tangents are written per corner.
+struct MikkGeometry<'a> {
+    positions: &'a [[f32; 3]],
+    normals: &'a [[f32; 3]],
+    tex_coords: &'a [[f32; 2]],
+    indices: &'a [u32],
+    corner_tangents: Vec<[f32; 4]>,
+}
+
+impl MikkGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.indices[face * 3 + vert] as usize
+    }
+}
+
+impl mikktspace::Geometry for MikkGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.positions[self.vertex(face, vert)]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        // back to a bottom left origin, so the bitangent follows +Y of the
+        // normal map like the tangents baked by DCC tools do
+        let [u, v] = self.tex_coords[self.vertex(face, vert)];
+        [u, 1.0 - v]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corner_tangents[face * 3 + vert] = tangent;
+    }
+}
+
+/// Tangents of a triangle list whose vertices were split where the faces
+/// around them disagree, e.g. at mirrored uv seams.
+pub struct Tangents {
+    pub tangents: Vec<[f32; 4]>,
+    /// Source vertex of every vertex. The source vertices come first in their
+    /// own order, the split off copies follow.
+    pub vertices: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl Tangents {
+    /// Copies a vertex attribute to the split vertices.
+    pub fn remap<T: Copy>(&self, attribute: &[T]) -> Vec<T> {
+        self.vertices
+            .iter()
+            .map(|&source| attribute[source as usize])
+            .collect()
+    }
+}
+
+/// MikkTSpace tangents with the handedness in `w`, for an indexed triangle
+/// list. MikkTSpace works per corner, corners of a vertex that end up with
+/// different tangents get a vertex each. Degenerate inputs fall back to +X.
+pub fn generate_tangents(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+    indices: &[u32],
+) -> Tangents {
+    const FALLBACK: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    let mut geometry = MikkGeometry {
+        positions,
+        normals,
+        tex_coords,
+        indices,
+        corner_tangents: vec![FALLBACK; indices.len() / 3 * 3],
+    };
+    let mut tangents = Tangents {
+        tangents: vec![FALLBACK; positions.len()],
+        vertices: (0..positions.len() as u32).collect(),
+        indices: indices.to_vec(),
+    };
+    if !mikktspace::generate_tangents(&mut geometry) {
+        return tangents;
+    }
+
+    // the first corner claims the source vertex, corners agreeing with an
+    // earlier one share its vertex
+    let bits = |t: [f32; 4]| {
+        [
+            t[0].to_bits(),
+            t[1].to_bits(),
+            t[2].to_bits(),
+            t[3].to_bits(),
+        ]
+    };
+    let mut claimed = vec![false; positions.len()];
+    let mut splits: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+    for (corner, &tangent) in geometry.corner_tangents.iter().enumerate() {
+        let source = indices[corner];
+        let vertex = if !claimed[source as usize] {
+            claimed[source as usize] = true;
+            tangents.tangents[source as usize] = tangent;
+            source
+        } else if tangents.tangents[source as usize] == tangent {
+            source
+        } else {
+            *splits.entry((source, bits(tangent))).or_insert_with(|| {
+                tangents.tangents.push(tangent);
+                tangents.vertices.push(source);
+                tangents.vertices.len() as u32 - 1
+            })
+        };
+        tangents.indices[corner] = vertex;
+    }
+    tangents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles sharing the edge from (0, 0) to (0, 1), with the uvs of
+    /// the right one either continuing or mirroring the left one.
+    fn seam(mirrored: bool) -> Tangents {
+        let positions = [
+            [-1.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+        ];
+        let normals = [[0.0, 0.0, 1.0]; 4];
+        let right_u = if mirrored { 0.0 } else { 2.0 };
+        let tex_coords = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [right_u, 1.0]];
+        generate_tangents(&positions, &normals, &tex_coords, &[0, 1, 2, 1, 3, 2])
+    }
+
+    #[test]
+    fn continuous_uvs_share_vertices() {
+        let tangents = seam(false);
+        assert_eq!(tangents.vertices, vec![0, 1, 2, 3]);
+        assert_eq!(tangents.indices, vec![0, 1, 2, 1, 3, 2]);
+        assert!(tangents.tangents.iter().all(|t| t[0] > 0.99));
+    }
+
+    #[test]
+    fn mirrored_uvs_split_the_seam() {
+        let tangents = seam(true);
+        assert_eq!(tangents.vertices, vec![0, 1, 2, 3, 1, 2]);
+        assert_eq!(tangents.indices, vec![0, 1, 2, 4, 3, 5]);
+        for (corner, &vertex) in tangents.indices.iter().enumerate() {
+            let x = tangents.tangents[vertex as usize][0];
+            if corner < 3 {
+                assert!(x > 0.99, "left corner {} has tangent x {}", corner, x);
+            } else {
+                assert!(x < -0.99, "right corner {} has tangent x {}", corner, x);
+            }
+        }
+        assert_eq!(
+            tangents.remap(&['a', 'b', 'c', 'd']),
+            vec!['a', 'b', 'c', 'd', 'b', 'c']
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::geometry::MeshData;
+use anyhow::{ensure, Context, Result};
+use cgmath::InnerSpace;
+use std::path::Path;
+
+/// Heights in `0..=1` from a grayscale image, row by row.
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read heightmap {}", path.display()))?;
+        let img = image::load_from_memory(&bytes)
+            .with_context(|| format!("Failed to decode heightmap {}", path.display()))?;
+        Ok(Self::from_image(&img))
+    }
+
+    pub fn from_image(img: &image::DynamicImage) -> Self {
+        let luma = img.to_luma();
+        Heightmap {
+            width: luma.width(),
+            height: luma.height(),
+            heights: luma.pixels().map(|p| p[0] as f32 / 255.0).collect(),
+        }
+    }
+
+    /// Clamps to the edges, so border normals fall back to one-sided
+    /// differences.
+    fn sample(&self, x: i64, z: i64) -> f32 {
+        let x = x.max(0).min(self.width as i64 - 1) as usize;
+        let z = z.max(0).min(self.height as i64 - 1) as usize;
+        self.heights[z * self.width as usize + x]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainConfig {
+    /// World size along the longer side of the heightmap.
+    pub size: f32,
+    /// World height of a white pixel.
+    pub height_scale: f32,
+    /// Cells per chunk side, at most `MAX_CHUNK_CELLS` for 16-bit indices.
+    pub chunk_cells: u32,
+    /// How far the skirts hang below the chunk edges.
+    pub skirt_depth: f32,
+    /// World size of one repeat of the material.
+    pub tile_size: f32,
+}
+
+impl TerrainConfig {
+    // (n + 1)^2 grid vertices plus 4 (n + 1) skirt vertices
+    pub const MAX_CHUNK_CELLS: u32 = 253;
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            size: 20.0,
+            height_scale: 3.0,
+            chunk_cells: 64,
+            skirt_depth: 0.5,
+            tile_size: 2.0,
+        }
+    }
+}
+
+/// Splits the heightmap into square chunks of world-space meshes centered on
+/// the origin, each with a skirt around its edges so cracks between chunks at
+/// different levels of detail stay hidden.
+pub fn build_chunks(heightmap: &Heightmap, config: &TerrainConfig) -> Result<Vec<MeshData>> {
+    ensure!(
+        heightmap.width >= 2 && heightmap.height >= 2,
+        "Heightmap must be at least 2x2 pixels, got {}x{}",
+        heightmap.width,
+        heightmap.height
+    );
+    ensure!(
+        (1..=TerrainConfig::MAX_CHUNK_CELLS).contains(&config.chunk_cells),
+        "Terrain chunks must have 1 to {} cells per side, got {}",
+        TerrainConfig::MAX_CHUNK_CELLS,
+        config.chunk_cells
+    );
+
+    let cells = [heightmap.width - 1, heightmap.height - 1];
+    let spacing = config.size / cells[0].max(cells[1]) as f32;
+    let position = |x: u32, z: u32| {
+        [
+            (x as f32 - cells[0] as f32 / 2.0) * spacing,
+            heightmap.sample(x as i64, z as i64) * config.height_scale,
+            (z as f32 - cells[1] as f32 / 2.0) * spacing,
+        ]
+    };
+    let normal = |x: u32, z: u32| {
+        let (x, z) = (x as i64, z as i64);
+        let height = |x, z| heightmap.sample(x, z) * config.height_scale;
+        let span = |a: i64, max: u32| ((a + 1).min(max as i64) - (a - 1).max(0)) as f32 * spacing;
+        let dx = (height(x + 1, z) - height(x - 1, z)) / span(x, cells[0]);
+        let dz = (height(x, z + 1) - height(x, z - 1)) / span(z, cells[1]);
+        cgmath::Vector3::new(-dx, 1.0, -dz).normalize().into()
+    };
+
+    let mut chunks = Vec::new();
+    for z0 in (0..cells[1]).step_by(config.chunk_cells as usize) {
+        for x0 in (0..cells[0]).step_by(config.chunk_cells as usize) {
+            let x1 = (x0 + config.chunk_cells).min(cells[0]);
+            let z1 = (z0 + config.chunk_cells).min(cells[1]);
+            let mut mesh = MeshData::default();
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    let p = position(x, z);
+                    mesh.positions.push(p);
+                    mesh.normals.push(normal(x, z));
+                    mesh.tex_coords
+                        .push([p[0] / config.tile_size, p[2] / config.tile_size]);
+                }
+            }
+
+            let columns = (x1 - x0) as u16;
+            let rows = (z1 - z0) as u16;
+            let index = |column: u16, row: u16| row * (columns + 1) + column;
+            for row in 0..rows {
+                for column in 0..columns {
+                    let top_left = index(column, row);
+                    let bottom_left = index(column, row + 1);
+                    mesh.indices.extend_from_slice(&[
+                        top_left,
+                        bottom_left,
+                        bottom_left + 1,
+                        top_left,
+                        bottom_left + 1,
+                        top_left + 1,
+                    ]);
+                }
+            }
+
+            let edges: [(Vec<u16>, [f32; 2]); 4] = [
+                ((0..=columns).map(|c| index(c, 0)).collect(), [0.0, -1.0]),
+                ((0..=columns).map(|c| index(c, rows)).collect(), [0.0, 1.0]),
+                ((0..=rows).map(|r| index(0, r)).collect(), [-1.0, 0.0]),
+                ((0..=rows).map(|r| index(columns, r)).collect(), [1.0, 0.0]),
+            ];
+            for (edge, outward) in edges.iter() {
+                push_skirt(&mut mesh, edge, *outward, config.skirt_depth);
+            }
+            chunks.push(mesh);
+        }
+    }
+    Ok(chunks)
+}
+
+/// Hangs a vertical strip below the `edge` vertices, wound to face `outward`
+/// in the XZ plane.
+fn push_skirt(mesh: &mut MeshData, edge: &[u16], outward: [f32; 2], depth: f32) {
+    let base = mesh.positions.len() as u16;
+    for &i in edge {
+        let [x, y, z] = mesh.positions[i as usize];
+        mesh.positions.push([x, y - depth, z]);
+        mesh.normals.push(mesh.normals[i as usize]);
+        mesh.tex_coords.push(mesh.tex_coords[i as usize]);
+    }
+    // (top, bottom, next top) faces along down × edge = (-edge.z, 0, edge.x)
+    let first = mesh.positions[edge[0] as usize];
+    let last = mesh.positions[edge[edge.len() - 1] as usize];
+    let along = [last[0] - first[0], last[2] - first[2]];
+    let flip = -along[1] * outward[0] + along[0] * outward[1] < 0.0;
+    for k in 0..edge.len() as u16 - 1 {
+        let (top, next_top) = (edge[k as usize], edge[k as usize + 1]);
+        let (bottom, next_bottom) = (base + k, base + k + 1);
+        if flip {
+            mesh.indices
+                .extend_from_slice(&[top, next_top, bottom, next_top, next_bottom, bottom]);
+        } else {
+            mesh.indices
+                .extend_from_slice(&[top, bottom, next_top, next_top, bottom, next_bottom]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    /// Uneven heights, so seams and normals depend on the right samples.
+    fn heightmap(width: u32, height: u32) -> Heightmap {
+        let heights = (0..height)
+            .flat_map(|z| (0..width).map(move |x| ((x * 7 + z * 3) % 11) as f32 / 10.0))
+            .collect();
+        Heightmap {
+            width,
+            height,
+            heights,
+        }
+    }
+
+    fn config(chunk_cells: u32) -> TerrainConfig {
+        TerrainConfig {
+            chunk_cells,
+            ..TerrainConfig::default()
+        }
+    }
+
+    fn grid_vertices(columns: usize, rows: usize) -> usize {
+        (columns + 1) * (rows + 1)
+    }
+
+    #[test]
+    fn chunks_cover_the_heightmap() {
+        // 9 x 6 cells in chunks of 4: three columns of 4, 4 and 1 cells, two
+        // rows of 4 and 2
+        let chunks = build_chunks(&heightmap(10, 7), &config(4)).unwrap();
+        assert_eq!(chunks.len(), 6);
+        let sizes = [(4, 4), (4, 4), (1, 4), (4, 2), (4, 2), (1, 2)];
+        for (chunk, &(columns, rows)) in chunks.iter().zip(&sizes) {
+            let skirt = 2 * (columns + 1) + 2 * (rows + 1);
+            assert_eq!(chunk.positions.len(), grid_vertices(columns, rows) + skirt);
+            assert_eq!(chunk.normals.len(), chunk.positions.len());
+            assert_eq!(chunk.tex_coords.len(), chunk.positions.len());
+            assert_eq!(chunk.indices.len(), (columns * rows + skirt - 4) * 6);
+        }
+        let extent = |axis: usize| {
+            chunks
+                .iter()
+                .flat_map(|chunk| chunk.positions.iter().map(move |p| p[axis]))
+                .fold((f32::MAX, f32::MIN), |(min, max), v| {
+                    (min.min(v), max.max(v))
+                })
+        };
+        // the longer side spans `size`, centered on the origin
+        assert_eq!(extent(0), (-10.0, 10.0));
+        let half_depth = 3.0 * 20.0 / 9.0;
+        assert!((extent(2).0 + half_depth).abs() < 1e-5);
+        assert!((extent(2).1 - half_depth).abs() < 1e-5);
+    }
+
+    /// Grid vertices of a chunk, without its skirts, with their normals.
+    fn grid(chunk: &MeshData, columns: usize, rows: usize) -> Vec<([f32; 3], [f32; 3])> {
+        let count = grid_vertices(columns, rows);
+        chunk.positions[..count]
+            .iter()
+            .copied()
+            .zip(chunk.normals[..count].iter().copied())
+            .collect()
+    }
+
+    fn on_line(
+        vertices: &[([f32; 3], [f32; 3])],
+        axis: usize,
+        at: f32,
+    ) -> Vec<([f32; 3], [f32; 3])> {
+        vertices
+            .iter()
+            .filter(|(p, _)| p[axis] == at)
+            .copied()
+            .collect()
+    }
+
+    fn face_normal(chunk: &MeshData, triangle: &[u16]) -> Vector3<f32> {
+        let [a, b, c] = [
+            Vector3::from(chunk.positions[triangle[0] as usize]),
+            Vector3::from(chunk.positions[triangle[1] as usize]),
+            Vector3::from(chunk.positions[triangle[2] as usize]),
+        ];
+        (b - a).cross(c - a)
+    }
+
+    #[test]
+    fn neighbouring_chunks_share_their_edge() {
+        let chunks = build_chunks(&heightmap(10, 7), &config(4)).unwrap();
+        let first = grid(&chunks[0], 4, 4);
+        let right = grid(&chunks[1], 4, 4);
+        let below = grid(&chunks[3], 4, 2);
+
+        // the last column of the first chunk is the first of the next one,
+        // with the same heights and normals
+        let x_seam = first[4].0[0];
+        assert_eq!(x_seam, right[0].0[0]);
+        let shared = on_line(&first, 0, x_seam);
+        assert_eq!(shared.len(), 5);
+        assert_eq!(shared, on_line(&right, 0, x_seam));
+
+        let z_seam = first[first.len() - 1].0[2];
+        assert_eq!(z_seam, below[0].0[2]);
+        let shared = on_line(&first, 2, z_seam);
+        assert_eq!(shared.len(), 5);
+        assert_eq!(shared, on_line(&below, 2, z_seam));
+    }
+
+    #[test]
+    fn skirts_hang_down_and_face_outward() {
+        let config = config(4);
+        let chunks = build_chunks(&heightmap(10, 7), &config).unwrap();
+        let sizes = [(4, 4), (4, 4), (1, 4), (4, 2), (4, 2), (1, 2)];
+        for (chunk, &(columns, rows)) in chunks.iter().zip(&sizes) {
+            let top = grid_vertices(columns, rows);
+            let edges = [columns + 1, columns + 1, rows + 1, rows + 1];
+            let mut skirt_vertex = top;
+            for &edge in edges.iter() {
+                for _ in 0..edge {
+                    let bottom = chunk.positions[skirt_vertex];
+                    // a copy of some grid vertex, moved down by the depth
+                    assert!(chunk.positions[..top].iter().any(|p| {
+                        p[0] == bottom[0]
+                            && p[2] == bottom[2]
+                            && p[1] - config.skirt_depth == bottom[1]
+                    }));
+                    skirt_vertex += 1;
+                }
+            }
+            assert_eq!(skirt_vertex, chunk.positions.len());
+
+            let center = grid(chunk, columns, rows)
+                .iter()
+                .fold(Vector3::new(0.0, 0.0, 0.0), |sum, (p, _)| {
+                    sum + Vector3::from(*p)
+                })
+                / top as f32;
+            let (surface, skirts) = chunk.indices.split_at(columns * rows * 6);
+            for triangle in surface.chunks_exact(3) {
+                assert!(face_normal(chunk, triangle).y > 0.0);
+            }
+            assert_eq!(skirts.len(), (edges.iter().sum::<usize>() - 4) * 6);
+            for triangle in skirts.chunks_exact(3) {
+                let normal = face_normal(chunk, triangle);
+                let corner = Vector3::from(chunk.positions[triangle[0] as usize]);
+                let outward = Vector3::new(corner.x - center.x, 0.0, corner.z - center.z);
+                assert!(normal.y.abs() < 1e-5, "skirt normal {:?}", normal);
+                assert!(
+                    normal.dot(outward) > 0.0,
+                    "skirt at {:?} faces {:?}",
+                    corner,
+                    normal
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn the_largest_chunk_fits_16_bit_indices() {
+        let n = TerrainConfig::MAX_CHUNK_CELLS as usize;
+        let vertices = |cells: usize| (cells + 1) * (cells + 1) + 4 * (cells + 1);
+        assert!(vertices(n) <= u16::MAX as usize + 1);
+        assert!(vertices(n + 1) > u16::MAX as usize + 1);
+
+        let size = TerrainConfig::MAX_CHUNK_CELLS + 1;
+        let chunks = build_chunks(
+            &heightmap(size, size),
+            &config(TerrainConfig::MAX_CHUNK_CELLS),
+        )
+        .unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].positions.len(), vertices(n));
+        let max_index = *chunks[0].indices.iter().max().unwrap() as usize;
+        assert_eq!(max_index, chunks[0].positions.len() - 1);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let heightmap = heightmap(10, 7);
+        assert!(build_chunks(&heightmap, &config(0)).is_err());
+        assert!(build_chunks(&heightmap, &config(TerrainConfig::MAX_CHUNK_CELLS + 1)).is_err());
+        assert!(build_chunks(&self::heightmap(1, 7), &config(4)).is_err());
+    }
+}
@@ -8,49 +8,7 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-
-    pub fn create_depth_texture(
-        device: &wgpu::Device,
-        sc_desc: &wgpu::SwapChainDescriptor,
-        label: &str,
-    ) -> Self {
-        let size = wgpu::Extent3d {
-            width: sc_desc.width,
-            height: sc_desc.height,
-            depth: 1,
-        };
-        let desc = wgpu::TextureDescriptor {
-            label: Some(label),
-            size,
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        };
-        let texture = device.create_texture(&desc);
-        let view = texture.create_default_view();
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: wgpu::CompareFunction::LessEqual,
-        });
-        Self {
-            texture,
-            view,
-            sampler,
-        }
-    }
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
 
     pub fn from_bytes(
         device: &wgpu::Device,
@@ -61,6 +19,20 @@ impl Texture {
         Self::from_image(device, &img, is_normal)
     }
 
+    /// A 1x1 texture, for material inputs that have no map.
+    pub fn from_color(
+        device: &wgpu::Device,
+        color: [u8; 4],
+        is_normal: bool,
+    ) -> Result<(Self, wgpu::CommandBuffer)> {
+        let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(
+            1,
+            1,
+            image::Rgba(color),
+        ));
+        Self::from_image(device, &img, is_normal)
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         img: &image::DynamicImage,
@@ -117,17 +89,7 @@ impl Texture {
         let cmd_buffer = encoder.finish();
 
         let view = texture.create_default_view();
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: wgpu::CompareFunction::Always,
-        });
+        let sampler = Self::create_sampler(device, wgpu::AddressMode::ClampToEdge);
 
         Ok((
             Self {
@@ -138,4 +100,23 @@ impl Texture {
             cmd_buffer,
         ))
     }
+
+    /// Swaps the sampler, e.g. to `Repeat` for materials that tile.
+    pub fn set_address_mode(&mut self, device: &wgpu::Device, address_mode: wgpu::AddressMode) {
+        self.sampler = Self::create_sampler(device, address_mode);
+    }
+
+    fn create_sampler(device: &wgpu::Device, address_mode: wgpu::AddressMode) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare: wgpu::CompareFunction::Always,
+        })
+    }
 }
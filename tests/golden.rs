@@ -0,0 +1,33 @@
+//! Renders the golden scenes, including the glTF assets in `tests/assets`,
+//! and compares them to the references in `tests/golden`. The references
+//! come from a software adapter, record them with
+//! `cargo run -- --golden tests/golden --update tests/assets/*.gltf`.
+
+use std::process::Command;
+
+/// Exit code of `--golden` when there is no adapter to render with.
+const EXIT_NO_ADAPTER: i32 = 77;
+
+const ASSETS: [&str; 3] = [
+    "tests/assets/textured.gltf",
+    "tests/assets/skinned.gltf",
+    "tests/assets/instanced.gltf",
+];
+
+#[test]
+fn golden_images() {
+    let status = Command::new(env!("CARGO_BIN_EXE_skinning"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--golden", "tests/golden"])
+        .args(ASSETS)
+        .status()
+        .expect("Failed to run skinning");
+    if status.code() == Some(EXIT_NO_ADAPTER) {
+        eprintln!("No adapter, skipping the golden images");
+        return;
+    }
+    assert!(
+        status.success(),
+        "Golden images differ, see tests/golden/failures"
+    );
+}